@@ -0,0 +1,4 @@
+-- Add migration script here
+CREATE INDEX recordings_uploaded_at ON recordings(uploaded_at, id);
+CREATE INDEX recordings_status ON recordings(analysis_status, uploaded_at, id);
+CREATE INDEX channels_recording ON channels(recording);
@@ -0,0 +1,61 @@
+-- Add migration script here
+CREATE TABLE analysis_runs (
+    id UUID PRIMARY KEY NOT NULL,
+    recording_id UUID NOT NULL REFERENCES recordings(id) ON DELETE CASCADE,
+    pipeline_version VARCHAR(255),
+    force_diarize BOOLEAN,
+    transcript_s3_path VARCHAR(255),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    started_at TIMESTAMPTZ,
+    finished_at TIMESTAMPTZ,
+    status VARCHAR(255) NOT NULL DEFAULT 'pending',
+    percent INTEGER NOT NULL DEFAULT 0,
+    description VARCHAR(255),
+    channel INTEGER,
+    error TEXT,
+    last_update TIMESTAMPTZ
+);
+
+CREATE INDEX analysis_runs_recording ON analysis_runs(recording_id, created_at);
+
+-- every existing recording gets one run carrying its current analysis state
+INSERT INTO analysis_runs (id, recording_id, force_diarize, transcript_s3_path, created_at, started_at, finished_at, status, percent, description, channel, error, last_update)
+SELECT
+    gen_random_uuid(),
+    id,
+    force_diarize,
+    original_transcript_s3_path,
+    uploaded_at,
+    analysis_started,
+    CASE WHEN analysis_status IN ('done', 'error', 'cancelled') THEN analysis_last_update END,
+    analysis_status,
+    analysis_percent,
+    analysis_description,
+    analysis_channel,
+    analysis_error,
+    analysis_last_update
+FROM recordings;
+
+ALTER TABLE recordings ADD COLUMN latest_run UUID REFERENCES analysis_runs(id) ON DELETE SET NULL;
+UPDATE recordings SET latest_run = analysis_runs.id
+FROM analysis_runs WHERE analysis_runs.recording_id = recordings.id;
+
+ALTER TABLE channels ADD COLUMN run_id UUID REFERENCES analysis_runs(id) ON DELETE CASCADE;
+UPDATE channels SET run_id = analysis_runs.id
+FROM analysis_runs WHERE analysis_runs.recording_id = channels.recording;
+ALTER TABLE channels ALTER COLUMN run_id SET NOT NULL;
+CREATE INDEX channels_run ON channels(run_id);
+
+ALTER TABLE recording_stats ADD COLUMN run_id UUID REFERENCES analysis_runs(id) ON DELETE CASCADE;
+UPDATE recording_stats SET run_id = analysis_runs.id
+FROM analysis_runs WHERE analysis_runs.recording_id = recording_stats.recording_id;
+ALTER TABLE recording_stats DROP CONSTRAINT recording_stats_pkey;
+ALTER TABLE recording_stats ADD PRIMARY KEY (run_id);
+
+ALTER TABLE recordings DROP COLUMN analysis_status;
+ALTER TABLE recordings DROP COLUMN analysis_percent;
+ALTER TABLE recordings DROP COLUMN analysis_error;
+ALTER TABLE recordings DROP COLUMN analysis_started;
+ALTER TABLE recordings DROP COLUMN analysis_last_update;
+ALTER TABLE recordings DROP COLUMN analysis_description;
+ALTER TABLE recordings DROP COLUMN analysis_channel;
@@ -0,0 +1,9 @@
+-- Add migration script here
+-- redelivered results used to create duplicate channels; keep one per index
+DELETE FROM channels a USING channels b
+WHERE a.run_id = b.run_id
+AND a.idx_in_file = b.idx_in_file
+AND a.ctid < b.ctid;
+
+ALTER TABLE channels ADD CONSTRAINT channels_run_idx UNIQUE (run_id, idx_in_file);
+DROP INDEX channels_run;
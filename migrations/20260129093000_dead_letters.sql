@@ -0,0 +1,15 @@
+-- Add migration script here
+CREATE TABLE dead_letters (
+    id UUID PRIMARY KEY NOT NULL,
+    topic VARCHAR(255) NOT NULL,
+    kafka_partition INTEGER NOT NULL,
+    kafka_offset BIGINT NOT NULL,
+    payload BYTEA NOT NULL,
+    error TEXT NOT NULL,
+    attempts INTEGER NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    replayed_at TIMESTAMPTZ,
+    replay_error TEXT
+);
+
+CREATE INDEX dead_letters_created ON dead_letters(created_at);
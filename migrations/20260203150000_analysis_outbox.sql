@@ -0,0 +1,16 @@
+-- Add migration script here
+-- messages for the analysis workers, written in the same transaction as the state change
+-- that causes them and published by a background relay
+CREATE TABLE analysis_outbox (
+    id UUID PRIMARY KEY NOT NULL,
+    -- no foreign key: a cancellation must still go out after its run was deleted
+    run_id UUID NOT NULL,
+    kind VARCHAR(255) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_error TEXT,
+    published_at TIMESTAMPTZ
+);
+
+CREATE INDEX analysis_outbox_pending ON analysis_outbox(next_attempt_at) WHERE published_at IS NULL;
@@ -0,0 +1,12 @@
+-- Add migration script here
+-- blobs of deleted recordings, removed from storage by a background worker so a storage
+-- outage never blocks or undoes a deletion
+CREATE TABLE blob_deletions (
+    key VARCHAR(255) PRIMARY KEY NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_error TEXT
+);
+
+CREATE INDEX blob_deletions_next_attempt ON blob_deletions(next_attempt_at);
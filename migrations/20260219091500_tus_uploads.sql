@@ -0,0 +1,23 @@
+-- Add migration script here
+-- resumable uploads in progress; the id becomes the id of the recording once complete
+CREATE TABLE tus_uploads (
+    id UUID PRIMARY KEY NOT NULL,
+    storage_key VARCHAR(255) NOT NULL,
+    storage_upload_id TEXT NOT NULL,
+    upload_length BIGINT NOT NULL,
+    upload_offset BIGINT NOT NULL DEFAULT 0,
+    -- raw Upload-Metadata header, echoed back to clients
+    metadata TEXT,
+    filename VARCHAR(255) NOT NULL,
+    force_diarize BOOLEAN,
+    -- parts already in storage, as [{"part_number": 1, "etag": "..."}]
+    parts JSONB NOT NULL DEFAULT '[]',
+    -- received bytes that are not yet enough for a part of their own
+    pending BYTEA NOT NULL DEFAULT '',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    assembled_at TIMESTAMPTZ,
+    completed_at TIMESTAMPTZ
+);
+
+CREATE INDEX tus_uploads_expires ON tus_uploads(expires_at);
@@ -0,0 +1,19 @@
+-- Add migration script here
+-- uploads that clients send straight to storage through presigned URLs; the id becomes
+-- the id of the recording once the upload is completed
+CREATE TABLE direct_uploads (
+    id UUID PRIMARY KEY NOT NULL,
+    storage_key VARCHAR(255) NOT NULL,
+    -- set for multipart uploads
+    storage_upload_id TEXT,
+    filename VARCHAR(255) NOT NULL,
+    force_diarize BOOLEAN,
+    expected_size BIGINT NOT NULL,
+    -- lowercase hex
+    expected_sha256 VARCHAR(64),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    completed_at TIMESTAMPTZ
+);
+
+CREATE INDEX direct_uploads_expires ON direct_uploads(expires_at);
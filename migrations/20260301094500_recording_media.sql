@@ -0,0 +1,8 @@
+-- Add migration script here
+-- probed from the upload; NULL for recordings uploaded before probing was added
+ALTER TABLE recordings ADD COLUMN media_container VARCHAR(32);
+ALTER TABLE recordings ADD COLUMN media_codec VARCHAR(32);
+ALTER TABLE recordings ADD COLUMN media_sample_rate INTEGER;
+ALTER TABLE recordings ADD COLUMN media_channels INTEGER;
+ALTER TABLE recordings ADD COLUMN media_duration_sec DOUBLE PRECISION;
+ALTER TABLE recordings ADD COLUMN media_bits_per_sample INTEGER;
@@ -0,0 +1,7 @@
+-- Add migration script here
+-- lowercase hex SHA-256 of the audio; NULL for recordings uploaded before it was recorded
+ALTER TABLE recordings ADD COLUMN content_sha256 VARCHAR(64);
+-- value of the Idempotency-Key header the recording was uploaded with
+ALTER TABLE recordings ADD COLUMN idempotency_key VARCHAR(255) UNIQUE;
+
+CREATE INDEX recordings_content_sha256 ON recordings(content_sha256);
@@ -0,0 +1,4 @@
+-- Add migration script here
+-- segments are paged by (start_sec, id), so the id breaks ties within the index
+DROP INDEX segment_start;
+CREATE INDEX segment_start ON segments(channel, start_sec, id);
@@ -0,0 +1,22 @@
+-- Add migration script here
+-- a single row holding the text search configuration segments are indexed with
+CREATE TABLE search_settings (
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    text_search_config REGCONFIG NOT NULL
+);
+INSERT INTO search_settings (text_search_config) VALUES ('russian');
+
+ALTER TABLE segments ADD COLUMN content_tsv TSVECTOR;
+
+CREATE FUNCTION segments_content_tsv() RETURNS TRIGGER AS $$
+BEGIN
+    NEW.content_tsv := to_tsvector((SELECT text_search_config FROM search_settings), NEW.content);
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER segments_content_tsv BEFORE INSERT OR UPDATE OF content ON segments
+    FOR EACH ROW EXECUTE FUNCTION segments_content_tsv();
+
+UPDATE segments SET content_tsv = to_tsvector('russian', content);
+CREATE INDEX segments_content_tsv ON segments USING GIN (content_tsv);
@@ -0,0 +1,3 @@
+-- Add migration script here
+-- serves the `metrics_list @@ jsonpath` predicates metric filters compile to
+CREATE INDEX segments_metrics ON segments USING GIN (metrics_list jsonb_path_ops);
@@ -0,0 +1,37 @@
+-- Add migration script here
+-- every metric ingested so far, per provider, name, level and value type
+CREATE TABLE metric_catalog (
+    provider TEXT NOT NULL,
+    name TEXT NOT NULL,
+    level VARCHAR(16) NOT NULL CHECK (level IN ('recording', 'channel', 'segment')),
+    value_type VARCHAR(8) NOT NULL CHECK (value_type IN ('int', 'float', 'str', 'bool')),
+    unit TEXT,
+    description TEXT,
+    provider_description TEXT,
+    value_count BIGINT NOT NULL,
+    null_count BIGINT NOT NULL,
+    min_value DOUBLE PRECISION,
+    max_value DOUBLE PRECISION,
+    first_seen_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_seen_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (provider, name, level, value_type)
+);
+
+INSERT INTO metric_catalog (
+    provider, name, level, value_type, unit, description, provider_description,
+    value_count, null_count, min_value, max_value
+)
+SELECT collection->>'provider', metric->>'name', level, metric->>'type',
+    max(metric->>'unit'), max(metric->>'description'), max(collection->>'description'),
+    count(*) FILTER (WHERE COALESCE(jsonb_typeof(metric->'value'), 'null') <> 'null'),
+    count(*) FILTER (WHERE COALESCE(jsonb_typeof(metric->'value'), 'null') = 'null'),
+    min((metric->>'value')::DOUBLE PRECISION) FILTER (WHERE jsonb_typeof(metric->'value') = 'number'),
+    max((metric->>'value')::DOUBLE PRECISION) FILTER (WHERE jsonb_typeof(metric->'value') = 'number')
+FROM (
+    SELECT 'recording' AS level, metrics_list FROM recording_stats
+    UNION ALL SELECT 'channel', metrics_list FROM channels
+    UNION ALL SELECT 'segment', metrics_list FROM segments
+) AS lists,
+    jsonb_array_elements(lists.metrics_list) AS collection,
+    jsonb_array_elements(collection->'metrics') AS metric
+GROUP BY 1, 2, 3, 4;
@@ -0,0 +1,30 @@
+-- Add migration script here
+-- the recordings listing filters and sorts by the status of the latest run; keeping a copy
+-- on the recording lets an index serve that, as recordings_status did before runs existed
+ALTER TABLE recordings ADD COLUMN latest_status VARCHAR(255) NOT NULL DEFAULT 'pending';
+UPDATE recordings SET latest_status = analysis_runs.status
+FROM analysis_runs WHERE analysis_runs.id = recordings.latest_run;
+CREATE INDEX recordings_latest_status ON recordings(latest_status, uploaded_at, id);
+
+CREATE FUNCTION recordings_latest_status() RETURNS TRIGGER AS $$
+BEGIN
+    NEW.latest_status := COALESCE((SELECT status FROM analysis_runs WHERE id = NEW.latest_run), 'pending');
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+-- also fires when deleting the latest run sets latest_run to NULL
+CREATE TRIGGER recordings_latest_status BEFORE INSERT OR UPDATE OF latest_run ON recordings
+    FOR EACH ROW EXECUTE FUNCTION recordings_latest_status();
+
+CREATE FUNCTION analysis_runs_latest_status() RETURNS TRIGGER AS $$
+BEGIN
+    UPDATE recordings SET latest_status = NEW.status
+    WHERE id = NEW.recording_id AND latest_run = NEW.id;
+    RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER analysis_runs_latest_status AFTER UPDATE OF status ON analysis_runs
+    FOR EACH ROW WHEN (OLD.status IS DISTINCT FROM NEW.status)
+    EXECUTE FUNCTION analysis_runs_latest_status();
@@ -0,0 +1,6 @@
+-- Add migration script here
+-- a PATCH claims the upload until locked_until instead of holding a lock for the whole
+-- request; lock_token identifies the claim, so a request whose lease ran out cannot
+-- overwrite the progress of the one that took over
+ALTER TABLE tus_uploads ADD COLUMN locked_until TIMESTAMPTZ;
+ALTER TABLE tus_uploads ADD COLUMN lock_token UUID;
@@ -1,28 +1,205 @@
+use std::time::Duration;
+
+use sqlx::PgConnection;
+
 use crate::{
     AppState,
-    message_queue::types::{AnalysisRequestInner, KafkaEnvelope},
+    message_queue::types::{AnalysisControlInner, AnalysisRequestInner, KafkaEnvelope},
 };
 
-pub async fn analyze_recording(state: AppState, rec_id: uuid::Uuid) -> eyre::Result<()> {
+const RELAY_BATCH_SIZE: i64 = 20;
+const RELAY_IDLE_INTERVAL: Duration = Duration::from_secs(5);
+const MAX_RETRY_DELAY_SECS: i32 = 300;
+const PRESIGNED_URL_LIFETIME: Duration = Duration::from_secs(3600);
+/// How long claimed entries are hidden from other relays; an entry is only picked up
+/// again before it was published if its relay died.
+const CLAIM_LEASE: Duration = Duration::from_secs(300);
+
+/// Creates a new analysis run for the recording and queues its request in the outbox.
+///
+/// Runs on the caller's connection so the run is only created if the surrounding
+/// transaction commits; call [`wake_relay`] afterwards to publish without delay.
+/// The run id doubles as the message envelope id, so results can be attributed to the
+/// run that produced them.
+pub async fn analyze_recording(
+    conn: &mut PgConnection,
+    rec_id: uuid::Uuid,
+) -> eyre::Result<uuid::Uuid> {
     let row = sqlx::query!("SELECT * FROM recordings WHERE id=$1", rec_id)
-        .fetch_one(&state.db)
+        .fetch_one(&mut *conn)
         .await?;
 
+    let run_id = uuid::Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO analysis_runs (id, recording_id, pipeline_version, force_diarize, transcript_s3_path, last_update) VALUES ($1, $2, $3, $4, $5, now())",
+        run_id,
+        rec_id,
+        std::env::var("ANALYSIS_PIPELINE_VERSION").ok(),
+        row.force_diarize,
+        row.original_transcript_s3_path,
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!(
+        "UPDATE recordings SET latest_run=$1 WHERE id=$2",
+        run_id,
+        rec_id
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!(
+        "INSERT INTO analysis_outbox (id, run_id, kind) VALUES ($1, $2, 'request')",
+        uuid::Uuid::new_v4(),
+        run_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(run_id)
+}
+
+/// Queues a cancellation of the run in the outbox, dropping its request if that was not
+/// published yet.
+pub async fn cancel_run(conn: &mut PgConnection, run_id: uuid::Uuid) -> eyre::Result<()> {
+    sqlx::query!(
+        "UPDATE analysis_outbox SET published_at=now(), last_error='cancelled before publishing'
+        WHERE run_id=$1 AND kind='request' AND published_at IS NULL",
+        run_id
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query!(
+        "INSERT INTO analysis_outbox (id, run_id, kind) VALUES ($1, $2, 'cancel')",
+        uuid::Uuid::new_v4(),
+        run_id
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Tells the outbox relay that new entries were committed.
+pub fn wake_relay(state: &AppState) {
+    state.outbox_wakeup.notify_one();
+}
+
+/// Publishes pending outbox entries, retrying failed ones with exponential backoff.
+pub async fn relay_loop(state: AppState) {
+    loop {
+        match relay_batch(&state).await {
+            Ok(published) if published > 0 => continue,
+            Ok(_) => {}
+            Err(why) => tracing::error!("failed to relay analysis outbox: {why:?}"),
+        }
+        let _ = tokio::time::timeout(RELAY_IDLE_INTERVAL, state.outbox_wakeup.notified()).await;
+    }
+}
+
+/// Returns the number of entries that were published.
+///
+/// Entries are claimed by moving their next attempt past [`CLAIM_LEASE`], so no
+/// transaction is held open while publishing; each outcome is stored on its own.
+async fn relay_batch(state: &AppState) -> eyre::Result<usize> {
+    let entries = sqlx::query!(
+        "WITH claimed AS (
+            UPDATE analysis_outbox SET next_attempt_at = now() + make_interval(secs => $2)
+            WHERE id IN (
+                SELECT id FROM analysis_outbox
+                WHERE published_at IS NULL AND next_attempt_at <= now()
+                ORDER BY created_at
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, run_id, kind, attempts, created_at
+        )
+        SELECT id, run_id, kind, attempts FROM claimed ORDER BY created_at",
+        RELAY_BATCH_SIZE,
+        CLAIM_LEASE.as_secs_f64()
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    let mut published = 0;
+    for entry in entries {
+        let result = match entry.kind.as_str() {
+            "request" => submit_run(state, entry.run_id).await,
+            "cancel" => {
+                state
+                    .bus
+                    .send_control(&KafkaEnvelope {
+                        id: entry.run_id,
+                        data: AnalysisControlInner::Cancel,
+                    })
+                    .await
+            }
+            other => Err(eyre::eyre!("unknown outbox entry kind '{other}'")),
+        };
+
+        match result {
+            Ok(()) => {
+                sqlx::query!(
+                    "UPDATE analysis_outbox SET published_at=now(), attempts=attempts+1, last_error=NULL WHERE id=$1",
+                    entry.id
+                )
+                .execute(&state.db)
+                .await?;
+                published += 1;
+            }
+            Err(why) => {
+                tracing::warn!("failed to publish outbox entry {}: {why:?}", entry.id);
+                let delay_secs = 2_i32
+                    .saturating_pow(entry.attempts.clamp(0, 16) as u32)
+                    .min(MAX_RETRY_DELAY_SECS);
+                sqlx::query!(
+                    "UPDATE analysis_outbox SET attempts=attempts+1, last_error=$1, next_attempt_at=now() + make_interval(secs => $2) WHERE id=$3",
+                    format!("{why:?}"),
+                    delay_secs as f64,
+                    entry.id
+                )
+                .execute(&state.db)
+                .await?;
+            }
+        }
+    }
+
+    Ok(published)
+}
+
+async fn submit_run(state: &AppState, run_id: uuid::Uuid) -> eyre::Result<()> {
+    let Some(row) = sqlx::query!(
+        "SELECT analysis_runs.force_diarize, analysis_runs.transcript_s3_path, recordings.original_s3_path
+        FROM analysis_runs
+        JOIN recordings ON recordings.id = analysis_runs.recording_id
+        WHERE analysis_runs.id=$1",
+        run_id
+    )
+    .fetch_optional(&state.db)
+    .await?
+    else {
+        tracing::warn!("analysis run {run_id} no longer exists, not submitting it");
+        return Ok(());
+    };
+
     let download_url = state
-        .s3
-        .presign_get(row.original_s3_path, 3600, None)
+        .storage
+        .presign_get(&row.original_s3_path, PRESIGNED_URL_LIFETIME, None)
         .await?;
-    let transcript_url = match row.original_transcript_s3_path {
-        Some(path) => Some(state.s3.presign_get(path, 3600, None).await?),
+    let transcript_url = match row.transcript_s3_path {
+        Some(path) => Some(
+            state
+                .storage
+                .presign_get(&path, PRESIGNED_URL_LIFETIME, None)
+                .await?,
+        ),
         None => None,
     };
 
     let force_diarize = row.force_diarize;
 
     state
-        .kafka
+        .bus
         .send_request(&KafkaEnvelope {
-            id: rec_id,
+            id: run_id,
             data: AnalysisRequestInner {
                 download_url,
                 transcript_url,
@@ -0,0 +1,231 @@
+use std::{collections::HashSet, time::Duration};
+
+use sqlx::{PgConnection, PgPool};
+
+use crate::{
+    AppState,
+    endpoints::{direct_upload, tus},
+    storage::{BlobInfo, BlobStorage},
+};
+
+const CLEANUP_BATCH_SIZE: i64 = 50;
+const CLEANUP_IDLE_INTERVAL: Duration = Duration::from_secs(30);
+const MAX_RETRY_DELAY_SECS: i32 = 3600;
+/// How long claimed deletions are hidden from other workers; one is only picked up again
+/// before its outcome was stored if its worker died or storage hangs.
+const CLAIM_LEASE: Duration = Duration::from_secs(300);
+/// Key prefixes the backend writes blobs under.
+const BLOB_PREFIXES: [&str; 3] = ["original_upload/", "original_transcript/", "direct_upload/"];
+/// Unreferenced objects younger than this may belong to an upload that is still running.
+const ORPHAN_GRACE_PERIOD: chrono::TimeDelta = chrono::TimeDelta::hours(1);
+
+/// Queues the blob at `key` for removal from storage.
+///
+/// Runs on the caller's connection so the blob is only removed if the surrounding
+/// transaction commits; call [`wake_cleanup`] afterwards to remove it without delay.
+pub async fn queue_deletion(conn: &mut PgConnection, key: &str) -> eyre::Result<()> {
+    sqlx::query!(
+        "INSERT INTO blob_deletions (key) VALUES ($1) ON CONFLICT (key) DO NOTHING",
+        key
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Tells the cleanup worker that new deletions were committed.
+pub fn wake_cleanup(state: &AppState) {
+    state.cleanup_wakeup.notify_one();
+}
+
+/// Removes queued blobs from storage, retrying failed ones with exponential backoff.
+///
+/// Expired resumable and direct uploads are discarded along the way.
+pub async fn cleanup_loop(state: AppState) {
+    loop {
+        if let Err(why) = tus::expire_uploads(&state).await {
+            tracing::error!("failed to expire resumable uploads: {why:?}");
+        }
+        if let Err(why) = direct_upload::expire_uploads(&state).await {
+            tracing::error!("failed to expire direct uploads: {why:?}");
+        }
+        match cleanup_batch(&state).await {
+            Ok(removed) if removed > 0 => continue,
+            Ok(_) => {}
+            Err(why) => tracing::error!("failed to clean up deleted blobs: {why:?}"),
+        }
+        let _ = tokio::time::timeout(CLEANUP_IDLE_INTERVAL, state.cleanup_wakeup.notified()).await;
+    }
+}
+
+/// Returns the number of blobs that were removed.
+///
+/// Deletions are claimed by moving their next attempt past [`CLAIM_LEASE`], so no
+/// transaction is held open while storage answers; each outcome is stored on its own.
+async fn cleanup_batch(state: &AppState) -> eyre::Result<usize> {
+    let entries = sqlx::query!(
+        "WITH claimed AS (
+            UPDATE blob_deletions SET next_attempt_at = now() + make_interval(secs => $2)
+            WHERE key IN (
+                SELECT key FROM blob_deletions
+                WHERE next_attempt_at <= now()
+                ORDER BY created_at
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING key, attempts, created_at
+        )
+        SELECT key, attempts FROM claimed ORDER BY created_at",
+        CLEANUP_BATCH_SIZE,
+        CLAIM_LEASE.as_secs_f64()
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    let mut removed = 0;
+    for entry in entries {
+        // never remove a blob that a recording or run still points at
+        let referenced = sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM recordings WHERE original_s3_path=$1 OR original_transcript_s3_path=$1)
+            OR EXISTS(SELECT 1 FROM analysis_runs WHERE transcript_s3_path=$1) AS "referenced!""#,
+            entry.key
+        )
+        .fetch_one(&state.db)
+        .await?;
+
+        let result = if referenced {
+            tracing::warn!("blob {} is still referenced, not removing it", entry.key);
+            Ok(())
+        } else {
+            state.storage.delete(&entry.key).await
+        };
+
+        match result {
+            Ok(()) => {
+                sqlx::query!("DELETE FROM blob_deletions WHERE key=$1", entry.key)
+                    .execute(&state.db)
+                    .await?;
+                removed += 1;
+            }
+            Err(why) => {
+                tracing::warn!("failed to remove blob {}: {why:?}", entry.key);
+                let delay_secs = 2_i32
+                    .saturating_pow(entry.attempts.clamp(0, 16) as u32)
+                    .min(MAX_RETRY_DELAY_SECS);
+                sqlx::query!(
+                    "UPDATE blob_deletions SET attempts=attempts+1, last_error=$1, next_attempt_at=now() + make_interval(secs => $2) WHERE key=$3",
+                    format!("{why:?}"),
+                    delay_secs as f64,
+                    entry.key
+                )
+                .execute(&state.db)
+                .await?;
+            }
+        }
+    }
+
+    Ok(removed)
+}
+
+/// A recording or run that points at a blob which is not in storage.
+#[derive(Debug)]
+pub struct MissingBlob {
+    pub recording_id: uuid::Uuid,
+    pub key: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Reconciliation {
+    /// Objects in storage that nothing in the database refers to.
+    pub orphaned: Vec<BlobInfo>,
+    pub missing: Vec<MissingBlob>,
+}
+
+/// Compares the blobs in storage with the keys referenced from the database.
+///
+/// Objects that are already queued for deletion or that are younger than
+/// [`ORPHAN_GRACE_PERIOD`] are not reported as orphaned.
+pub async fn reconcile(db: &PgPool, storage: &dyn BlobStorage) -> eyre::Result<Reconciliation> {
+    let mut stored = Vec::new();
+    for prefix in BLOB_PREFIXES {
+        stored.extend(storage.list(prefix).await?);
+    }
+
+    let referenced = sqlx::query_as!(
+        MissingBlob,
+        r#"SELECT id AS "recording_id!", original_s3_path AS "key!" FROM recordings
+        UNION
+        SELECT id, original_transcript_s3_path FROM recordings WHERE original_transcript_s3_path IS NOT NULL
+        UNION
+        SELECT recording_id, transcript_s3_path FROM analysis_runs WHERE transcript_s3_path IS NOT NULL"#
+    )
+    .fetch_all(db)
+    .await?;
+    // queued objects are about to go away, unfinished uploads are about to be referenced
+    let queued: HashSet<String> = sqlx::query_scalar!(
+        r#"SELECT key AS "key!" FROM blob_deletions
+        UNION
+        SELECT storage_key FROM tus_uploads WHERE completed_at IS NULL
+        UNION
+        SELECT storage_key FROM direct_uploads WHERE completed_at IS NULL"#
+    )
+    .fetch_all(db)
+    .await?
+    .into_iter()
+    .collect();
+
+    let stored_keys: HashSet<&str> = stored.iter().map(|blob| blob.key.as_str()).collect();
+    let referenced_keys: HashSet<&str> = referenced.iter().map(|blob| blob.key.as_str()).collect();
+    let cutoff = chrono::Utc::now() - ORPHAN_GRACE_PERIOD;
+
+    let orphaned = stored
+        .iter()
+        .filter(|blob| !referenced_keys.contains(blob.key.as_str()))
+        .filter(|blob| !queued.contains(&blob.key))
+        .filter(|blob| blob.last_modified.is_none_or(|modified| modified < cutoff))
+        .cloned()
+        .collect();
+    let missing = referenced
+        .into_iter()
+        .filter(|blob| !stored_keys.contains(blob.key.as_str()))
+        .collect();
+
+    Ok(Reconciliation { orphaned, missing })
+}
+
+/// Entry point of `backend reconcile [--fix]`.
+///
+/// Prints one line per problem; with `--fix`, orphaned objects are queued for deletion.
+/// Rows with missing blobs are only reported, since the data cannot be recovered.
+pub async fn run_reconcile_command(
+    db: &PgPool,
+    storage: &dyn BlobStorage,
+    fix: bool,
+) -> eyre::Result<()> {
+    let report = reconcile(db, storage).await?;
+    for blob in &report.orphaned {
+        println!("orphaned\t{}\t{} bytes", blob.key, blob.size);
+    }
+    for blob in &report.missing {
+        println!("missing\t{}\trecording {}", blob.key, blob.recording_id);
+    }
+
+    if fix && !report.orphaned.is_empty() {
+        let mut tx = db.begin().await?;
+        for blob in &report.orphaned {
+            queue_deletion(&mut tx, &blob.key).await?;
+        }
+        tx.commit().await?;
+        println!(
+            "queued {} orphaned objects for deletion; they are removed once the server runs",
+            report.orphaned.len()
+        );
+    }
+
+    println!(
+        "{} orphaned objects, {} missing blobs",
+        report.orphaned.len(),
+        report.missing.len()
+    );
+    Ok(())
+}
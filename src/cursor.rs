@@ -0,0 +1,48 @@
+//! Opaque pagination cursors.
+//!
+//! A cursor is the URL-safe base64 encoding of a JSON value describing where the previous
+//! page stopped. Clients must treat it as an opaque token.
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::result::AppError;
+
+pub fn encode<T: Serialize>(value: &T) -> String {
+    URL_SAFE_NO_PAD.encode(serde_json::to_vec(value).expect("cursor should serialize"))
+}
+
+pub fn decode<T: DeserializeOwned>(cursor: &str) -> Result<T, AppError> {
+    let bytes = URL_SAFE_NO_PAD
+        .decode(cursor)
+        .map_err(|_| AppError::bad_request("malformed pagination cursor"))?;
+    serde_json::from_slice(&bytes).map_err(|_| AppError::bad_request("malformed pagination cursor"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct Position {
+        start: f32,
+        id: uuid::Uuid,
+    }
+
+    #[test]
+    fn test_cursor_roundtrip() {
+        let position = Position {
+            start: 12.345,
+            id: uuid::Uuid::new_v4(),
+        };
+        let cursor = encode(&position);
+        assert!(!cursor.contains(['+', '/', '=']));
+        assert_eq!(decode::<Position>(&cursor).unwrap(), position);
+    }
+
+    #[test]
+    fn test_cursor_rejects_garbage() {
+        assert!(decode::<Position>("not a cursor!").is_err());
+        assert!(decode::<Position>(&encode(&"wrong shape")).is_err());
+    }
+}
@@ -0,0 +1,114 @@
+use axum::{Json, extract::State, http::StatusCode};
+use chrono::{DateTime, Utc};
+
+use crate::{
+    AppState,
+    extract::{Path, Query},
+    message_queue::ingest::{self, IngestError},
+    result::{AppError, AppResult},
+    url::UrlGenerator,
+};
+
+#[derive(Debug, serde::Deserialize)]
+pub struct ListDeadLettersQuery {
+    #[serde(default)]
+    include_replayed: bool,
+    limit: Option<i64>,
+}
+
+#[derive(serde::Serialize)]
+pub struct DeadLetterEntry {
+    replay_url: String,
+    id: uuid::Uuid,
+    topic: String,
+    partition: i32,
+    offset: i64,
+    error: String,
+    attempts: i32,
+    created_at: DateTime<Utc>,
+    replayed_at: Option<DateTime<Utc>>,
+    replay_error: Option<String>,
+    payload: String,
+}
+
+pub async fn list_dead_letters(
+    State(state): State<AppState>,
+    Query(query): Query<ListDeadLettersQuery>,
+    url: UrlGenerator,
+) -> AppResult<Json<Vec<DeadLetterEntry>>> {
+    let rows = sqlx::query!(
+        "SELECT * FROM dead_letters
+        WHERE $1 OR replayed_at IS NULL
+        ORDER BY created_at DESC
+        LIMIT $2",
+        query.include_replayed,
+        query.limit.unwrap_or(100).clamp(1, 1000),
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(Json(
+        rows.into_iter()
+            .map(|row| DeadLetterEntry {
+                replay_url: url.url(format!("/admin/dead-letters/{}/replay", row.id)),
+                id: row.id,
+                topic: row.topic,
+                partition: row.kafka_partition,
+                offset: row.kafka_offset,
+                error: row.error,
+                attempts: row.attempts,
+                created_at: row.created_at,
+                replayed_at: row.replayed_at,
+                replay_error: row.replay_error,
+                payload: String::from_utf8_lossy(&row.payload).into_owned(),
+            })
+            .collect(),
+    ))
+}
+
+/// Feeds a dead-lettered message through ingestion again.
+pub async fn replay_dead_letter(
+    State(state): State<AppState>,
+    Path(id): Path<uuid::Uuid>,
+) -> AppResult<StatusCode> {
+    let Some(row) = sqlx::query!(
+        "SELECT payload, replayed_at FROM dead_letters WHERE id=$1",
+        id
+    )
+    .fetch_optional(&state.db)
+    .await?
+    else {
+        return Err(AppError::not_found("dead letter not found"));
+    };
+
+    if row.replayed_at.is_some() {
+        return Err(AppError::conflict("dead letter was already replayed"));
+    }
+
+    match ingest::handle_message(&state, &row.payload).await {
+        Ok(()) => {
+            sqlx::query!(
+                "UPDATE dead_letters SET replayed_at=now(), replay_error=NULL WHERE id=$1",
+                id
+            )
+            .execute(&state.db)
+            .await?;
+            Ok(StatusCode::NO_CONTENT)
+        }
+        Err(failure) => {
+            sqlx::query!(
+                "UPDATE dead_letters SET attempts=attempts+1, replay_error=$1 WHERE id=$2",
+                format!("{:?}", failure.report()),
+                id
+            )
+            .execute(&state.db)
+            .await?;
+            match failure {
+                IngestError::Transient(why) => Err(AppError::upstream(why)),
+                IngestError::Poison(why) => {
+                    Err(AppError::conflict(format!("replay failed: {why}")))
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+};
+use eyre::Context;
+
+use crate::{
+    AppState,
+    analysis_submit::{analyze_recording, cancel_run, wake_relay},
+    events::{self, AnalysisEvent},
+    extract::Path,
+    result::{AppError, AppResult},
+};
+
+fn is_in_progress(status: &str) -> bool {
+    matches!(status, "pending" | "running")
+}
+
+/// Submits the recording for a fresh analysis run; earlier runs are kept.
+pub async fn reanalyze_recording(
+    State(state): State<AppState>,
+    Path(id): Path<uuid::Uuid>,
+) -> AppResult<(StatusCode, HeaderMap)> {
+    let mut tx = state.db.begin().await?;
+    // the lock serializes concurrent submissions and cancellations of the recording
+    let Some(latest_run) = sqlx::query_scalar!(
+        "SELECT latest_run FROM recordings WHERE id=$1 FOR NO KEY UPDATE",
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    else {
+        return Err(AppError::not_found("recording not found"));
+    };
+
+    if let Some(run_id) = latest_run {
+        let status = sqlx::query_scalar!("SELECT status FROM analysis_runs WHERE id=$1", run_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        if is_in_progress(&status) {
+            return Err(AppError::conflict(
+                "analysis is already in progress; cancel it before resubmitting",
+            ));
+        }
+    }
+
+    let run_id = analyze_recording(&mut tx, id)
+        .await
+        .wrap_err("failed to queue recording for analysis")?;
+    tx.commit().await?;
+    wake_relay(&state);
+
+    let mut headers = HeaderMap::new();
+    headers.insert("Location", format!("/runs/{}", run_id).try_into().unwrap());
+    Ok((StatusCode::ACCEPTED, headers))
+}
+
+/// Asks the analysis workers to stop the latest run and marks it as cancelled.
+///
+/// Results that arrive after this point are dropped by the receive loop.
+pub async fn cancel_analysis(
+    State(state): State<AppState>,
+    Path(id): Path<uuid::Uuid>,
+) -> AppResult<StatusCode> {
+    let mut tx = state.db.begin().await?;
+    let Some(latest_run) = sqlx::query_scalar!(
+        "SELECT latest_run FROM recordings WHERE id=$1 FOR NO KEY UPDATE",
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    else {
+        return Err(AppError::not_found("recording not found"));
+    };
+    let Some(run_id) = latest_run else {
+        return Err(AppError::conflict("analysis is not in progress"));
+    };
+
+    // a run that finished in the meantime keeps its final status
+    let cancelled = sqlx::query!(
+        "UPDATE analysis_runs SET status='cancelled', finished_at=now(), last_update=now()
+        WHERE id=$1 AND status IN ('pending', 'running')",
+        run_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    if cancelled.rows_affected() == 0 {
+        return Err(AppError::conflict("analysis is not in progress"));
+    }
+    cancel_run(&mut tx, run_id).await?;
+    events::publish(
+        &mut tx,
+        &AnalysisEvent::Cancelled {
+            recording_id: id,
+            run_id,
+        },
+    )
+    .await?;
+    tx.commit().await?;
+    wake_relay(&state);
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -1,11 +1,12 @@
-use axum::{
-    Json,
-    extract::{Path, State},
-};
+use axum::extract::State;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    AppState, message_queue::types::MetricCollection, result::AppResult, url::UrlGenerator,
+    AppState,
+    extract::{Json, Path},
+    message_queue::types::MetricCollection,
+    result::{AppError, AppResult},
+    url::UrlGenerator,
 };
 
 pub async fn get_channel(
@@ -21,7 +22,7 @@ pub async fn get_channel(
     .await?;
 
     let Some(row) = row else {
-        return Err(eyre::eyre!("channel not found").into());
+        return Err(AppError::not_found("channel not found"));
     };
 
     let segments_begin_url = url.url(format!("/channels/{}/segments?start=0&end=3600", id));
@@ -29,6 +30,7 @@ pub async fn get_channel(
     Ok(Json(ChannelData {
         self_url: url.url(format!("/channels/{}", id)),
         recording_url: url.url(format!("/recordings/{}", row.recording)),
+        run_url: url.url(format!("/runs/{}", row.run_id)),
         idx_in_file: row.idx_in_file,
         assigned_name: row.assigned_name,
         metrics: row.metrics.0,
@@ -42,23 +44,26 @@ pub async fn set_assigned_name(
     Json(name): Json<Option<String>>,
 ) -> AppResult<axum::http::StatusCode> {
     tracing::info!("set assigned name for channel {id} to {name:?}");
-    if let Some(name) = name {
+    let result = if let Some(name) = name {
         sqlx::query!("UPDATE channels SET assigned_name=$1 WHERE id=$2", name, id)
             .execute(&state.db)
-            .await?;
-        Ok(axum::http::StatusCode::NO_CONTENT)
+            .await?
     } else {
         sqlx::query!("UPDATE channels SET assigned_name=NULL WHERE id=$1", id)
             .execute(&state.db)
-            .await?;
-        return Ok(axum::http::StatusCode::NO_CONTENT);
+            .await?
+    };
+    if result.rows_affected() == 0 {
+        return Err(AppError::not_found("channel not found"));
     }
+    Ok(axum::http::StatusCode::NO_CONTENT)
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ChannelData {
     self_url: String,
     recording_url: String,
+    run_url: String,
     idx_in_file: i32,
     assigned_name: Option<String>,
     segments_begin_url: String,
@@ -0,0 +1,377 @@
+//! Uploads that go straight to storage through presigned URLs.
+//!
+//! `POST /uploads` hands out a presigned `PUT` URL, or one URL per part for large files.
+//! Once the client has uploaded the audio, `POST /uploads/{id}/complete` checks and probes
+//! the object, then creates the recording.
+//!
+//! The audio never passes through the backend: checksums are checked by storage, which
+//! only accepts a body with the digest the URL was signed for, and the file is probed from
+//! its first few megabytes.
+//!
+//! A presigned `PUT` URL stays valid until the upload expires, so single-request uploads go
+//! to a staging key and are copied to the recording's key before being checked; the checked
+//! object can then no longer be replaced by the client. Multipart uploads go to the
+//! recording's key directly, as their part URLs stop working once the parts are assembled.
+
+use std::{collections::BTreeMap, time::Duration};
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+};
+use chrono::{DateTime, Utc};
+use eyre::Context;
+
+use crate::{
+    AppState,
+    analysis_submit::wake_relay,
+    blob_cleanup::{queue_deletion, wake_cleanup},
+    endpoints::upload::{
+        MAX_UPLOAD_SIZE, NewRecording, UploadResponse, audio_key, create_recording,
+    },
+    extract::{Json, Path},
+    media,
+    result::{AppError, AppResult},
+    storage::{Checksum, UploadedPart},
+    url::UrlGenerator,
+};
+
+/// Files larger than this are uploaded in parts of this size.
+const PART_SIZE: i64 = 64 * 1024 * 1024;
+const UPLOAD_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Where a single-request upload is stored until it is completed.
+fn staging_key(upload_id: uuid::Uuid) -> String {
+    format!("direct_upload/{upload_id}")
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct CreateDirectUpload {
+    filename: String,
+    /// Exact size of the file in bytes.
+    size: i64,
+    /// Hex SHA-256 of a file uploaded in a single request; storage rejects other bodies.
+    sha256: Option<String>,
+    /// Hex MD5 of every part of a multipart upload, in order; storage rejects other parts.
+    #[serde(default)]
+    part_md5: Vec<String>,
+    diarize: Option<bool>,
+}
+
+/// Headers a presigned `PUT` has to be sent with.
+type UploadHeaders = BTreeMap<&'static str, String>;
+
+fn upload_headers(checksum: Option<&Checksum>) -> UploadHeaders {
+    checksum.map(Checksum::header).into_iter().collect()
+}
+
+#[derive(serde::Serialize)]
+pub struct PartUrl {
+    part_number: u32,
+    url: String,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    headers: UploadHeaders,
+}
+
+#[derive(serde::Serialize)]
+pub struct DirectUpload {
+    id: uuid::Uuid,
+    complete_url: String,
+    expires_at: DateTime<Utc>,
+    /// Set for single-request uploads.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upload_url: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    upload_headers: UploadHeaders,
+    /// Set for multipart uploads; every part except the last one has this size.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    part_size: Option<i64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    parts: Vec<PartUrl>,
+}
+
+pub async fn create_direct_upload(
+    State(state): State<AppState>,
+    url: UrlGenerator,
+    Json(body): Json<CreateDirectUpload>,
+) -> AppResult<(StatusCode, Json<DirectUpload>)> {
+    if body.filename.is_empty() {
+        return Err(AppError::bad_request("filename must not be empty"));
+    }
+    if body.size <= 0 {
+        return Err(AppError::bad_request("size must be positive"));
+    }
+    if body.size > MAX_UPLOAD_SIZE {
+        return Err(AppError::PayloadTooLarge(format!(
+            "uploads are limited to {MAX_UPLOAD_SIZE} bytes"
+        )));
+    }
+    let multipart = body.size > PART_SIZE;
+    let part_count = (body.size as u64).div_ceil(PART_SIZE as u64) as usize;
+    let sha256 = body.sha256.map(|sha256| sha256.to_ascii_lowercase());
+    let checksum = sha256
+        .as_deref()
+        .map(|sha256| {
+            Checksum::sha256(sha256)
+                .ok_or_else(|| AppError::bad_request("sha256 must be 64 hex digits"))
+        })
+        .transpose()?;
+    if multipart && checksum.is_some() {
+        return Err(AppError::bad_request(format!(
+            "sha256 is only checked for files of up to {PART_SIZE} bytes; send part_md5 instead"
+        )));
+    }
+    if !body.part_md5.is_empty() && (!multipart || body.part_md5.len() != part_count) {
+        return Err(AppError::bad_request(format!(
+            "part_md5 must have one digest for each of the {part_count} parts of a file larger than {PART_SIZE} bytes"
+        )));
+    }
+    let part_checksums = body
+        .part_md5
+        .iter()
+        .map(|md5| {
+            Checksum::md5(md5)
+                .ok_or_else(|| AppError::bad_request("part_md5 must contain 32 hex digits each"))
+        })
+        .collect::<AppResult<Vec<_>>>()?;
+
+    let id = uuid::Uuid::new_v4();
+    let key = if multipart {
+        audio_key(id)
+    } else {
+        staging_key(id)
+    };
+    let expires_at = Utc::now() + UPLOAD_LIFETIME;
+
+    let mut upload = DirectUpload {
+        id,
+        complete_url: url.url(format!("/uploads/{id}/complete")),
+        expires_at,
+        upload_url: None,
+        upload_headers: UploadHeaders::new(),
+        part_size: None,
+        parts: Vec::new(),
+    };
+    // the URLs must not outlive the upload, or objects could be written after expiry
+    let url_lifetime = (expires_at - Utc::now()).to_std().unwrap_or_default();
+    let mut storage_upload_id = None;
+    let presigned: eyre::Result<()> = async {
+        if !multipart {
+            upload.upload_url = Some(
+                state
+                    .storage
+                    .presign_put(&key, url_lifetime, checksum.as_ref())
+                    .await?,
+            );
+            upload.upload_headers = upload_headers(checksum.as_ref());
+        } else {
+            let upload_id = state.storage.create_multipart(&key).await?;
+            for part_number in 1..=part_count as u32 {
+                let checksum = part_checksums.get(part_number as usize - 1);
+                upload.parts.push(PartUrl {
+                    part_number,
+                    url: state
+                        .storage
+                        .presign_upload_part(&key, &upload_id, part_number, url_lifetime, checksum)
+                        .await?,
+                    headers: upload_headers(checksum),
+                });
+            }
+            upload.part_size = Some(PART_SIZE);
+            storage_upload_id = Some(upload_id);
+        }
+        Ok(())
+    }
+    .await;
+    presigned
+        .wrap_err("failed to prepare upload in storage")
+        .map_err(AppError::upstream)?;
+
+    sqlx::query!(
+        "INSERT INTO direct_uploads (id, storage_key, storage_upload_id, filename, force_diarize, expected_size, expected_sha256, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+        id,
+        key,
+        storage_upload_id,
+        body.filename,
+        body.diarize,
+        body.size,
+        sha256,
+        expires_at
+    )
+    .execute(&state.db)
+    .await?;
+
+    Ok((StatusCode::CREATED, Json(upload)))
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct CompleteDirectUpload {
+    /// Part numbers and `ETag`s of the uploaded parts; required for multipart uploads.
+    #[serde(default)]
+    parts: Vec<UploadedPart>,
+}
+
+/// Checks the uploaded object and creates the recording.
+///
+/// Completing an upload again returns the recording created the first time.
+pub async fn complete_direct_upload(
+    State(state): State<AppState>,
+    Path(id): Path<uuid::Uuid>,
+    body: Option<Json<CompleteDirectUpload>>,
+) -> AppResult<(StatusCode, HeaderMap, Json<UploadResponse>)> {
+    let Some(upload) = sqlx::query!(
+        "SELECT * FROM direct_uploads WHERE id=$1 AND expires_at > now()",
+        id
+    )
+    .fetch_optional(&state.db)
+    .await?
+    else {
+        return Err(AppError::not_found("upload not found"));
+    };
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "Location",
+        format!("/recordings/{}", id).try_into().unwrap(),
+    );
+    let response = Json(UploadResponse {
+        upload_id: id,
+        webhook: None,
+    });
+    if upload.completed_at.is_some() {
+        return Ok((StatusCode::OK, headers, response));
+    }
+
+    // a multipart upload only shows up in storage once its parts are assembled; an earlier
+    // attempt may already have done that before failing the checks below
+    let mut blob = state
+        .storage
+        .head(&upload.storage_key)
+        .await
+        .map_err(AppError::upstream)?;
+    if let (None, Some(storage_upload_id)) = (&blob, &upload.storage_upload_id) {
+        let Json(body) = body.unwrap_or_default();
+        if body.parts.is_empty() {
+            return Err(AppError::bad_request(
+                "parts are required to complete a multipart upload",
+            ));
+        }
+        let mut parts = body.parts;
+        parts.sort_by_key(|part| part.part_number);
+        state
+            .storage
+            .complete_multipart(&upload.storage_key, storage_upload_id, &parts)
+            .await
+            .wrap_err("failed to assemble uploaded parts")
+            .map_err(AppError::upstream)?;
+        blob = state
+            .storage
+            .head(&upload.storage_key)
+            .await
+            .map_err(AppError::upstream)?;
+    }
+    if blob.is_none() {
+        return Err(AppError::conflict("the file has not been uploaded yet"));
+    }
+
+    let key = audio_key(id);
+    if upload.storage_key != key {
+        state
+            .storage
+            .copy(&upload.storage_key, &key)
+            .await
+            .wrap_err("failed to copy uploaded file")
+            .map_err(AppError::upstream)?;
+    }
+    let Some(blob) = state.storage.head(&key).await.map_err(AppError::upstream)? else {
+        return Err(AppError::upstream(eyre::eyre!(
+            "copied upload {id} is missing from storage"
+        )));
+    };
+    if blob.size as i64 != upload.expected_size {
+        return Err(AppError::bad_request(format!(
+            "uploaded file has {} bytes, expected {}",
+            blob.size, upload.expected_size
+        )));
+    }
+    let media = media::probe_stored(state.storage.as_ref(), &key).await?;
+
+    let mut tx = state.db.begin().await?;
+    let completed = sqlx::query!(
+        "UPDATE direct_uploads SET completed_at=now() WHERE id=$1 AND completed_at IS NULL",
+        id
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    if completed == 0 {
+        // a concurrent request completed it first
+        return Ok((StatusCode::OK, headers, response));
+    }
+    create_recording(
+        &mut tx,
+        &NewRecording {
+            id,
+            filename: upload.filename,
+            force_diarize: upload.force_diarize,
+            transcript_path: None,
+            // storage only accepted a body with this digest
+            content_sha256: upload.expected_sha256,
+            idempotency_key: None,
+            media,
+        },
+    )
+    .await?;
+    if upload.storage_key != key {
+        queue_deletion(&mut tx, &upload.storage_key).await?;
+    }
+    tx.commit().await?;
+    wake_relay(&state);
+    wake_cleanup(&state);
+
+    Ok((StatusCode::CREATED, headers, response))
+}
+
+/// Discards expired uploads that were never completed, together with anything uploaded,
+/// and whatever was written to the staging key of completed ones.
+pub async fn expire_uploads(state: &AppState) -> eyre::Result<()> {
+    let expired = sqlx::query!(
+        "SELECT id, storage_key, storage_upload_id, completed_at FROM direct_uploads WHERE expires_at <= now()"
+    )
+    .fetch_all(&state.db)
+    .await?;
+    for upload in expired {
+        if let (None, Some(storage_upload_id)) = (upload.completed_at, &upload.storage_upload_id)
+            && let Err(why) = state
+                .storage
+                .abort_multipart(&upload.storage_key, storage_upload_id)
+                .await
+        {
+            tracing::warn!(
+                "failed to abort expired upload {}, retrying later: {why:?}",
+                upload.id
+            );
+            continue;
+        }
+
+        let mut tx = state.db.begin().await?;
+        let key = audio_key(upload.id);
+        if upload.completed_at.is_none() {
+            queue_deletion(&mut tx, &upload.storage_key).await?;
+            // a completion that failed its checks may have left a copy behind
+            if upload.storage_key != key {
+                queue_deletion(&mut tx, &key).await?;
+            }
+        } else if upload.storage_key != key {
+            // the upload URL worked until now, so the staging object may have been written
+            // again after completion removed it
+            queue_deletion(&mut tx, &upload.storage_key).await?;
+        }
+        sqlx::query!("DELETE FROM direct_uploads WHERE id=$1", upload.id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,97 @@
+use std::convert::Infallible;
+
+use axum::{
+    extract::State,
+    response::sse::{Event, KeepAlive, Sse},
+};
+use futures_util::{Stream, StreamExt};
+use tokio_stream::wrappers::{BroadcastStream, errors::BroadcastStreamRecvError};
+
+use crate::{
+    AppState,
+    events::AnalysisEvent,
+    extract::Path,
+    result::{AppError, AppResult},
+};
+
+/// Analysis state of the latest run, sent when a client connects and whenever it fell
+/// too far behind to be sent the individual events it missed.
+#[derive(serde::Serialize)]
+struct Snapshot {
+    recording_id: uuid::Uuid,
+    run_id: Option<uuid::Uuid>,
+    status: String,
+    percent: i32,
+    description: Option<String>,
+    channel: Option<i32>,
+}
+
+async fn snapshot(state: &AppState, recording_id: uuid::Uuid) -> AppResult<Option<Event>> {
+    let Some(row) = sqlx::query!(
+        "SELECT analysis_runs.id AS \"run_id?\", analysis_runs.status AS \"status?\", analysis_runs.percent AS \"percent?\",
+            analysis_runs.description, analysis_runs.channel
+        FROM recordings
+        LEFT JOIN analysis_runs ON analysis_runs.id = recordings.latest_run
+        WHERE recordings.id=$1",
+        recording_id
+    )
+    .fetch_optional(&state.db)
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    let snapshot = Snapshot {
+        recording_id,
+        run_id: row.run_id,
+        status: row.status.unwrap_or_else(|| "pending".into()),
+        percent: row.percent.unwrap_or_default(),
+        description: row.description,
+        channel: row.channel,
+    };
+    Ok(Some(
+        Event::default().event("snapshot").json_data(snapshot)?,
+    ))
+}
+
+/// Streams analysis progress of the recording as Server-Sent Events.
+///
+/// The first event is a `snapshot` of the latest run, followed by `progress`,
+/// `channel_completed`, `done`, `error` and `cancelled` events as they happen.
+pub async fn recording_events(
+    State(state): State<AppState>,
+    Path(id): Path<uuid::Uuid>,
+) -> AppResult<Sse<impl Stream<Item = Result<Event, Infallible>>>> {
+    // subscribe before taking the snapshot, so no event falls between the two
+    let receiver = state.events.subscribe();
+    let Some(initial) = snapshot(&state, id).await? else {
+        return Err(AppError::not_found("recording not found"));
+    };
+
+    let updates = BroadcastStream::new(receiver).filter_map(move |event| {
+        let state = state.clone();
+        async move {
+            match event {
+                Ok(event) if event.recording_id() == id => sse_event(&event),
+                Ok(_) => None,
+                Err(BroadcastStreamRecvError::Lagged(skipped)) => {
+                    tracing::warn!("event stream of recording {id} skipped {skipped} events");
+                    snapshot(&state, id).await.ok().flatten()
+                }
+            }
+        }
+    });
+    let stream = futures_util::stream::once(async { initial })
+        .chain(updates)
+        .map(Ok);
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+fn sse_event(event: &AnalysisEvent) -> Option<Event> {
+    Event::default()
+        .event(event.name())
+        .json_data(event)
+        .inspect_err(|why| tracing::error!("failed to serialize analysis event: {why}"))
+        .ok()
+}
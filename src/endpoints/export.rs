@@ -0,0 +1,352 @@
+use axum::{
+    extract::State,
+    http::{HeaderMap, HeaderValue, header},
+};
+use sqlx::types::Json as SJson;
+
+use crate::{
+    AppState,
+    extract::{Path, Query},
+    message_queue::types::MetricCollection,
+    metrics_table::{MetricsTable, SegmentRow, TableFormat},
+    result::{AppError, AppResult},
+    transcript::annotation::{self, AnnotationFormat, EafMedia, Interval, Tier},
+};
+
+#[derive(Debug, serde::Deserialize)]
+pub struct ExportAnnotationsQuery {
+    format: AnnotationFormat,
+    /// Comma-separated `provider:metric` pairs to write as extra tiers, e.g. `emotion:label`.
+    metrics: Option<String>,
+    /// Analysis run to export; defaults to the latest one.
+    run: Option<uuid::Uuid>,
+}
+
+/// Exports the segments of a recording for Praat or ELAN.
+///
+/// Each channel becomes an interval tier named after its assigned name, or
+/// `Channel {idx_in_file}`, followed by one tier per requested metric holding the
+/// metric's value for every segment that has one.
+pub async fn export_annotations(
+    State(state): State<AppState>,
+    Path(id): Path<uuid::Uuid>,
+    Query(query): Query<ExportAnnotationsQuery>,
+) -> AppResult<(HeaderMap, String)> {
+    let metrics = match &query.metrics {
+        Some(metrics) => metrics
+            .split(',')
+            .map(|pair| {
+                pair.trim()
+                    .split_once(':')
+                    .filter(|(provider, name)| !provider.is_empty() && !name.is_empty())
+                    .ok_or_else(|| {
+                        AppError::bad_request(format!(
+                            "invalid metric '{pair}', expected 'provider:metric'"
+                        ))
+                    })
+            })
+            .collect::<AppResult<Vec<_>>>()?,
+        None => Vec::new(),
+    };
+
+    let Some(recording) = sqlx::query!(
+        "SELECT original_filename, latest_run, media_container, media_duration_sec FROM recordings WHERE id=$1",
+        id
+    )
+    .fetch_optional(&state.db)
+    .await?
+    else {
+        return Err(AppError::not_found("recording not found"));
+    };
+
+    let run_id = query.run.or(recording.latest_run);
+    if let Some(run_id) = query.run {
+        let exists = sqlx::query_scalar!(
+            "SELECT EXISTS(SELECT 1 FROM analysis_runs WHERE id=$1 AND recording_id=$2) AS \"exists!\"",
+            run_id,
+            id
+        )
+        .fetch_one(&state.db)
+        .await?;
+        if !exists {
+            return Err(AppError::not_found("analysis run not found"));
+        }
+    }
+
+    let channels = sqlx::query!(
+        "SELECT id, idx_in_file, assigned_name FROM channels WHERE run_id = $1 ORDER BY idx_in_file",
+        run_id
+    )
+    .fetch_all(&state.db)
+    .await?;
+    let segments = sqlx::query!(
+        r#"SELECT segments.channel, segments.start_sec, segments.end_sec, segments.content,
+        segments.metrics_list AS "metrics: SJson<Vec<MetricCollection>>"
+        FROM segments
+        JOIN channels ON channels.id = segments.channel
+        WHERE channels.run_id = $1
+        ORDER BY segments.start_sec"#,
+        run_id
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    let mut tiers = Vec::with_capacity(channels.len() * (metrics.len() + 1));
+    for channel in &channels {
+        let speaker = channel
+            .assigned_name
+            .clone()
+            .unwrap_or_else(|| format!("Channel {}", channel.idx_in_file));
+        let channel_segments: Vec<_> = segments
+            .iter()
+            .filter(|segment| segment.channel == channel.id)
+            .collect();
+        tiers.push(Tier {
+            name: speaker.clone(),
+            intervals: channel_segments
+                .iter()
+                .map(|segment| Interval {
+                    start: round_millis(segment.start_sec),
+                    end: round_millis(segment.end_sec),
+                    text: segment.content.clone(),
+                })
+                .collect(),
+        });
+        for (provider, name) in &metrics {
+            tiers.push(Tier {
+                name: format!("{speaker} {provider}:{name}"),
+                intervals: channel_segments
+                    .iter()
+                    .filter_map(|segment| {
+                        let label = segment
+                            .metrics
+                            .iter()
+                            .filter(|collection| collection.provider == *provider)
+                            .flat_map(|collection| &collection.metrics)
+                            .find(|metric| metric.name() == *name)?
+                            .value_label()?;
+                        Some(Interval {
+                            start: round_millis(segment.start_sec),
+                            end: round_millis(segment.end_sec),
+                            text: label,
+                        })
+                    })
+                    .collect(),
+            });
+        }
+    }
+
+    let body = match query.format {
+        AnnotationFormat::TextGrid => {
+            annotation::textgrid(&tiers, recording.media_duration_sec.unwrap_or_default())
+        }
+        AnnotationFormat::Eaf => {
+            let media = EafMedia {
+                filename: &recording.original_filename,
+                mime_type: match recording.media_container.as_deref() {
+                    Some("wav") => "audio/x-wav",
+                    _ => "audio/*",
+                },
+            };
+            annotation::eaf(&tiers, Some(&media), chrono::Utc::now())
+        }
+    };
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(query.format.content_type()),
+    );
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        format!("attachment; filename=\"{id}.{}\"", query.format.extension())
+            .try_into()
+            .unwrap(),
+    );
+    Ok((headers, body))
+}
+
+/// Most recordings a single bulk metrics export may cover.
+const MAX_BULK_RECORDINGS: usize = 1000;
+
+#[derive(Debug, serde::Deserialize)]
+pub struct ExportMetricsQuery {
+    /// Analysis run to export; defaults to the latest one.
+    run: Option<uuid::Uuid>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct ExportBulkMetricsQuery {
+    /// Comma-separated recording ids; each recording contributes its latest run.
+    ids: String,
+}
+
+pub async fn export_metrics_csv(
+    state: State<AppState>,
+    id: Path<uuid::Uuid>,
+    query: Query<ExportMetricsQuery>,
+) -> AppResult<(HeaderMap, Vec<u8>)> {
+    export_metrics(state, id, query, TableFormat::Csv).await
+}
+
+pub async fn export_metrics_parquet(
+    state: State<AppState>,
+    id: Path<uuid::Uuid>,
+    query: Query<ExportMetricsQuery>,
+) -> AppResult<(HeaderMap, Vec<u8>)> {
+    export_metrics(state, id, query, TableFormat::Parquet).await
+}
+
+/// Exports one row per segment with its channel, timing, text and one column per
+/// `provider.metric_name`.
+async fn export_metrics(
+    State(state): State<AppState>,
+    Path(id): Path<uuid::Uuid>,
+    Query(query): Query<ExportMetricsQuery>,
+    format: TableFormat,
+) -> AppResult<(HeaderMap, Vec<u8>)> {
+    let exists = sqlx::query_scalar!(
+        "SELECT EXISTS(SELECT 1 FROM recordings WHERE id=$1) AS \"exists!\"",
+        id
+    )
+    .fetch_one(&state.db)
+    .await?;
+    if !exists {
+        return Err(AppError::not_found("recording not found"));
+    }
+    if let Some(run_id) = query.run {
+        let exists = sqlx::query_scalar!(
+            "SELECT EXISTS(SELECT 1 FROM analysis_runs WHERE id=$1 AND recording_id=$2) AS \"exists!\"",
+            run_id,
+            id
+        )
+        .fetch_one(&state.db)
+        .await?;
+        if !exists {
+            return Err(AppError::not_found("analysis run not found"));
+        }
+    }
+
+    let rows = fetch_segment_rows(&state, &[id], query.run).await?;
+    let table = MetricsTable::new(&rows, false).write(format)?;
+    Ok((
+        table_headers(format, &format!("{id}-metrics.{}", format.extension())),
+        table,
+    ))
+}
+
+pub async fn export_bulk_metrics_csv(
+    state: State<AppState>,
+    query: Query<ExportBulkMetricsQuery>,
+) -> AppResult<(HeaderMap, Vec<u8>)> {
+    export_bulk_metrics(state, query, TableFormat::Csv).await
+}
+
+pub async fn export_bulk_metrics_parquet(
+    state: State<AppState>,
+    query: Query<ExportBulkMetricsQuery>,
+) -> AppResult<(HeaderMap, Vec<u8>)> {
+    export_bulk_metrics(state, query, TableFormat::Parquet).await
+}
+
+/// Exports the metrics of several recordings as one table, with a leading `recording_id`
+/// column.
+async fn export_bulk_metrics(
+    State(state): State<AppState>,
+    Query(query): Query<ExportBulkMetricsQuery>,
+    format: TableFormat,
+) -> AppResult<(HeaderMap, Vec<u8>)> {
+    let mut ids = Vec::new();
+    for id in query
+        .ids
+        .split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+    {
+        let id = id
+            .parse::<uuid::Uuid>()
+            .map_err(|_| AppError::bad_request(format!("invalid recording id '{id}'")))?;
+        if !ids.contains(&id) {
+            ids.push(id);
+        }
+    }
+    if ids.is_empty() {
+        return Err(AppError::bad_request("no recording ids given"));
+    }
+    if ids.len() > MAX_BULK_RECORDINGS {
+        return Err(AppError::bad_request(format!(
+            "at most {MAX_BULK_RECORDINGS} recordings can be exported at once"
+        )));
+    }
+
+    let found = sqlx::query_scalar!("SELECT id FROM recordings WHERE id = ANY($1)", &ids)
+        .fetch_all(&state.db)
+        .await?;
+    if let Some(missing) = ids.iter().find(|id| !found.contains(id)) {
+        return Err(AppError::not_found(format!(
+            "recording {missing} not found"
+        )));
+    }
+
+    let rows = fetch_segment_rows(&state, &ids, None).await?;
+    let table = MetricsTable::new(&rows, true).write(format)?;
+    Ok((
+        table_headers(format, &format!("metrics.{}", format.extension())),
+        table,
+    ))
+}
+
+/// Segments of the given run, or of each recording's latest run, in upload order.
+async fn fetch_segment_rows(
+    state: &AppState,
+    ids: &[uuid::Uuid],
+    run: Option<uuid::Uuid>,
+) -> AppResult<Vec<SegmentRow>> {
+    let rows = sqlx::query!(
+        r#"SELECT recordings.id AS recording_id, channels.idx_in_file, segments.id AS segment_id,
+        segments.start_sec, segments.end_sec, segments.content,
+        segments.metrics_list AS "metrics: SJson<Vec<MetricCollection>>"
+        FROM recordings
+        JOIN channels ON channels.run_id = COALESCE($2, recordings.latest_run)
+        JOIN segments ON segments.channel = channels.id
+        WHERE recordings.id = ANY($1)
+        ORDER BY recordings.uploaded_at, recordings.id, channels.idx_in_file, segments.start_sec"#,
+        ids,
+        run
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| SegmentRow {
+            recording_id: row.recording_id,
+            channel: row.idx_in_file,
+            segment_id: row.segment_id,
+            start: round_millis(row.start_sec),
+            end: round_millis(row.end_sec),
+            text: row.content,
+            metrics: row.metrics.0,
+        })
+        .collect())
+}
+
+/// Segment times are stored as REAL; rounding to milliseconds drops the float noise.
+fn round_millis(value: f32) -> f64 {
+    (value as f64 * 1000.0).round() / 1000.0
+}
+
+fn table_headers(format: TableFormat, filename: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(format.content_type()),
+    );
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        format!("attachment; filename=\"{filename}\"")
+            .try_into()
+            .unwrap(),
+    );
+    headers
+}
@@ -0,0 +1,261 @@
+use axum::{Json, extract::State};
+use chrono::{DateTime, Utc};
+use sqlx::types::Json as SJson;
+
+use crate::{
+    AppState,
+    extract::{Path, Query},
+    message_queue::types::MetricCollection,
+    metric_catalog::MetricLevel,
+    metric_stats::{DEFAULT_PERCENTILES, ProviderSummary, Summarizer, Weighting},
+    result::{AppError, AppResult},
+    url::UrlGenerator,
+};
+
+#[derive(Debug, serde::Deserialize)]
+pub struct CatalogQuery {
+    provider: Option<String>,
+    level: Option<MetricLevel>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct CatalogMetric {
+    pub name: String,
+    pub level: String,
+    /// One of the `Metric` type tags: `int`, `float`, `str` or `bool`.
+    #[serde(rename = "type")]
+    pub value_type: String,
+    pub unit: Option<String>,
+    pub description: Option<String>,
+    pub value_count: i64,
+    pub null_count: i64,
+    /// Observed range; only set for `int` and `float` metrics.
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub first_seen_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct CatalogProvider {
+    pub provider: String,
+    pub description: Option<String>,
+    pub metrics: Vec<CatalogMetric>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct CatalogResponse {
+    pub providers: Vec<CatalogProvider>,
+}
+
+/// Lists every metric the analysis service has reported, grouped by provider.
+pub async fn get_catalog(
+    State(state): State<AppState>,
+    Query(query): Query<CatalogQuery>,
+) -> AppResult<Json<CatalogResponse>> {
+    let rows = sqlx::query!(
+        "SELECT provider, name, level, value_type, unit, description, provider_description,
+        value_count, null_count, min_value, max_value, first_seen_at, last_seen_at
+        FROM metric_catalog
+        WHERE ($1::TEXT IS NULL OR provider = $1)
+        AND ($2::TEXT IS NULL OR level = $2)
+        ORDER BY provider, name,
+        array_position(ARRAY['recording', 'channel', 'segment'], level), value_type",
+        query.provider,
+        query.level.map(MetricLevel::as_str)
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    let mut providers: Vec<CatalogProvider> = vec![];
+    for row in rows {
+        let metric = CatalogMetric {
+            name: row.name,
+            level: row.level,
+            value_type: row.value_type,
+            unit: row.unit,
+            description: row.description,
+            value_count: row.value_count,
+            null_count: row.null_count,
+            min: row.min_value,
+            max: row.max_value,
+            first_seen_at: row.first_seen_at,
+            last_seen_at: row.last_seen_at,
+        };
+        match providers.last_mut() {
+            Some(provider) if provider.provider == row.provider => {
+                if provider.description.is_none() {
+                    provider.description = row.provider_description;
+                }
+                provider.metrics.push(metric);
+            }
+            _ => providers.push(CatalogProvider {
+                provider: row.provider,
+                description: row.provider_description,
+                metrics: vec![metric],
+            }),
+        }
+    }
+
+    Ok(Json(CatalogResponse { providers }))
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct SummaryQuery {
+    #[serde(default)]
+    weight: Weighting,
+    /// Comma-separated percentiles between 0 and 100; defaults to
+    /// [`DEFAULT_PERCENTILES`].
+    percentiles: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct RecordingSummaryQuery {
+    #[serde(flatten)]
+    summary: SummaryQuery,
+    /// Analysis run to summarize; defaults to the latest one.
+    run: Option<uuid::Uuid>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct ChannelSummaryResponse {
+    pub channel_url: String,
+    pub weight: Weighting,
+    pub segment_count: usize,
+    pub providers: Vec<ProviderSummary>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct RecordingSummaryResponse {
+    pub recording_url: String,
+    pub run_id: Option<uuid::Uuid>,
+    pub weight: Weighting,
+    pub segment_count: usize,
+    pub providers: Vec<ProviderSummary>,
+}
+
+struct SummarySegment {
+    start_sec: f32,
+    end_sec: f32,
+    metrics: SJson<Vec<MetricCollection>>,
+}
+
+/// Summarizes the segment metrics of a channel.
+pub async fn get_channel_summary(
+    State(state): State<AppState>,
+    Path(id): Path<uuid::Uuid>,
+    Query(query): Query<SummaryQuery>,
+    url: UrlGenerator,
+) -> AppResult<Json<ChannelSummaryResponse>> {
+    let percentiles = parse_percentiles(query.percentiles.as_deref())?;
+    let exists = sqlx::query_scalar!(
+        "SELECT EXISTS(SELECT 1 FROM channels WHERE id=$1) AS \"exists!\"",
+        id
+    )
+    .fetch_one(&state.db)
+    .await?;
+    if !exists {
+        return Err(AppError::not_found("channel not found"));
+    }
+
+    let segments = sqlx::query_as!(
+        SummarySegment,
+        r#"SELECT start_sec, end_sec, metrics_list as "metrics: SJson<Vec<MetricCollection>>"
+        FROM segments WHERE channel=$1"#,
+        id
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(Json(ChannelSummaryResponse {
+        channel_url: url.url(format!("/channels/{id}")),
+        weight: query.weight,
+        segment_count: segments.len(),
+        providers: summarize(&segments, query.weight, percentiles),
+    }))
+}
+
+/// Summarizes the segment metrics of all channels of a recording's analysis run.
+pub async fn get_recording_summary(
+    State(state): State<AppState>,
+    Path(id): Path<uuid::Uuid>,
+    Query(query): Query<RecordingSummaryQuery>,
+    url: UrlGenerator,
+) -> AppResult<Json<RecordingSummaryResponse>> {
+    let percentiles = parse_percentiles(query.summary.percentiles.as_deref())?;
+    let latest_run = sqlx::query_scalar!("SELECT latest_run FROM recordings WHERE id=$1", id)
+        .fetch_optional(&state.db)
+        .await?
+        .ok_or_else(|| AppError::not_found("recording not found"))?;
+    let run_id = match query.run {
+        Some(run_id) => {
+            let exists = sqlx::query_scalar!(
+                "SELECT EXISTS(SELECT 1 FROM analysis_runs WHERE id=$1 AND recording_id=$2) AS \"exists!\"",
+                run_id,
+                id
+            )
+            .fetch_one(&state.db)
+            .await?;
+            if !exists {
+                return Err(AppError::not_found("analysis run not found"));
+            }
+            Some(run_id)
+        }
+        None => latest_run,
+    };
+
+    let segments = sqlx::query_as!(
+        SummarySegment,
+        r#"SELECT segments.start_sec, segments.end_sec,
+        segments.metrics_list as "metrics: SJson<Vec<MetricCollection>>"
+        FROM channels JOIN segments ON segments.channel = channels.id
+        WHERE channels.run_id = $1"#,
+        run_id
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(Json(RecordingSummaryResponse {
+        recording_url: url.url(format!("/recordings/{id}")),
+        run_id,
+        weight: query.summary.weight,
+        segment_count: segments.len(),
+        providers: summarize(&segments, query.summary.weight, percentiles),
+    }))
+}
+
+fn parse_percentiles(percentiles: Option<&str>) -> AppResult<Vec<f64>> {
+    let Some(percentiles) = percentiles else {
+        return Ok(DEFAULT_PERCENTILES.to_vec());
+    };
+    percentiles
+        .split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            p.parse::<f64>()
+                .ok()
+                .filter(|p| (0.0..=100.0).contains(p))
+                .ok_or_else(|| {
+                    AppError::bad_request(format!(
+                        "invalid percentile '{p}', must be between 0 and 100"
+                    ))
+                })
+        })
+        .collect()
+}
+
+fn summarize(
+    segments: &[SummarySegment],
+    weight: Weighting,
+    percentiles: Vec<f64>,
+) -> Vec<ProviderSummary> {
+    let mut summarizer = Summarizer::new(weight, percentiles);
+    for segment in segments {
+        summarizer.add(
+            (segment.end_sec - segment.start_sec) as f64,
+            &segment.metrics.0,
+        );
+    }
+    summarizer.finish()
+}
@@ -1,4 +1,15 @@
+pub mod admin;
+pub mod analysis;
 pub mod channel;
+pub mod direct_upload;
+pub mod events;
+pub mod export;
+pub mod metrics;
 pub mod recording;
+pub mod run;
+pub mod search;
 pub mod segment;
+pub mod transcript;
+pub mod tus;
 pub mod upload;
+pub mod webhook;
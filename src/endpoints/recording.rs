@@ -1,94 +1,339 @@
-use std::collections::HashMap;
+use std::time::Duration;
 
-use axum::{
-    Json,
-    extract::{Path, State},
-};
+use axum::{Json, extract::State};
+use chrono::{DateTime, Utc};
+use sqlx::{Postgres, QueryBuilder};
 
 use crate::{
-    AppState, message_queue::types::MetricCollection, result::AppResult, url::UrlGenerator,
+    AppState,
+    analysis_submit::{cancel_run, wake_relay},
+    blob_cleanup::{queue_deletion, wake_cleanup},
+    cursor,
+    extract::{Path, Query},
+    media::MediaInfo,
+    message_queue::types::MetricCollection,
+    result::{AppError, AppResult},
+    url::UrlGenerator,
 };
 
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordingSort {
+    #[default]
+    UploadedAt,
+    Status,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct ListRecordingsQuery {
+    /// Comma-separated list of analysis statuses to include.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uploaded_after: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uploaded_before: Option<DateTime<Utc>>,
+    /// Case-insensitive substring of the original filename.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    filename: Option<String>,
+    #[serde(default)]
+    sort: RecordingSort,
+    #[serde(default)]
+    order: SortOrder,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cursor: Option<String>,
+}
+
+/// Position of the last item of a page; the next page starts strictly after it.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct RecordingCursor {
+    sort: RecordingSort,
+    order: SortOrder,
+    status: String,
+    uploaded_at: DateTime<Utc>,
+    id: uuid::Uuid,
+}
+
+#[derive(sqlx::FromRow)]
+struct RecordingSummaryRow {
+    id: uuid::Uuid,
+    uploaded_at: DateTime<Utc>,
+    original_filename: String,
+    analysis_status: String,
+    analysis_percent: i32,
+    channel_count: i64,
+    duration_sec: Option<f32>,
+}
+
 #[derive(serde::Serialize)]
-pub struct RecordingRow {
+pub struct RecordingSummary {
     url: String,
     id: uuid::Uuid,
+    original_filename: String,
+    uploaded_at: DateTime<Utc>,
+    analysis_status: String,
+    analysis_percent_done: f32,
+    channel_count: i64,
+    duration_sec: Option<f32>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ListRecordingsResponse {
+    items: Vec<RecordingSummary>,
+    next_url: Option<String>,
 }
 
 pub async fn list_recordings(
     url: UrlGenerator,
     State(state): State<AppState>,
-) -> AppResult<Json<Vec<RecordingRow>>> {
-    let rows = sqlx::query!("SELECT id FROM recordings")
-        .fetch_all(&state.db)
-        .await?;
+    Query(query): Query<ListRecordingsQuery>,
+) -> AppResult<Json<ListRecordingsResponse>> {
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(AppError::bad_request(format!(
+            "limit must be between 1 and {MAX_PAGE_SIZE}"
+        )));
+    }
 
-    Ok(Json(
-        rows.into_iter()
-            .map(|row| RecordingRow {
-                id: row.id,
+    let mut builder = QueryBuilder::<Postgres>::new(
+        "SELECT r.id, r.uploaded_at, r.original_filename,
+        r.latest_status AS analysis_status,
+        COALESCE(lr.percent, 0) AS analysis_percent,
+        (SELECT COUNT(*) FROM channels c WHERE c.run_id = r.latest_run) AS channel_count,
+        COALESCE(r.media_duration_sec::REAL,
+            (SELECT MAX(s.end_sec) FROM segments s JOIN channels c ON s.channel = c.id WHERE c.run_id = r.latest_run)) AS duration_sec
+        FROM recordings r
+        LEFT JOIN analysis_runs lr ON lr.id = r.latest_run
+        WHERE TRUE",
+    );
+
+    if let Some(status) = &query.status {
+        let statuses: Vec<String> = status.split(',').map(|s| s.trim().to_owned()).collect();
+        builder.push(" AND r.latest_status = ANY(");
+        builder.push_bind(statuses);
+        builder.push(")");
+    }
+    if let Some(after) = query.uploaded_after {
+        builder.push(" AND r.uploaded_at >= ");
+        builder.push_bind(after);
+    }
+    if let Some(before) = query.uploaded_before {
+        builder.push(" AND r.uploaded_at < ");
+        builder.push_bind(before);
+    }
+    if let Some(filename) = &query.filename {
+        builder.push(" AND r.original_filename ILIKE ");
+        builder.push_bind(format!("%{}%", escape_like(filename)));
+    }
+
+    let comparison = match query.order {
+        SortOrder::Asc => ">",
+        SortOrder::Desc => "<",
+    };
+    if let Some(cursor) = &query.cursor {
+        let cursor: RecordingCursor = cursor::decode(cursor)?;
+        if cursor.sort != query.sort || cursor.order != query.order {
+            return Err(AppError::bad_request(
+                "cursor was issued for a different sort order",
+            ));
+        }
+        match query.sort {
+            RecordingSort::UploadedAt => {
+                builder.push(format_args!(" AND (r.uploaded_at, r.id) {comparison} ("));
+            }
+            RecordingSort::Status => {
+                builder.push(format_args!(
+                    " AND (r.latest_status, r.uploaded_at, r.id) {comparison} ("
+                ));
+                builder.push_bind(cursor.status);
+                builder.push(", ");
+            }
+        }
+        builder.push_bind(cursor.uploaded_at);
+        builder.push(", ");
+        builder.push_bind(cursor.id);
+        builder.push(")");
+    }
+
+    let direction = match query.order {
+        SortOrder::Asc => "ASC",
+        SortOrder::Desc => "DESC",
+    };
+    match query.sort {
+        RecordingSort::UploadedAt => {
+            builder.push(format_args!(
+                " ORDER BY r.uploaded_at {direction}, r.id {direction}"
+            ));
+        }
+        RecordingSort::Status => {
+            builder.push(format_args!(
+                " ORDER BY r.latest_status {direction}, r.uploaded_at {direction}, r.id {direction}"
+            ));
+        }
+    }
+    builder.push(" LIMIT ");
+    builder.push_bind(limit + 1);
+
+    let mut rows: Vec<RecordingSummaryRow> = builder.build_query_as().fetch_all(&state.db).await?;
+
+    let mut next_url = None;
+    if rows.len() as i64 > limit {
+        rows.truncate(limit as usize);
+        let last = rows.last().expect("page should not be empty");
+        let next_query = ListRecordingsQuery {
+            cursor: Some(cursor::encode(&RecordingCursor {
+                sort: query.sort,
+                order: query.order,
+                status: last.analysis_status.clone(),
+                uploaded_at: last.uploaded_at,
+                id: last.id,
+            })),
+            ..query
+        };
+        next_url = Some(url.url_with_query("/recordings", &next_query));
+    }
+
+    Ok(Json(ListRecordingsResponse {
+        items: rows
+            .into_iter()
+            .map(|row| RecordingSummary {
                 url: url.url(format!("/recordings/{}", row.id)),
+                id: row.id,
+                original_filename: row.original_filename,
+                uploaded_at: row.uploaded_at,
+                analysis_status: row.analysis_status,
+                analysis_percent_done: row.analysis_percent as f32,
+                channel_count: row.channel_count,
+                duration_sec: row.duration_sec,
             })
             .collect(),
-    ))
+        next_url,
+    }))
+}
+
+/// Escapes `LIKE` wildcards so user input is matched literally.
+fn escape_like(input: &str) -> String {
+    input
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct GetRecordingQuery {
+    /// Analysis run to show; defaults to the latest one.
+    run: Option<uuid::Uuid>,
 }
 
 pub async fn get_recording(
     State(state): State<AppState>,
     Path(id): Path<uuid::Uuid>,
+    Query(query): Query<GetRecordingQuery>,
     url: UrlGenerator,
 ) -> AppResult<Json<RecordingData>> {
-    let row = sqlx::query!(
-        "SELECT recordings.*,
-    recording_stats.metrics_list AS \"metrics: Option<sqlx::types::Json<Vec<MetricCollection>>>\"
-    FROM recordings
-    LEFT JOIN recording_stats ON recordings.id = recording_stats.recording_id
-    WHERE id=$1",
-        id
-    )
-    .fetch_optional(&state.db)
-    .await?;
+    let row = sqlx::query!("SELECT * FROM recordings WHERE id=$1", id)
+        .fetch_optional(&state.db)
+        .await?;
 
     let Some(row) = row else {
-        return Err(eyre::eyre!("recording not found").into());
+        return Err(AppError::not_found("recording not found"));
+    };
+
+    let run_id = query.run.or(row.latest_run);
+    let run = match run_id {
+        Some(run_id) => {
+            let run = sqlx::query!(
+                r#"SELECT analysis_runs.*,
+                recording_stats.metrics_list AS "metrics: Option<sqlx::types::Json<Vec<MetricCollection>>>"
+                FROM analysis_runs
+                LEFT JOIN recording_stats ON recording_stats.run_id = analysis_runs.id
+                WHERE analysis_runs.id=$1 AND analysis_runs.recording_id=$2"#,
+                run_id,
+                id
+            )
+            .fetch_optional(&state.db)
+            .await?;
+            match run {
+                Some(run) => Some(run),
+                None => return Err(AppError::not_found("analysis run not found")),
+            }
+        }
+        None => None,
     };
 
-    let mut custom_queries = HashMap::new();
-    custom_queries.insert(
-        "response-content-disposition".into(),
-        format!("attachment; filename=\"{}\"", row.original_filename),
-    );
     let download_url = state
-        .s3
+        .storage
         .presign_get(
-            format!("original_upload/{}", row.id),
-            3600,
-            Some(custom_queries),
+            &row.original_s3_path,
+            Duration::from_secs(3600),
+            Some(&row.original_filename),
         )
-        .await?;
+        .await
+        .map_err(AppError::upstream)?;
 
-    let channels = sqlx::query!("SELECT id FROM channels WHERE recording=$1", id)
-        .fetch_all(&state.db)
-        .await?;
+    let channels = sqlx::query!(
+        "SELECT id FROM channels WHERE run_id=$1 ORDER BY idx_in_file",
+        run_id
+    )
+    .fetch_all(&state.db)
+    .await?;
     let channel_urls = channels
         .iter()
         .map(|c| url.url(format!("/channels/{}", c.id)))
         .collect();
 
-    Ok(axum::Json(RecordingData {
+    let mut data = RecordingData {
         self_url: url.url(format!("/recordings/{}", id)),
         id: row.id,
         uploaded_at: row.uploaded_at,
         download_url,
         channels: channel_urls,
-        metrics: row.metrics.map(|m| m.0),
-        analysis_status: row.analysis_status,
-        analysis_percent_done: row.analysis_percent as f32,
-        analysis_error_message: row.analysis_error,
-        analysis_channel: row.analysis_channel,
-        analysis_description: row.analysis_description,
-        analysis_updated_at: row.analysis_last_update.unwrap_or(row.uploaded_at),
-    }))
+        runs_url: url.url(format!("/recordings/{}/runs", id)),
+        run_url: None,
+        metrics: None,
+        analysis_status: "pending".into(),
+        analysis_percent_done: 0.0,
+        analysis_channel: None,
+        analysis_description: None,
+        analysis_error_message: None,
+        analysis_updated_at: row.uploaded_at,
+        media: match (row.media_container, row.media_codec) {
+            (Some(container), Some(codec)) => Some(MediaInfo {
+                container,
+                codec,
+                sample_rate: row.media_sample_rate,
+                channels: row.media_channels,
+                duration_sec: row.media_duration_sec,
+                bits_per_sample: row.media_bits_per_sample,
+            }),
+            _ => None,
+        },
+    };
+    if let Some(run) = run {
+        data.run_url = Some(url.url(format!("/runs/{}", run.id)));
+        data.metrics = run.metrics.map(|m| m.0);
+        data.analysis_status = run.status;
+        data.analysis_percent_done = run.percent as f32;
+        data.analysis_channel = run.channel;
+        data.analysis_description = run.description;
+        data.analysis_error_message = run.error;
+        data.analysis_updated_at = run.last_update.unwrap_or(run.created_at);
+    }
+
+    Ok(axum::Json(data))
 }
 
 #[derive(serde::Serialize)]
@@ -98,6 +343,8 @@ pub struct RecordingData {
     uploaded_at: chrono::DateTime<chrono::Utc>,
     download_url: String,
     channels: Vec<String>,
+    runs_url: String,
+    run_url: Option<String>,
     metrics: Option<Vec<MetricCollection>>,
     analysis_status: String,
     analysis_percent_done: f32,
@@ -105,14 +352,61 @@ pub struct RecordingData {
     analysis_description: Option<String>,
     analysis_error_message: Option<String>,
     analysis_updated_at: chrono::DateTime<chrono::Utc>,
+    /// Probed at upload; missing for recordings uploaded before probing was added.
+    media: Option<MediaInfo>,
 }
 
+/// Deletes the recording with all its runs and queues its blobs for removal.
+///
+/// Analysis that is still in progress is cancelled in the same transaction.
 pub async fn delete_recording(
     State(state): State<AppState>,
     Path(id): Path<uuid::Uuid>,
 ) -> AppResult<axum::http::StatusCode> {
+    let mut tx = state.db.begin().await?;
+    let Some(recording) = sqlx::query!(
+        "SELECT original_s3_path, original_transcript_s3_path FROM recordings WHERE id=$1 FOR UPDATE",
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    else {
+        return Err(AppError::not_found("recording not found"));
+    };
+
+    let in_progress = sqlx::query_scalar!(
+        "SELECT id FROM analysis_runs WHERE recording_id=$1 AND status IN ('pending', 'running')",
+        id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    for run_id in &in_progress {
+        cancel_run(&mut tx, *run_id).await?;
+    }
+
+    let run_transcripts = sqlx::query_scalar!(
+        r#"SELECT DISTINCT transcript_s3_path AS "path!" FROM analysis_runs
+        WHERE recording_id=$1 AND transcript_s3_path IS NOT NULL"#,
+        id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    let keys = std::iter::once(recording.original_s3_path)
+        .chain(recording.original_transcript_s3_path)
+        .chain(run_transcripts);
+    for key in keys {
+        queue_deletion(&mut tx, &key).await?;
+    }
+
     sqlx::query!("DELETE FROM recordings WHERE id=$1", id)
-        .execute(&state.db)
+        .execute(&mut *tx)
         .await?;
+    tx.commit().await?;
+
+    if !in_progress.is_empty() {
+        wake_relay(&state);
+    }
+    wake_cleanup(&state);
+
     Ok(axum::http::StatusCode::NO_CONTENT)
 }
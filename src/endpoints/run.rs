@@ -0,0 +1,242 @@
+use axum::{Json, extract::State};
+use chrono::{DateTime, Utc};
+
+use crate::{
+    AppState,
+    extract::{Path, Query},
+    message_queue::types::{Metric, MetricCollection},
+    result::{AppError, AppResult},
+    url::UrlGenerator,
+};
+
+#[derive(serde::Serialize)]
+pub struct RunSummary {
+    url: String,
+    id: uuid::Uuid,
+    recording_url: String,
+    is_latest: bool,
+    pipeline_version: Option<String>,
+    force_diarize: Option<bool>,
+    with_transcript: bool,
+    created_at: DateTime<Utc>,
+    started_at: Option<DateTime<Utc>>,
+    finished_at: Option<DateTime<Utc>>,
+    status: String,
+    percent_done: f32,
+    error_message: Option<String>,
+}
+
+pub async fn list_runs(
+    State(state): State<AppState>,
+    Path(recording_id): Path<uuid::Uuid>,
+    url: UrlGenerator,
+) -> AppResult<Json<Vec<RunSummary>>> {
+    let Some(recording) = sqlx::query!(
+        "SELECT latest_run FROM recordings WHERE id=$1",
+        recording_id
+    )
+    .fetch_optional(&state.db)
+    .await?
+    else {
+        return Err(AppError::not_found("recording not found"));
+    };
+
+    let runs = sqlx::query!(
+        "SELECT * FROM analysis_runs WHERE recording_id=$1 ORDER BY created_at DESC",
+        recording_id
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(Json(
+        runs.into_iter()
+            .map(|run| RunSummary {
+                url: url.url(format!("/runs/{}", run.id)),
+                id: run.id,
+                recording_url: url.url(format!("/recordings/{}", run.recording_id)),
+                is_latest: recording.latest_run == Some(run.id),
+                pipeline_version: run.pipeline_version,
+                force_diarize: run.force_diarize,
+                with_transcript: run.transcript_s3_path.is_some(),
+                created_at: run.created_at,
+                started_at: run.started_at,
+                finished_at: run.finished_at,
+                status: run.status,
+                percent_done: run.percent as f32,
+                error_message: run.error,
+            })
+            .collect(),
+    ))
+}
+
+#[derive(serde::Serialize)]
+pub struct RunData {
+    #[serde(flatten)]
+    summary: RunSummary,
+    description: Option<String>,
+    channel: Option<i32>,
+    channels: Vec<String>,
+    metrics: Option<Vec<MetricCollection>>,
+}
+
+pub async fn get_run(
+    State(state): State<AppState>,
+    Path(id): Path<uuid::Uuid>,
+    url: UrlGenerator,
+) -> AppResult<Json<RunData>> {
+    let Some(run) = sqlx::query!(
+        r#"SELECT analysis_runs.*,
+        recordings.latest_run,
+        recording_stats.metrics_list AS "metrics: Option<sqlx::types::Json<Vec<MetricCollection>>>"
+        FROM analysis_runs
+        JOIN recordings ON recordings.id = analysis_runs.recording_id
+        LEFT JOIN recording_stats ON recording_stats.run_id = analysis_runs.id
+        WHERE analysis_runs.id=$1"#,
+        id
+    )
+    .fetch_optional(&state.db)
+    .await?
+    else {
+        return Err(AppError::not_found("analysis run not found"));
+    };
+
+    let channels = sqlx::query!(
+        "SELECT id FROM channels WHERE run_id=$1 ORDER BY idx_in_file",
+        id
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(Json(RunData {
+        summary: RunSummary {
+            url: url.url(format!("/runs/{}", run.id)),
+            id: run.id,
+            recording_url: url.url(format!("/recordings/{}", run.recording_id)),
+            is_latest: run.latest_run == Some(run.id),
+            pipeline_version: run.pipeline_version,
+            force_diarize: run.force_diarize,
+            with_transcript: run.transcript_s3_path.is_some(),
+            created_at: run.created_at,
+            started_at: run.started_at,
+            finished_at: run.finished_at,
+            status: run.status,
+            percent_done: run.percent as f32,
+            error_message: run.error,
+        },
+        description: run.description,
+        channel: run.channel,
+        channels: channels
+            .iter()
+            .map(|c| url.url(format!("/channels/{}", c.id)))
+            .collect(),
+        metrics: run.metrics.map(|m| m.0),
+    }))
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct CompareRunsQuery {
+    base: uuid::Uuid,
+    other: uuid::Uuid,
+}
+
+#[derive(serde::Serialize)]
+pub struct MetricComparison {
+    provider: String,
+    name: String,
+    base: Option<Metric>,
+    other: Option<Metric>,
+    /// `other - base`, for numeric metrics present in both runs.
+    delta: Option<f64>,
+}
+
+#[derive(serde::Serialize)]
+pub struct CompareRunsResponse {
+    base_url: String,
+    other_url: String,
+    base_channel_count: i64,
+    other_channel_count: i64,
+    metrics: Vec<MetricComparison>,
+}
+
+/// Compares the recording-level metrics of two analysis runs.
+pub async fn compare_runs(
+    State(state): State<AppState>,
+    Query(query): Query<CompareRunsQuery>,
+    url: UrlGenerator,
+) -> AppResult<Json<CompareRunsResponse>> {
+    let base = fetch_run_metrics(&state, query.base).await?;
+    let other = fetch_run_metrics(&state, query.other).await?;
+
+    let mut metrics: Vec<MetricComparison> = Vec::new();
+    for collection in &base.metrics {
+        for metric in &collection.metrics {
+            metrics.push(MetricComparison {
+                provider: collection.provider.clone(),
+                name: metric.name().to_owned(),
+                base: Some(metric.clone()),
+                other: None,
+                delta: None,
+            });
+        }
+    }
+    for collection in &other.metrics {
+        for metric in &collection.metrics {
+            let existing = metrics.iter_mut().find(|m| {
+                m.provider == collection.provider && m.name == metric.name() && m.other.is_none()
+            });
+            match existing {
+                Some(comparison) => {
+                    comparison.delta = comparison
+                        .base
+                        .as_ref()
+                        .and_then(Metric::as_f64)
+                        .zip(metric.as_f64())
+                        .map(|(base, other)| other - base);
+                    comparison.other = Some(metric.clone());
+                }
+                None => metrics.push(MetricComparison {
+                    provider: collection.provider.clone(),
+                    name: metric.name().to_owned(),
+                    base: None,
+                    other: Some(metric.clone()),
+                    delta: None,
+                }),
+            }
+        }
+    }
+
+    Ok(Json(CompareRunsResponse {
+        base_url: url.url(format!("/runs/{}", query.base)),
+        other_url: url.url(format!("/runs/{}", query.other)),
+        base_channel_count: base.channel_count,
+        other_channel_count: other.channel_count,
+        metrics,
+    }))
+}
+
+struct RunMetrics {
+    channel_count: i64,
+    metrics: Vec<MetricCollection>,
+}
+
+async fn fetch_run_metrics(state: &AppState, run_id: uuid::Uuid) -> AppResult<RunMetrics> {
+    let Some(row) = sqlx::query!(
+        r#"SELECT
+        (SELECT COUNT(*) FROM channels WHERE channels.run_id = analysis_runs.id) AS "channel_count!",
+        recording_stats.metrics_list AS "metrics: Option<sqlx::types::Json<Vec<MetricCollection>>>"
+        FROM analysis_runs
+        LEFT JOIN recording_stats ON recording_stats.run_id = analysis_runs.id
+        WHERE analysis_runs.id=$1"#,
+        run_id
+    )
+    .fetch_optional(&state.db)
+    .await?
+    else {
+        return Err(AppError::not_found(format!("analysis run {run_id} not found")));
+    };
+
+    Ok(RunMetrics {
+        channel_count: row.channel_count,
+        metrics: row.metrics.map(|m| m.0).unwrap_or_default(),
+    })
+}
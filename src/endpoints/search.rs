@@ -0,0 +1,197 @@
+use axum::{Json, extract::State};
+use chrono::{DateTime, Utc};
+use sqlx::{Postgres, QueryBuilder};
+
+use crate::{
+    AppState, cursor,
+    extract::Query,
+    result::{AppError, AppResult},
+    url::UrlGenerator,
+};
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 200;
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct SearchQuery {
+    /// Words to find, in `websearch_to_tsquery` syntax: `"quoted phrases"`, `or` and
+    /// `-excluded` words are supported.
+    q: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recording: Option<uuid::Uuid>,
+    /// Assigned name of the channel, compared case-insensitively.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    speaker: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uploaded_after: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uploaded_before: Option<DateTime<Utc>>,
+    /// Comma-separated list of analysis statuses to include.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cursor: Option<String>,
+}
+
+/// Position of the last hit of a page; hits are ordered by descending rank, then id.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SearchCursor {
+    rank: f32,
+    id: uuid::Uuid,
+}
+
+#[derive(sqlx::FromRow)]
+struct SearchRow {
+    recording_id: uuid::Uuid,
+    original_filename: String,
+    channel_id: uuid::Uuid,
+    idx_in_file: i32,
+    assigned_name: Option<String>,
+    segment_id: uuid::Uuid,
+    start_sec: f32,
+    end_sec: f32,
+    rank: f32,
+    snippet: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct SearchHit {
+    recording_url: String,
+    recording_id: uuid::Uuid,
+    original_filename: String,
+    channel_id: uuid::Uuid,
+    channel_index: i32,
+    speaker: Option<String>,
+    segment_id: uuid::Uuid,
+    start: f32,
+    end: f32,
+    rank: f32,
+    /// HTML-escaped excerpt of the segment with matches wrapped in `<mark>` tags.
+    snippet: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct SearchResponse {
+    items: Vec<SearchHit>,
+    next_url: Option<String>,
+}
+
+/// Searches the transcripts of the latest analysis run of every recording.
+pub async fn search_segments(
+    url: UrlGenerator,
+    State(state): State<AppState>,
+    Query(query): Query<SearchQuery>,
+) -> AppResult<Json<SearchResponse>> {
+    if query.q.trim().is_empty() {
+        return Err(AppError::bad_request("q must not be empty"));
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(AppError::bad_request(format!(
+            "limit must be between 1 and {MAX_PAGE_SIZE}"
+        )));
+    }
+
+    // snippets are only built for the hits of the page, as ts_headline is slow
+    let mut builder = QueryBuilder::<Postgres>::new(
+        "WITH search AS (
+            SELECT text_search_config AS config, websearch_to_tsquery(text_search_config, ",
+    );
+    builder.push_bind(&query.q);
+    builder.push(
+        ") AS query FROM search_settings
+        ), hits AS (
+            SELECT r.id AS recording_id, r.original_filename,
+            c.id AS channel_id, c.idx_in_file, c.assigned_name,
+            s.id AS segment_id, s.start_sec, s.end_sec, s.content,
+            ts_rank(s.content_tsv, search.query) AS rank
+            FROM search
+            JOIN segments s ON s.content_tsv @@ search.query
+            JOIN channels c ON c.id = s.channel
+            JOIN recordings r ON r.id = c.recording AND r.latest_run = c.run_id
+            WHERE TRUE",
+    );
+
+    if let Some(recording) = query.recording {
+        builder.push(" AND r.id = ");
+        builder.push_bind(recording);
+    }
+    if let Some(speaker) = &query.speaker {
+        builder.push(" AND lower(c.assigned_name) = lower(");
+        builder.push_bind(speaker);
+        builder.push(")");
+    }
+    if let Some(after) = query.uploaded_after {
+        builder.push(" AND r.uploaded_at >= ");
+        builder.push_bind(after);
+    }
+    if let Some(before) = query.uploaded_before {
+        builder.push(" AND r.uploaded_at < ");
+        builder.push_bind(before);
+    }
+    if let Some(status) = &query.status {
+        let statuses: Vec<String> = status.split(',').map(|s| s.trim().to_owned()).collect();
+        builder.push(" AND r.latest_status = ANY(");
+        builder.push_bind(statuses);
+        builder.push(")");
+    }
+    if let Some(cursor) = &query.cursor {
+        let cursor: SearchCursor = cursor::decode(cursor)?;
+        builder.push(" AND (ts_rank(s.content_tsv, search.query), s.id) < (");
+        builder.push_bind(cursor.rank);
+        builder.push(", ");
+        builder.push_bind(cursor.id);
+        builder.push(")");
+    }
+    builder.push(" ORDER BY rank DESC, s.id DESC LIMIT ");
+    builder.push_bind(limit + 1);
+    builder.push(
+        ")
+        SELECT hits.recording_id, hits.original_filename, hits.channel_id, hits.idx_in_file,
+        hits.assigned_name, hits.segment_id, hits.start_sec, hits.end_sec, hits.rank,
+        ts_headline(search.config,
+            replace(replace(replace(hits.content, '&', '&amp;'), '<', '&lt;'), '>', '&gt;'),
+            search.query,
+            'StartSel=<mark>, StopSel=</mark>') AS snippet
+        FROM hits, search
+        ORDER BY hits.rank DESC, hits.segment_id DESC",
+    );
+
+    let mut rows: Vec<SearchRow> = builder.build_query_as().fetch_all(&state.db).await?;
+
+    let mut next_url = None;
+    if rows.len() as i64 > limit {
+        rows.truncate(limit as usize);
+        let last = rows.last().expect("page should not be empty");
+        let next_query = SearchQuery {
+            cursor: Some(cursor::encode(&SearchCursor {
+                rank: last.rank,
+                id: last.segment_id,
+            })),
+            ..query
+        };
+        next_url = Some(url.url_with_query("/search", &next_query));
+    }
+
+    Ok(Json(SearchResponse {
+        items: rows
+            .into_iter()
+            .map(|row| SearchHit {
+                recording_url: url.url(format!("/recordings/{}", row.recording_id)),
+                recording_id: row.recording_id,
+                original_filename: row.original_filename,
+                channel_id: row.channel_id,
+                channel_index: row.idx_in_file,
+                speaker: row.assigned_name,
+                segment_id: row.segment_id,
+                start: row.start_sec,
+                end: row.end_sec,
+                rank: row.rank,
+                snippet: row.snippet,
+            })
+            .collect(),
+        next_url,
+    }))
+}
@@ -1,107 +1,211 @@
-use axum::{
-    Json,
-    extract::{Path, Query, State},
-};
+use axum::{Json, extract::State};
+use chrono::{DateTime, Utc};
+use sqlx::types::Json as SJson;
 use uuid::Uuid;
 
 use crate::{
-    AppState, message_queue::types::MetricCollection, result::AppResult, url::UrlGenerator,
+    AppState, cursor,
+    extract::{Path, Query},
+    message_queue::types::MetricCollection,
+    metric_filter::Filter,
+    result::{AppError, AppResult},
+    url::UrlGenerator,
 };
 
-#[derive(Debug, serde::Deserialize)]
+const DEFAULT_PAGE_SIZE: i64 = 100;
+const MAX_PAGE_SIZE: i64 = 1000;
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct GetSegmentsQuery {
+    /// Only segments that end at or after this time.
+    #[serde(skip_serializing_if = "Option::is_none")]
     start: Option<f32>,
+    /// Only segments that start at or before this time.
+    #[serde(skip_serializing_if = "Option::is_none")]
     end: Option<f32>,
+    /// Only segments whose metrics match this [`Filter`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    filter: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cursor: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum PageDirection {
+    After,
+    Before,
+}
+
+/// A segment position in `(start_sec, id)` order; the page holds the segments strictly
+/// after or before it.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SegmentCursor {
+    direction: PageDirection,
+    start: f32,
+    id: Uuid,
+}
+
+struct SegmentRow {
+    id: Uuid,
+    start_sec: f32,
+    end_sec: f32,
+    content: String,
+    metrics: SJson<Vec<MetricCollection>>,
 }
 
+/// Pages through the segments of a channel in order of start time.
+///
+/// `prev_url` and `next_url` carry a cursor next to the page boundary, so walking in
+/// either direction returns every segment of the range exactly once.
 pub async fn get_segments(
     State(state): State<AppState>,
     Path(channel_id): Path<uuid::Uuid>,
     Query(query): Query<GetSegmentsQuery>,
     url: UrlGenerator,
 ) -> AppResult<Json<GetSegmentsResponse>> {
-    let channel = sqlx::query!("SELECT * FROM channels WHERE id=$1", channel_id)
-        .fetch_optional(&state.db)
-        .await?;
-
-    let Some(_channel) = channel else {
-        return Err(eyre::eyre!("channel not found").into());
-    };
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(AppError::bad_request(format!(
+            "limit must be between 1 and {MAX_PAGE_SIZE}"
+        )));
+    }
+    if let (Some(start), Some(end)) = (query.start, query.end)
+        && start > end
+    {
+        return Err(AppError::bad_request("start must not be after end"));
+    }
+    let position = query
+        .cursor
+        .as_deref()
+        .map(cursor::decode::<SegmentCursor>)
+        .transpose()?;
+    let filter = query.filter.as_deref().map(compile_filter).transpose()?;
 
-    use sqlx::types::Json as SJson;
-    let segments_inside_bounds = sqlx::query!(
-        r#"
-        SELECT *,
-        metrics_list as "metrics: SJson<Vec<MetricCollection>>"
-        FROM segments
-        WHERE channel=$1
-        AND start_sec >= $2
-        AND end_sec <= $3
-        ORDER BY start_sec
-        "#,
-        channel_id,
-        query.start.unwrap_or(0.0),
-        query.end.unwrap_or(f32::MAX)
+    let exists = sqlx::query_scalar!(
+        "SELECT EXISTS(SELECT 1 FROM channels WHERE id=$1) AS \"exists!\"",
+        channel_id
     )
-    .fetch_all(&state.db)
+    .fetch_one(&state.db)
     .await?;
+    if !exists {
+        return Err(AppError::not_found("channel not found"));
+    }
 
-    let segments = segments_inside_bounds
+    let mut rows = match &position {
+        Some(SegmentCursor {
+            direction: PageDirection::Before,
+            start,
+            id,
+        }) => {
+            let mut rows = sqlx::query_as!(
+                SegmentRow,
+                r#"
+                SELECT id, start_sec, end_sec, content,
+                metrics_list as "metrics: SJson<Vec<MetricCollection>>"
+                FROM segments
+                WHERE channel=$1
+                AND ($2::REAL IS NULL OR end_sec >= $2)
+                AND ($3::REAL IS NULL OR start_sec <= $3)
+                AND (start_sec, id) < ($4, $5)
+                AND ($7::TEXT IS NULL OR metrics_list @@ $7::TEXT::JSONPATH)
+                ORDER BY start_sec DESC, id DESC
+                LIMIT $6
+                "#,
+                channel_id,
+                query.start,
+                query.end,
+                start,
+                id,
+                limit + 1,
+                filter
+            )
+            .fetch_all(&state.db)
+            .await?;
+            rows.reverse();
+            rows
+        }
+        after => {
+            sqlx::query_as!(
+                SegmentRow,
+                r#"
+                SELECT id, start_sec, end_sec, content,
+                metrics_list as "metrics: SJson<Vec<MetricCollection>>"
+                FROM segments
+                WHERE channel=$1
+                AND ($2::REAL IS NULL OR end_sec >= $2)
+                AND ($3::REAL IS NULL OR start_sec <= $3)
+                AND ($4::REAL IS NULL OR (start_sec, id) > ($4, $5::UUID))
+                AND ($7::TEXT IS NULL OR metrics_list @@ $7::TEXT::JSONPATH)
+                ORDER BY start_sec, id
+                LIMIT $6
+                "#,
+                channel_id,
+                query.start,
+                query.end,
+                after.as_ref().map(|position| position.start),
+                after.as_ref().map(|position| position.id),
+                limit + 1,
+                filter
+            )
+            .fetch_all(&state.db)
+            .await?
+        }
+    };
+
+    // the extra row tells whether there is more in the paging direction; the other
+    // direction is only known to be non-empty when a cursor led here
+    let backward = position
+        .as_ref()
+        .is_some_and(|position| position.direction == PageDirection::Before);
+    let has_more = rows.len() as i64 > limit;
+    if has_more {
+        if backward {
+            rows.drain(..rows.len() - limit as usize);
+        } else {
+            rows.truncate(limit as usize);
+        }
+    }
+    let (has_prev, has_next) = match (&position, backward) {
+        (_, true) => (has_more, true),
+        (Some(_), false) => (true, has_more),
+        (None, false) => (false, has_more),
+    };
+
+    let page_url = |direction, row: &SegmentRow| {
+        let page_query = GetSegmentsQuery {
+            limit: Some(limit),
+            cursor: Some(cursor::encode(&SegmentCursor {
+                direction,
+                start: row.start_sec,
+                id: row.id,
+            })),
+            ..query.clone()
+        };
+        url.url_with_query(format!("/channels/{channel_id}/segments"), &page_query)
+    };
+    let prev_url = rows
+        .first()
+        .filter(|_| has_prev)
+        .map(|row| page_url(PageDirection::Before, row));
+    let next_url = rows
+        .last()
+        .filter(|_| has_next)
+        .map(|row| page_url(PageDirection::After, row));
+
+    let segments = rows
         .into_iter()
         .map(|row| SingleSegmentResponse {
             id: row.id,
             start: row.start_sec,
             end: row.end_sec,
-            text: row.content.clone(),
+            text: row.content,
             metrics: row.metrics.0,
         })
         .collect();
 
-    let mut next_url = None;
-    let mut prev_url = None;
-
-    let prev_segment = sqlx::query!(
-        r#"
-        SELECT start_sec, end_sec FROM segments
-        WHERE channel=$1
-        AND end_sec < $2
-        LIMIT 1
-        "#,
-        channel_id,
-        query.start.unwrap_or(0.0)
-    )
-    .fetch_optional(&state.db)
-    .await?;
-
-    if let Some(utter) = prev_segment {
-        let end_time = utter.end_sec;
-        let start_time = (utter.start_sec - 1800.0).min(0.0);
-        prev_url = Some(url.url(format!(
-            "/channels/{channel_id}/segments?start={start_time}&end={end_time}",
-        )));
-    }
-
-    let next_segment = sqlx::query!(
-        r#"
-        SELECT start_sec, end_sec FROM segments
-        WHERE channel=$1
-        AND start_sec > $2
-        LIMIT 1
-        "#,
-        channel_id,
-        query.end.unwrap_or(f32::MAX)
-    )
-    .fetch_optional(&state.db)
-    .await?;
-
-    if let Some(utter) = next_segment {
-        let start_time = utter.start_sec;
-        let end_time = utter.end_sec + 1800.0;
-        next_url = Some(url.url(format!(
-            "/channels/{channel_id}/segments?start={start_time}&end={end_time}",
-        )));
-    }
-
     Ok(Json(GetSegmentsResponse {
         prev_url,
         next_url,
@@ -109,6 +213,13 @@ pub async fn get_segments(
     }))
 }
 
+/// Parses a metric filter into the JSON path predicate it is matched with.
+fn compile_filter(filter: &str) -> AppResult<String> {
+    let filter = Filter::parse(filter)
+        .map_err(|error| AppError::bad_request(format!("invalid filter: {error}")))?;
+    Ok(filter.json_path())
+}
+
 #[derive(Debug, serde::Serialize)]
 pub struct GetSegmentsResponse {
     pub prev_url: Option<String>,
@@ -124,3 +235,127 @@ pub struct SingleSegmentResponse {
     pub text: String,
     pub metrics: Vec<MetricCollection>,
 }
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct QuerySegmentsQuery {
+    /// A [`Filter`] on segment metrics.
+    filter: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recording: Option<uuid::Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cursor: Option<String>,
+}
+
+/// Position of the last match of a page, in upload, channel and start time order.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct QuerySegmentsCursor {
+    uploaded_at: DateTime<Utc>,
+    channel: Uuid,
+    start: f32,
+    id: Uuid,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct SegmentMatch {
+    pub recording_url: String,
+    pub recording_id: Uuid,
+    pub channel_id: Uuid,
+    pub channel_index: i32,
+    pub speaker: Option<String>,
+    pub id: Uuid,
+    pub start: f32,
+    pub end: f32,
+    pub text: String,
+    pub metrics: Vec<MetricCollection>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct QuerySegmentsResponse {
+    pub items: Vec<SegmentMatch>,
+    pub next_url: Option<String>,
+}
+
+/// Finds the segments of the latest analysis run of every recording whose metrics match
+/// the filter.
+pub async fn query_segments(
+    State(state): State<AppState>,
+    Query(query): Query<QuerySegmentsQuery>,
+    url: UrlGenerator,
+) -> AppResult<Json<QuerySegmentsResponse>> {
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(AppError::bad_request(format!(
+            "limit must be between 1 and {MAX_PAGE_SIZE}"
+        )));
+    }
+    let filter = compile_filter(&query.filter)?;
+    let position = query
+        .cursor
+        .as_deref()
+        .map(cursor::decode::<QuerySegmentsCursor>)
+        .transpose()?;
+
+    let mut rows = sqlx::query!(
+        r#"
+        SELECT r.id AS recording_id, r.uploaded_at, c.id AS channel_id, c.idx_in_file,
+        c.assigned_name, s.id, s.start_sec, s.end_sec, s.content,
+        s.metrics_list as "metrics: SJson<Vec<MetricCollection>>"
+        FROM segments s
+        JOIN channels c ON c.id = s.channel
+        JOIN recordings r ON r.id = c.recording AND r.latest_run = c.run_id
+        WHERE s.metrics_list @@ $1::TEXT::JSONPATH
+        AND ($2::UUID IS NULL OR r.id = $2)
+        AND ($3::TIMESTAMPTZ IS NULL
+            OR (r.uploaded_at, c.id, s.start_sec, s.id) > ($3, $4::UUID, $5::REAL, $6::UUID))
+        ORDER BY r.uploaded_at, c.id, s.start_sec, s.id
+        LIMIT $7
+        "#,
+        filter,
+        query.recording,
+        position.as_ref().map(|position| position.uploaded_at),
+        position.as_ref().map(|position| position.channel),
+        position.as_ref().map(|position| position.start),
+        position.as_ref().map(|position| position.id),
+        limit + 1
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    let mut next_url = None;
+    if rows.len() as i64 > limit {
+        rows.truncate(limit as usize);
+        let last = rows.last().expect("page should not be empty");
+        let next_query = QuerySegmentsQuery {
+            limit: Some(limit),
+            cursor: Some(cursor::encode(&QuerySegmentsCursor {
+                uploaded_at: last.uploaded_at,
+                channel: last.channel_id,
+                start: last.start_sec,
+                id: last.id,
+            })),
+            ..query
+        };
+        next_url = Some(url.url_with_query("/segments/query", &next_query));
+    }
+
+    Ok(Json(QuerySegmentsResponse {
+        items: rows
+            .into_iter()
+            .map(|row| SegmentMatch {
+                recording_url: url.url(format!("/recordings/{}", row.recording_id)),
+                recording_id: row.recording_id,
+                channel_id: row.channel_id,
+                channel_index: row.idx_in_file,
+                speaker: row.assigned_name,
+                id: row.id,
+                start: row.start_sec,
+                end: row.end_sec,
+                text: row.content,
+                metrics: row.metrics.0,
+            })
+            .collect(),
+        next_url,
+    }))
+}
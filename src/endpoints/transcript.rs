@@ -0,0 +1,112 @@
+use axum::{
+    extract::State,
+    http::{HeaderMap, HeaderValue, header},
+};
+
+use crate::{
+    AppState,
+    extract::{Path, Query},
+    result::{AppError, AppResult},
+    transcript::{Segment, render::SubtitleFormat},
+};
+
+#[derive(Debug, serde::Deserialize)]
+pub struct ExportTranscriptQuery {
+    format: SubtitleFormat,
+    /// Interleave all channels of a `txt` export by start time instead of listing them one
+    /// after another; subtitle cues are always in chronological order.
+    #[serde(default)]
+    merge: bool,
+    /// `idx_in_file` of the only channel to export.
+    channel: Option<i32>,
+    /// Analysis run to export; defaults to the latest one.
+    run: Option<uuid::Uuid>,
+}
+
+/// Exports the segments of a recording as subtitles or plain text.
+///
+/// Each line is prefixed with the channel's assigned name, or `Channel {idx_in_file}`.
+pub async fn export_transcript(
+    State(state): State<AppState>,
+    Path(id): Path<uuid::Uuid>,
+    Query(query): Query<ExportTranscriptQuery>,
+) -> AppResult<(HeaderMap, String)> {
+    let Some(latest_run) = sqlx::query_scalar!("SELECT latest_run FROM recordings WHERE id=$1", id)
+        .fetch_optional(&state.db)
+        .await?
+    else {
+        return Err(AppError::not_found("recording not found"));
+    };
+
+    let run_id = query.run.or(latest_run);
+    if let Some(run_id) = query.run {
+        let exists = sqlx::query_scalar!(
+            "SELECT EXISTS(SELECT 1 FROM analysis_runs WHERE id=$1 AND recording_id=$2) AS \"exists!\"",
+            run_id,
+            id
+        )
+        .fetch_one(&state.db)
+        .await?;
+        if !exists {
+            return Err(AppError::not_found("analysis run not found"));
+        }
+    }
+    if let Some(channel) = query.channel {
+        let exists = sqlx::query_scalar!(
+            "SELECT EXISTS(SELECT 1 FROM channels WHERE run_id=$1 AND idx_in_file=$2) AS \"exists!\"",
+            run_id,
+            channel
+        )
+        .fetch_one(&state.db)
+        .await?;
+        if !exists {
+            return Err(AppError::not_found("channel not found"));
+        }
+    }
+
+    let rows = sqlx::query!(
+        "SELECT channels.idx_in_file, channels.assigned_name, segments.start_sec, segments.end_sec, segments.content
+        FROM segments
+        JOIN channels ON channels.id = segments.channel
+        WHERE channels.run_id = $1 AND ($2::INTEGER IS NULL OR channels.idx_in_file = $2)
+        ORDER BY channels.idx_in_file, segments.start_sec",
+        run_id,
+        query.channel
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    let mut segments: Vec<Segment> = rows
+        .into_iter()
+        .map(|row| Segment {
+            speaker: Some(
+                row.assigned_name
+                    .unwrap_or_else(|| format!("Channel {}", row.idx_in_file)),
+            ),
+            start: Some(row.start_sec as f64),
+            end: Some(row.end_sec as f64),
+            text: row.content,
+        })
+        .collect();
+    if query.merge {
+        // stable, so simultaneous segments stay in channel order
+        segments.sort_by(|a, b| {
+            a.start
+                .unwrap_or_default()
+                .total_cmp(&b.start.unwrap_or_default())
+        });
+    }
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(query.format.content_type()),
+    );
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        format!("attachment; filename=\"{id}.{}\"", query.format.extension())
+            .try_into()
+            .unwrap(),
+    );
+    Ok((headers, query.format.render(&segments)))
+}
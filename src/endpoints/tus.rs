@@ -0,0 +1,555 @@
+//! Resumable uploads following the tus 1.0.0 protocol, with the `creation`,
+//! `termination` and `expiration` extensions.
+//!
+//! Uploaded bytes go into a storage multipart upload. Parts must be at least
+//! [`MIN_PART_SIZE`] long, so whatever is left over after a `PATCH` request is kept in
+//! the database until the next request adds to it. Once all bytes have arrived, the
+//! parts are assembled and the recording is created just like by a regular upload.
+//!
+//! There is no `OPTIONS` discovery endpoint, since the CORS layer answers every `OPTIONS`
+//! request; clients have to be configured for version 1.0.0.
+
+use std::time::{Duration, Instant};
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+};
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use eyre::Context;
+use futures_util::StreamExt;
+
+use crate::{
+    AppState,
+    analysis_submit::wake_relay,
+    blob_cleanup::queue_deletion,
+    endpoints::upload::{MAX_UPLOAD_SIZE, NewRecording, audio_key, create_recording},
+    extract::Path,
+    media,
+    result::{AppError, AppResult},
+    storage::UploadedPart,
+    url::UrlGenerator,
+};
+
+const TUS_VERSION: &str = "1.0.0";
+/// Smallest part storage accepts, except for the last one.
+const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+const UPLOAD_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+/// How long a `PATCH` keeps the upload to itself without recording progress.
+const WRITE_LEASE: Duration = Duration::from_secs(300);
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn require_tus_version(headers: &HeaderMap) -> AppResult<()> {
+    match header_str(headers, "Tus-Resumable") {
+        Some(TUS_VERSION) => Ok(()),
+        _ => Err(AppError::precondition_failed(format!(
+            "only tus version {TUS_VERSION} is supported"
+        ))),
+    }
+}
+
+fn http_date(time: DateTime<Utc>) -> HeaderValue {
+    time.format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+        .try_into()
+        .expect("dates should be valid header values")
+}
+
+fn tus_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert("Tus-Resumable", HeaderValue::from_static(TUS_VERSION));
+    headers
+}
+
+/// Parses `Upload-Metadata`, a comma separated list of keys and base64 encoded values.
+fn parse_metadata(raw: &str) -> AppResult<Vec<(String, String)>> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once(' ').unwrap_or((pair, ""));
+            let value = base64::engine::general_purpose::STANDARD
+                .decode(value.trim())
+                .ok()
+                .and_then(|value| String::from_utf8(value).ok())
+                .ok_or_else(|| {
+                    AppError::bad_request(format!("Upload-Metadata value of '{key}' is invalid"))
+                })?;
+            Ok((key.to_owned(), value))
+        })
+        .collect()
+}
+
+/// Starts an upload of `Upload-Length` bytes.
+///
+/// `Upload-Metadata` must contain `filename` (or `name`) and may contain `diarize`.
+pub async fn create_upload(
+    State(state): State<AppState>,
+    url: UrlGenerator,
+    headers: HeaderMap,
+) -> AppResult<(StatusCode, HeaderMap)> {
+    require_tus_version(&headers)?;
+    let length: i64 = header_str(&headers, "Upload-Length")
+        .and_then(|length| length.parse().ok())
+        .filter(|length| *length > 0)
+        .ok_or_else(|| AppError::bad_request("Upload-Length must be a positive integer"))?;
+    if length > MAX_UPLOAD_SIZE {
+        return Err(AppError::PayloadTooLarge(format!(
+            "uploads are limited to {MAX_UPLOAD_SIZE} bytes"
+        )));
+    }
+
+    let raw_metadata = header_str(&headers, "Upload-Metadata");
+    let metadata = parse_metadata(raw_metadata.unwrap_or_default())?;
+    let value = |key: &str| {
+        metadata
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    };
+    let filename = value("filename")
+        .or_else(|| value("name"))
+        .filter(|filename| !filename.is_empty())
+        .ok_or_else(|| AppError::bad_request("Upload-Metadata must contain a filename"))?;
+    let force_diarize = match value("diarize") {
+        Some("true") => Some(true),
+        Some("false") => Some(false),
+        _ => None,
+    };
+
+    let id = uuid::Uuid::new_v4();
+    let key = audio_key(id);
+    let storage_upload_id = state
+        .storage
+        .create_multipart(&key)
+        .await
+        .wrap_err("failed to start upload in storage")
+        .map_err(AppError::upstream)?;
+    let expires_at = Utc::now() + UPLOAD_LIFETIME;
+    sqlx::query!(
+        "INSERT INTO tus_uploads (id, storage_key, storage_upload_id, upload_length, metadata, filename, force_diarize, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+        id,
+        key,
+        storage_upload_id,
+        length,
+        raw_metadata,
+        filename,
+        force_diarize,
+        expires_at
+    )
+    .execute(&state.db)
+    .await?;
+
+    let mut headers = tus_headers();
+    headers.insert(
+        header::LOCATION,
+        url.url(format!("/files/{id}")).try_into().unwrap(),
+    );
+    headers.insert("Upload-Expires", http_date(expires_at));
+    Ok((StatusCode::CREATED, headers))
+}
+
+struct TusUpload {
+    storage_key: String,
+    storage_upload_id: String,
+    upload_length: i64,
+    upload_offset: i64,
+    metadata: Option<String>,
+    filename: String,
+    force_diarize: Option<bool>,
+    parts: sqlx::types::Json<Vec<UploadedPart>>,
+    pending: Vec<u8>,
+    expires_at: DateTime<Utc>,
+    assembled_at: Option<DateTime<Utc>>,
+    completed_at: Option<DateTime<Utc>>,
+}
+
+async fn fetch_upload(state: &AppState, id: uuid::Uuid) -> AppResult<TusUpload> {
+    sqlx::query_as!(
+        TusUpload,
+        r#"SELECT storage_key, storage_upload_id, upload_length, upload_offset, metadata, filename, force_diarize,
+            parts AS "parts: _", pending, expires_at, assembled_at, completed_at
+        FROM tus_uploads WHERE id=$1 AND expires_at > now()"#,
+        id
+    )
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or_else(|| AppError::not_found("upload not found"))
+}
+
+fn progress_headers(id: uuid::Uuid, upload: &TusUpload, url: &UrlGenerator) -> HeaderMap {
+    let mut headers = tus_headers();
+    headers.insert("Upload-Offset", upload.upload_offset.into());
+    headers.insert("Upload-Length", upload.upload_length.into());
+    headers.insert("Upload-Expires", http_date(upload.expires_at));
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    if upload.completed_at.is_some() {
+        headers.insert(
+            "Recording-Location",
+            url.url(format!("/recordings/{id}")).try_into().unwrap(),
+        );
+    }
+    headers
+}
+
+/// Reports how many bytes of the upload the server has.
+pub async fn upload_status(
+    State(state): State<AppState>,
+    Path(id): Path<uuid::Uuid>,
+    url: UrlGenerator,
+    headers: HeaderMap,
+) -> AppResult<(StatusCode, HeaderMap)> {
+    require_tus_version(&headers)?;
+    let upload = fetch_upload(&state, id).await?;
+    let mut headers = progress_headers(id, &upload, &url);
+    if let Some(metadata) = upload.metadata.as_deref().and_then(|m| m.try_into().ok()) {
+        headers.insert("Upload-Metadata", metadata);
+    }
+    Ok((StatusCode::OK, headers))
+}
+
+/// Appends the request body at `Upload-Offset`.
+///
+/// Bytes are kept even if the client disconnects halfway, so it can resume from the offset
+/// reported by `HEAD`. The request that completes the upload also creates the recording.
+pub async fn append_to_upload(
+    State(state): State<AppState>,
+    Path(id): Path<uuid::Uuid>,
+    url: UrlGenerator,
+    headers: HeaderMap,
+    body: Body,
+) -> AppResult<(StatusCode, HeaderMap)> {
+    require_tus_version(&headers)?;
+    if header_str(&headers, "Content-Type") != Some("application/offset+octet-stream") {
+        return Err(AppError::unsupported_media_type(
+            "Content-Type must be application/offset+octet-stream",
+        ));
+    }
+    let offset: i64 = header_str(&headers, "Upload-Offset")
+        .and_then(|offset| offset.parse().ok())
+        .ok_or_else(|| AppError::bad_request("Upload-Offset must be an integer"))?;
+
+    // claimed for the request so concurrent PATCHes cannot interleave their parts; no
+    // connection is held while the body streams in
+    let lease = uuid::Uuid::new_v4();
+    let claimed = sqlx::query!(
+        "UPDATE tus_uploads SET locked_until = now() + make_interval(secs => $2), lock_token=$3
+        WHERE id=$1 AND expires_at > now() AND (locked_until IS NULL OR locked_until <= now())",
+        id,
+        WRITE_LEASE.as_secs_f64(),
+        lease
+    )
+    .execute(&state.db)
+    .await?
+    .rows_affected();
+    if claimed == 0 {
+        fetch_upload(&state, id).await?;
+        return Err(AppError::conflict(
+            "another request is currently writing to this upload",
+        ));
+    }
+
+    let result = append(&state, id, lease, offset, &headers, body).await;
+    sqlx::query!(
+        "UPDATE tus_uploads SET locked_until=NULL, lock_token=NULL WHERE id=$1 AND lock_token=$2",
+        id,
+        lease
+    )
+    .execute(&state.db)
+    .await?;
+    let upload = result?;
+
+    Ok((StatusCode::NO_CONTENT, progress_headers(id, &upload, &url)))
+}
+
+async fn append(
+    state: &AppState,
+    id: uuid::Uuid,
+    lease: uuid::Uuid,
+    offset: i64,
+    headers: &HeaderMap,
+    body: Body,
+) -> AppResult<TusUpload> {
+    let mut upload = fetch_upload(state, id).await?;
+    if offset != upload.upload_offset {
+        return Err(AppError::conflict(format!(
+            "Upload-Offset is {offset}, but the upload is at {}",
+            upload.upload_offset
+        )));
+    }
+    let content_length: Option<i64> =
+        header_str(headers, "Content-Length").and_then(|length| length.parse().ok());
+    if content_length.is_some_and(|length| offset + length > upload.upload_length) {
+        return Err(AppError::PayloadTooLarge(
+            "request body would exceed Upload-Length".into(),
+        ));
+    }
+
+    let mut stream = body.into_data_stream();
+    let mut body_error = None;
+    let mut saved_at = Instant::now();
+    while let Some(chunk) = stream.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(why) => {
+                body_error = Some(why);
+                break;
+            }
+        };
+        if upload.upload_offset + chunk.len() as i64 > upload.upload_length {
+            return Err(AppError::PayloadTooLarge(
+                "request body exceeds Upload-Length".into(),
+            ));
+        }
+        upload.upload_offset += chunk.len() as i64;
+        upload.pending.extend_from_slice(&chunk);
+        if upload.pending.len() >= MIN_PART_SIZE {
+            store_part(state, id, lease, &mut upload).await?;
+            saved_at = Instant::now();
+        } else if saved_at.elapsed() > WRITE_LEASE / 3 {
+            // a slow client renews the lease without filling a part
+            save_progress(state, id, lease, &upload).await?;
+            saved_at = Instant::now();
+        }
+    }
+
+    save_progress(state, id, lease, &upload).await?;
+    if let Some(why) = body_error {
+        return Err(AppError::bad_request(format!(
+            "failed to read request body: {why}"
+        )));
+    }
+
+    if upload.upload_offset == upload.upload_length && upload.completed_at.is_none() {
+        finish_upload(state, id, lease, &mut upload).await?;
+    }
+    Ok(upload)
+}
+
+fn lease_lost() -> AppError {
+    AppError::conflict("another request took over this upload")
+}
+
+/// Records the offset and pending bytes and renews the lease.
+async fn save_progress(
+    state: &AppState,
+    id: uuid::Uuid,
+    lease: uuid::Uuid,
+    upload: &TusUpload,
+) -> AppResult<()> {
+    let updated = sqlx::query!(
+        "UPDATE tus_uploads SET upload_offset=$1, pending=$2, locked_until = now() + make_interval(secs => $3)
+        WHERE id=$4 AND lock_token=$5",
+        upload.upload_offset,
+        upload.pending,
+        WRITE_LEASE.as_secs_f64(),
+        id,
+        lease
+    )
+    .execute(&state.db)
+    .await?
+    .rows_affected();
+    if updated == 0 {
+        return Err(lease_lost());
+    }
+    Ok(())
+}
+
+/// Moves the pending bytes into a new part and records it.
+async fn store_part(
+    state: &AppState,
+    id: uuid::Uuid,
+    lease: uuid::Uuid,
+    upload: &mut TusUpload,
+) -> AppResult<()> {
+    let part = state
+        .storage
+        .upload_part(
+            &upload.storage_key,
+            &upload.storage_upload_id,
+            upload.parts.len() as u32 + 1,
+            std::mem::take(&mut upload.pending),
+        )
+        .await
+        .wrap_err("failed to store upload part")
+        .map_err(AppError::upstream)?;
+    upload.parts.push(part);
+    let updated = sqlx::query!(
+        "UPDATE tus_uploads SET parts=$1, upload_offset=$2, pending='', locked_until = now() + make_interval(secs => $3)
+        WHERE id=$4 AND lock_token=$5",
+        &upload.parts as _,
+        upload.upload_offset,
+        WRITE_LEASE.as_secs_f64(),
+        id,
+        lease
+    )
+    .execute(&state.db)
+    .await?
+    .rows_affected();
+    if updated == 0 {
+        return Err(lease_lost());
+    }
+    Ok(())
+}
+
+/// Assembles the stored parts, probes the result and creates the recording.
+///
+/// Each step is recorded, so a request that failed halfway can be retried by sending an
+/// empty `PATCH` at the final offset.
+async fn finish_upload(
+    state: &AppState,
+    id: uuid::Uuid,
+    lease: uuid::Uuid,
+    upload: &mut TusUpload,
+) -> AppResult<()> {
+    if upload.assembled_at.is_none() {
+        if !upload.pending.is_empty() {
+            store_part(state, id, lease, upload).await?;
+        }
+        state
+            .storage
+            .complete_multipart(
+                &upload.storage_key,
+                &upload.storage_upload_id,
+                &upload.parts,
+            )
+            .await
+            .wrap_err("failed to assemble upload")
+            .map_err(AppError::upstream)?;
+        sqlx::query!("UPDATE tus_uploads SET assembled_at=now() WHERE id=$1", id)
+            .execute(&state.db)
+            .await?;
+        upload.assembled_at = Some(Utc::now());
+    }
+
+    // the download below can outlast what is left of the lease
+    save_progress(state, id, lease, upload).await?;
+    let file = media::spool_stored(state.storage.as_ref(), &upload.storage_key)
+        .await
+        .wrap_err("failed to read assembled upload")
+        .map_err(AppError::upstream)?;
+    let media = media::probe(&file.path).await?;
+
+    let mut tx = state.db.begin().await?;
+    create_recording(
+        &mut tx,
+        &NewRecording {
+            id,
+            filename: upload.filename.clone(),
+            force_diarize: upload.force_diarize,
+            transcript_path: None,
+            content_sha256: Some(file.sha256),
+            idempotency_key: None,
+            media,
+        },
+    )
+    .await?;
+    let updated = sqlx::query!(
+        "UPDATE tus_uploads SET completed_at=now() WHERE id=$1 AND lock_token=$2",
+        id,
+        lease
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    if updated == 0 {
+        return Err(lease_lost());
+    }
+    tx.commit().await?;
+    wake_relay(state);
+    upload.completed_at = Some(Utc::now());
+    Ok(())
+}
+
+/// Abandons an unfinished upload and discards what was received.
+pub async fn terminate_upload(
+    State(state): State<AppState>,
+    Path(id): Path<uuid::Uuid>,
+    headers: HeaderMap,
+) -> AppResult<(StatusCode, HeaderMap)> {
+    require_tus_version(&headers)?;
+    let upload = fetch_upload(&state, id).await?;
+    if upload.completed_at.is_some() {
+        return Err(AppError::conflict(
+            "upload is complete; delete the recording instead",
+        ));
+    }
+    discard_upload(&state, id, &upload.storage_key, &upload.storage_upload_id).await?;
+    Ok((StatusCode::NO_CONTENT, tus_headers()))
+}
+
+async fn discard_upload(
+    state: &AppState,
+    id: uuid::Uuid,
+    storage_key: &str,
+    storage_upload_id: &str,
+) -> eyre::Result<()> {
+    state
+        .storage
+        .abort_multipart(storage_key, storage_upload_id)
+        .await?;
+    sqlx::query!("DELETE FROM tus_uploads WHERE id=$1", id)
+        .execute(&state.db)
+        .await?;
+    Ok(())
+}
+
+/// Discards expired uploads; completed ones only lose their bookkeeping row.
+pub async fn expire_uploads(state: &AppState) -> eyre::Result<()> {
+    let expired = sqlx::query!(
+        "SELECT id, storage_key, storage_upload_id, assembled_at, completed_at FROM tus_uploads WHERE expires_at <= now()"
+    )
+    .fetch_all(&state.db)
+    .await?;
+    for upload in expired {
+        if upload.completed_at.is_some() {
+            sqlx::query!("DELETE FROM tus_uploads WHERE id=$1", upload.id)
+                .execute(&state.db)
+                .await?;
+        } else if upload.assembled_at.is_some() {
+            // assembled, but the recording was never created
+            let mut tx = state.db.begin().await?;
+            queue_deletion(&mut tx, &upload.storage_key).await?;
+            sqlx::query!("DELETE FROM tus_uploads WHERE id=$1", upload.id)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+        } else if let Err(why) = discard_upload(
+            state,
+            upload.id,
+            &upload.storage_key,
+            &upload.storage_upload_id,
+        )
+        .await
+        {
+            tracing::warn!("failed to discard expired upload {}: {why:?}", upload.id);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_metadata() {
+        let metadata =
+            parse_metadata("filename dGFsay5tcDM=, diarize dHJ1ZQ==,is_confidential").unwrap();
+        assert_eq!(
+            metadata,
+            [
+                ("filename".into(), "talk.mp3".into()),
+                ("diarize".into(), "true".into()),
+                ("is_confidential".into(), "".into()),
+            ]
+        );
+        assert!(parse_metadata("filename not-base64!").is_err());
+    }
+}
@@ -3,79 +3,87 @@ use axum::{
     extract::{Multipart, State},
     http::{HeaderMap, StatusCode},
 };
-use eyre::{Context, OptionExt, eyre};
+use eyre::Context;
 use futures_util::TryStreamExt;
+use sqlx::PgConnection;
 
-use crate::{AppState, analysis_submit::analyze_recording, result::AppResult};
+use crate::{
+    AppState,
+    analysis_submit::{analyze_recording, wake_relay},
+    blob_cleanup::queue_deletion,
+    endpoints::webhook::validate_target_url,
+    media::{self, MediaInfo},
+    result::{AppError, AppResult},
+    transcript::{self, TranscriptFormat},
+    webhooks::generate_secret,
+};
+
+/// Largest audio file accepted by any of the upload endpoints.
+pub const MAX_UPLOAD_SIZE: i64 = 2 * 1024 * 1024 * 1024;
 
+/// Creates a recording from a multipart form with an `audio` file and optional `transcript`
+/// (SRT, WebVTT, plain text, TextGrid or JSON, stored as canonical JSON),
+/// `diarize`, `dedupe`, `webhook_url` and `webhook_secret` fields.
+///
+/// Repeating a request with the same `Idempotency-Key` header, or uploading audio that is
+/// already stored with `dedupe=true`, returns the existing recording with 200 instead of
+/// creating a new one; no subscription is created from `webhook_url` in that case.
 pub async fn upload_audio_file(
     State(state): State<AppState>,
+    headers: HeaderMap,
     mut multipart: Multipart,
 ) -> AppResult<(StatusCode, HeaderMap, Json<UploadResponse>)> {
+    let idempotency_key = match headers.get("Idempotency-Key") {
+        Some(value) => Some(
+            value
+                .to_str()
+                .ok()
+                .filter(|key| !key.is_empty() && key.len() <= 255)
+                .ok_or_else(|| {
+                    AppError::bad_request("Idempotency-Key must be 1 to 255 ASCII characters")
+                })?
+                .to_owned(),
+        ),
+        None => None,
+    };
+    if let Some(key) = &idempotency_key
+        && let Some(existing) =
+            sqlx::query_scalar!("SELECT id FROM recordings WHERE idempotency_key=$1", key)
+                .fetch_optional(&state.db)
+                .await?
+    {
+        return Ok(existing_upload(existing));
+    }
+
     let uuid = uuid::Uuid::new_v4();
-    let mut audio_filename: Option<String> = None;
-    let mut transcript_path: Option<String> = None;
+    let mut audio = None;
+    let mut transcript = None;
     let mut diarize = None;
+    let mut dedupe = false;
+    let mut webhook_url: Option<String> = None;
+    let mut webhook_secret: Option<String> = None;
 
-    while let Some(field) = multipart.next_field().await? {
-        let name = field.name().ok_or_eyre("field must have name")?.to_owned();
+    while let Some(field) = multipart.next_field().await.map_err(AppError::multipart)? {
+        let name = field
+            .name()
+            .ok_or_else(|| AppError::bad_request("multipart field must have a name"))?
+            .to_owned();
 
         if name == "audio" {
             let filename = field
                 .file_name()
-                .ok_or_eyre("uploaded audio file should have filename")?
+                .ok_or_else(|| AppError::bad_request("uploaded audio file should have a filename"))?
                 .to_owned();
-            // let content_type = field
-            //     .content_type()
-            //     .ok_or_eyre("uploaded file should have content type")?
-            //     .to_owned();
-            // if !content_type.starts_with("audio/") {
-            //     return Err(eyre!("file is not audio").into());
-            // }
-
-            let mut reader = tokio_util::io::StreamReader::new(field.map_err(|multipart_error| {
-                std::io::Error::new(std::io::ErrorKind::Other, multipart_error)
-            }));
-
-            let response = state
-                .s3
-                .put_object_stream_builder(format!("original_upload/{uuid}"))
-                .execute_stream(&mut reader)
+            let reader = tokio_util::io::StreamReader::new(field.map_err(std::io::Error::other));
+            let file = media::spool(reader)
                 .await
-                .wrap_err("failed to upload audio to storage")?;
-
-            if response.status_code() != 200 {
-                return Err(eyre!(format!(
-                    "failed to upload audio to storage (status code: {})",
-                    response.status_code()
-                ))
-                .into());
-            }
-
-            audio_filename = Some(filename);
+                .wrap_err("failed to receive audio")?;
+            let media_info = media::probe(&file.path).await?;
+            audio = Some((filename, file, media_info));
         } else if name == "transcript" {
-            if let Some(_) = field.file_name() {
-                let mut reader =
-                    tokio_util::io::StreamReader::new(field.map_err(|multipart_error| {
-                        std::io::Error::new(std::io::ErrorKind::Other, multipart_error)
-                    }));
-
-                let response = state
-                    .s3
-                    .put_object_stream_builder(format!("original_transcript/{uuid}"))
-                    .execute_stream(&mut reader)
-                    .await
-                    .wrap_err("failed to upload transcript to storage")?;
-
-                if response.status_code() != 200 {
-                    return Err(eyre!(format!(
-                        "failed to upload transcript to storage (status code: {})",
-                        response.status_code()
-                    ))
-                    .into());
-                }
-
-                transcript_path = Some(format!("original_transcript/{uuid}"));
+            if let Some(filename) = field.file_name().map(str::to_owned) {
+                let bytes = field.bytes().await.map_err(AppError::multipart)?;
+                transcript = Some(normalize_transcript(&filename, &bytes)?);
             }
         } else if name == "diarize" {
             let text = field.text().await.unwrap_or_default();
@@ -84,47 +92,217 @@ pub async fn upload_audio_file(
                 "false" => diarize = Some(false),
                 _ => diarize = None,
             }
+        } else if name == "dedupe" {
+            dedupe = field.text().await.map_err(AppError::multipart)? == "true";
+        } else if name == "webhook_url" {
+            let url = field.text().await.map_err(AppError::multipart)?;
+            validate_target_url(&url)?;
+            webhook_url = Some(url);
+        } else if name == "webhook_secret" {
+            webhook_secret = Some(field.text().await.map_err(AppError::multipart)?);
         }
     }
 
-    let audio_filename = if let Some(filename) = audio_filename {
-        filename
-    } else {
-        return Err(eyre!("no audio file in upload").into());
+    let Some((audio_filename, audio, media_info)) = audio else {
+        return Err(AppError::bad_request("no audio file in upload"));
     };
 
-    sqlx::query!(
-        "INSERT INTO recordings (id, uploaded_at, original_filename, original_s3_path, force_diarize, original_transcript_s3_path) VALUES ($1, $2, $3, $4, $5, $6)",
-        uuid,
-        chrono::Utc::now(),
-        audio_filename,
-        format!("original_upload/{uuid}"),
-        diarize,
-        transcript_path,
-    )
-    .execute(&state.db)
-    .await
-    .wrap_err("failed to insert into database")?;
+    if dedupe
+        && let Some(existing) = sqlx::query_scalar!(
+            "SELECT id FROM recordings WHERE content_sha256=$1 ORDER BY uploaded_at LIMIT 1",
+            audio.sha256
+        )
+        .fetch_optional(&state.db)
+        .await?
+    {
+        return Ok(existing_upload(existing));
+    }
 
-    // TODO: use the extra fields (diarize, transcript_path) in analysis
-    analyze_recording(state, uuid)
+    // files are stored before the transaction starts, so neither a connection nor the
+    // idempotency lock is held while they are sent
+    state
+        .storage
+        .put_stream(
+            &audio_key(uuid),
+            Box::new(tokio::fs::File::open(&audio.path).await?),
+        )
         .await
-        .wrap_err("failed to analyze")?;
+        .wrap_err("failed to upload audio to storage")
+        .map_err(AppError::upstream)?;
+    let transcript_path = match transcript {
+        Some(transcript) => {
+            let path = format!("original_transcript/{uuid}");
+            state
+                .storage
+                .put_stream(&path, Box::new(std::io::Cursor::new(transcript)))
+                .await
+                .wrap_err("failed to upload transcript to storage")
+                .map_err(AppError::upstream)?;
+            Some(path)
+        }
+        None => None,
+    };
 
+    let mut tx = state.db.begin().await?;
+    if let Some(key) = &idempotency_key {
+        // a concurrent request with the same key waits here until the first one commits
+        sqlx::query!("SELECT pg_advisory_xact_lock(hashtextextended($1, 0))", key)
+            .execute(&mut *tx)
+            .await?;
+        if let Some(existing) =
+            sqlx::query_scalar!("SELECT id FROM recordings WHERE idempotency_key=$1", key)
+                .fetch_optional(&mut *tx)
+                .await?
+        {
+            // the other request created the recording, so the files stored here are unused
+            queue_deletion(&mut tx, &audio_key(uuid)).await?;
+            if let Some(path) = &transcript_path {
+                queue_deletion(&mut tx, path).await?;
+            }
+            tx.commit().await?;
+            return Ok(existing_upload(existing));
+        }
+    }
+
+    create_recording(
+        &mut tx,
+        &NewRecording {
+            id: uuid,
+            filename: audio_filename,
+            force_diarize: diarize,
+            transcript_path,
+            content_sha256: Some(audio.sha256),
+            idempotency_key,
+            media: media_info,
+        },
+    )
+    .await?;
+
+    let webhook = match webhook_url {
+        Some(url) => {
+            let id = uuid::Uuid::new_v4();
+            let secret = webhook_secret.unwrap_or_else(generate_secret);
+            sqlx::query!(
+                "INSERT INTO webhook_subscriptions (id, url, secret, recording_id) VALUES ($1, $2, $3, $4)",
+                id,
+                url,
+                secret,
+                uuid
+            )
+            .execute(&mut *tx)
+            .await?;
+            Some(UploadWebhook { id, secret })
+        }
+        None => None,
+    };
+    tx.commit().await?;
+    wake_relay(&state);
+
+    Ok((
+        StatusCode::CREATED,
+        location(uuid),
+        Json(UploadResponse {
+            upload_id: uuid,
+            webhook,
+        }),
+    ))
+}
+
+/// Parses an uploaded transcript into the canonical JSON handed to the analysis service.
+fn normalize_transcript(filename: &str, bytes: &[u8]) -> AppResult<Vec<u8>> {
+    let contents = transcript::decode(bytes)
+        .map_err(|why| AppError::bad_request(format!("invalid transcript: {why}")))?;
+    let format = TranscriptFormat::detect(Some(filename), &contents);
+    let transcript = format
+        .parse(&contents)
+        .map_err(|why| AppError::bad_request(format!("invalid {format} transcript: {why}")))?;
+    Ok(serde_json::to_vec(&transcript).wrap_err("failed to serialize transcript")?)
+}
+
+fn location(recording_id: uuid::Uuid) -> HeaderMap {
     let mut headers = HeaderMap::new();
     headers.insert(
         "Location",
-        format!("/recordings/{}", uuid).try_into().unwrap(),
+        format!("/recordings/{}", recording_id).try_into().unwrap(),
     );
+    headers
+}
 
-    Ok((
-        StatusCode::CREATED,
-        headers,
-        Json(UploadResponse { upload_id: uuid }),
-    ))
+/// Response for an upload that matched a recording created earlier.
+fn existing_upload(recording_id: uuid::Uuid) -> (StatusCode, HeaderMap, Json<UploadResponse>) {
+    (
+        StatusCode::OK,
+        location(recording_id),
+        Json(UploadResponse {
+            upload_id: recording_id,
+            webhook: None,
+        }),
+    )
+}
+
+/// A recording whose audio is already in storage under [`audio_key`] and has been probed.
+pub struct NewRecording {
+    pub id: uuid::Uuid,
+    pub filename: String,
+    pub force_diarize: Option<bool>,
+    pub transcript_path: Option<String>,
+    /// Lowercase hex SHA-256 of the audio; unknown for multipart direct uploads.
+    pub content_sha256: Option<String>,
+    pub idempotency_key: Option<String>,
+    pub media: MediaInfo,
+}
+
+pub fn audio_key(recording_id: uuid::Uuid) -> String {
+    format!("original_upload/{recording_id}")
+}
+
+/// Inserts the recording and queues it for analysis.
+///
+/// Shared by all upload flows; call [`wake_relay`] once the transaction is committed.
+pub async fn create_recording(
+    conn: &mut PgConnection,
+    recording: &NewRecording,
+) -> eyre::Result<uuid::Uuid> {
+    sqlx::query!(
+        "INSERT INTO recordings (id, uploaded_at, original_filename, original_s3_path, force_diarize, original_transcript_s3_path,
+            media_container, media_codec, media_sample_rate, media_channels, media_duration_sec, media_bits_per_sample,
+            content_sha256, idempotency_key)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)",
+        recording.id,
+        chrono::Utc::now(),
+        recording.filename,
+        audio_key(recording.id),
+        recording.force_diarize,
+        recording.transcript_path,
+        recording.media.container,
+        recording.media.codec,
+        recording.media.sample_rate,
+        recording.media.channels,
+        recording.media.duration_sec,
+        recording.media.bits_per_sample,
+        recording.content_sha256,
+        recording.idempotency_key,
+    )
+    .execute(&mut *conn)
+    .await
+    .wrap_err("failed to insert into database")?;
+
+    analyze_recording(conn, recording.id)
+        .await
+        .wrap_err("failed to queue recording for analysis")
 }
 
 #[derive(serde::Serialize)]
 pub struct UploadResponse {
     pub upload_id: uuid::Uuid,
+    /// Subscription created from the `webhook_url` field, with the secret its deliveries
+    /// are signed with.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub webhook: Option<UploadWebhook>,
+}
+
+#[derive(serde::Serialize)]
+pub struct UploadWebhook {
+    pub id: uuid::Uuid,
+    pub secret: String,
 }
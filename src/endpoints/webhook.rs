@@ -0,0 +1,197 @@
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+};
+use chrono::{DateTime, Utc};
+
+use crate::{
+    AppState,
+    extract::{Json, Path, Query},
+    result::{AppError, AppResult},
+    url::UrlGenerator,
+    webhooks::generate_secret,
+};
+
+#[derive(Debug, serde::Deserialize)]
+pub struct CreateWebhook {
+    url: String,
+    /// Generated when omitted; only ever returned in the creation response.
+    secret: Option<String>,
+    /// Limits the subscription to a single recording.
+    recording_id: Option<uuid::Uuid>,
+}
+
+#[derive(serde::Serialize)]
+pub struct Webhook {
+    url: String,
+    id: uuid::Uuid,
+    target_url: String,
+    recording_url: Option<String>,
+    deliveries_url: String,
+    created_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    secret: Option<String>,
+}
+
+/// Checks that `url` can be delivered to.
+pub fn validate_target_url(url: &str) -> AppResult<()> {
+    match reqwest::Url::parse(url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => Ok(()),
+        _ => Err(AppError::bad_request(format!(
+            "webhook url '{url}' must be an absolute http or https url"
+        ))),
+    }
+}
+
+pub async fn create_webhook(
+    State(state): State<AppState>,
+    url: UrlGenerator,
+    Json(body): Json<CreateWebhook>,
+) -> AppResult<(StatusCode, HeaderMap, Json<Webhook>)> {
+    validate_target_url(&body.url)?;
+    if let Some(recording_id) = body.recording_id {
+        let exists = sqlx::query_scalar!(
+            "SELECT EXISTS(SELECT 1 FROM recordings WHERE id=$1) AS \"exists!\"",
+            recording_id
+        )
+        .fetch_one(&state.db)
+        .await?;
+        if !exists {
+            return Err(AppError::not_found("recording not found"));
+        }
+    }
+
+    let id = uuid::Uuid::new_v4();
+    let secret = body.secret.unwrap_or_else(generate_secret);
+    let created_at = sqlx::query_scalar!(
+        "INSERT INTO webhook_subscriptions (id, url, secret, recording_id) VALUES ($1, $2, $3, $4) RETURNING created_at",
+        id,
+        body.url,
+        secret,
+        body.recording_id
+    )
+    .fetch_one(&state.db)
+    .await?;
+
+    let mut headers = HeaderMap::new();
+    headers.insert("Location", format!("/webhooks/{}", id).try_into().unwrap());
+    Ok((
+        StatusCode::CREATED,
+        headers,
+        Json(Webhook {
+            url: url.url(format!("/webhooks/{id}")),
+            id,
+            target_url: body.url,
+            recording_url: body
+                .recording_id
+                .map(|recording_id| url.url(format!("/recordings/{recording_id}"))),
+            deliveries_url: url.url(format!("/webhooks/{id}/deliveries")),
+            created_at,
+            secret: Some(secret),
+        }),
+    ))
+}
+
+pub async fn list_webhooks(
+    State(state): State<AppState>,
+    url: UrlGenerator,
+) -> AppResult<Json<Vec<Webhook>>> {
+    let rows = sqlx::query!(
+        "SELECT id, url, recording_id, created_at FROM webhook_subscriptions ORDER BY created_at"
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(Json(
+        rows.into_iter()
+            .map(|row| Webhook {
+                url: url.url(format!("/webhooks/{}", row.id)),
+                id: row.id,
+                target_url: row.url,
+                recording_url: row
+                    .recording_id
+                    .map(|recording_id| url.url(format!("/recordings/{recording_id}"))),
+                deliveries_url: url.url(format!("/webhooks/{}/deliveries", row.id)),
+                created_at: row.created_at,
+                secret: None,
+            })
+            .collect(),
+    ))
+}
+
+/// Removes the subscription together with its delivery log; pending deliveries are dropped.
+pub async fn delete_webhook(
+    State(state): State<AppState>,
+    Path(id): Path<uuid::Uuid>,
+) -> AppResult<StatusCode> {
+    let result = sqlx::query!("DELETE FROM webhook_subscriptions WHERE id=$1", id)
+        .execute(&state.db)
+        .await?;
+    if result.rows_affected() == 0 {
+        return Err(AppError::not_found("webhook not found"));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct ListDeliveriesQuery {
+    limit: Option<i64>,
+}
+
+#[derive(serde::Serialize)]
+pub struct WebhookDelivery {
+    id: uuid::Uuid,
+    event: String,
+    status: String,
+    attempts: i32,
+    created_at: DateTime<Utc>,
+    next_attempt_at: Option<DateTime<Utc>>,
+    delivered_at: Option<DateTime<Utc>>,
+    last_status_code: Option<i32>,
+    last_error: Option<String>,
+    payload: serde_json::Value,
+}
+
+pub async fn list_deliveries(
+    State(state): State<AppState>,
+    Path(id): Path<uuid::Uuid>,
+    Query(query): Query<ListDeliveriesQuery>,
+) -> AppResult<Json<Vec<WebhookDelivery>>> {
+    let exists = sqlx::query_scalar!(
+        "SELECT EXISTS(SELECT 1 FROM webhook_subscriptions WHERE id=$1) AS \"exists!\"",
+        id
+    )
+    .fetch_one(&state.db)
+    .await?;
+    if !exists {
+        return Err(AppError::not_found("webhook not found"));
+    }
+
+    let rows = sqlx::query!(
+        "SELECT * FROM webhook_deliveries
+        WHERE subscription_id=$1
+        ORDER BY created_at DESC
+        LIMIT $2",
+        id,
+        query.limit.unwrap_or(100).clamp(1, 1000),
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(Json(
+        rows.into_iter()
+            .map(|row| WebhookDelivery {
+                id: row.id,
+                event: row.event,
+                next_attempt_at: (row.status == "pending").then_some(row.next_attempt_at),
+                status: row.status,
+                attempts: row.attempts,
+                created_at: row.created_at,
+                delivered_at: row.delivered_at,
+                last_status_code: row.last_status_code,
+                last_error: row.last_error,
+                payload: row.payload,
+            })
+            .collect(),
+    ))
+}
@@ -0,0 +1,158 @@
+use std::time::Duration;
+
+use sqlx::{PgConnection, PgPool, postgres::PgListener};
+use tokio::sync::broadcast;
+
+const CHANNEL: &str = "analysis_events";
+/// Events buffered per subscriber before it starts missing them.
+const HUB_CAPACITY: usize = 256;
+/// NOTIFY payloads are limited to 8000 bytes, error messages are cut well below that.
+const MAX_ERROR_LEN: usize = 2000;
+
+/// A change in the analysis state of a recording.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AnalysisEvent {
+    Progress {
+        recording_id: uuid::Uuid,
+        run_id: uuid::Uuid,
+        percent: Option<i32>,
+        description: Option<String>,
+        channel: Option<i32>,
+    },
+    ChannelCompleted {
+        recording_id: uuid::Uuid,
+        run_id: uuid::Uuid,
+        channel_id: uuid::Uuid,
+        idx_in_file: i32,
+    },
+    Done {
+        recording_id: uuid::Uuid,
+        run_id: uuid::Uuid,
+    },
+    Error {
+        recording_id: uuid::Uuid,
+        run_id: uuid::Uuid,
+        error: String,
+    },
+    Cancelled {
+        recording_id: uuid::Uuid,
+        run_id: uuid::Uuid,
+    },
+}
+
+impl AnalysisEvent {
+    pub fn recording_id(&self) -> uuid::Uuid {
+        match self {
+            AnalysisEvent::Progress { recording_id, .. }
+            | AnalysisEvent::ChannelCompleted { recording_id, .. }
+            | AnalysisEvent::Done { recording_id, .. }
+            | AnalysisEvent::Error { recording_id, .. }
+            | AnalysisEvent::Cancelled { recording_id, .. } => *recording_id,
+        }
+    }
+
+    /// Name used as the SSE `event` field.
+    pub fn name(&self) -> &'static str {
+        match self {
+            AnalysisEvent::Progress { .. } => "progress",
+            AnalysisEvent::ChannelCompleted { .. } => "channel_completed",
+            AnalysisEvent::Done { .. } => "done",
+            AnalysisEvent::Error { .. } => "error",
+            AnalysisEvent::Cancelled { .. } => "cancelled",
+        }
+    }
+}
+
+/// Announces `event` to every backend replica through `NOTIFY`.
+///
+/// Postgres delivers the notification only once the surrounding transaction commits, so
+/// subscribers never see a state change that was rolled back.
+pub async fn publish(conn: &mut PgConnection, event: &AnalysisEvent) -> eyre::Result<()> {
+    let mut event = event.clone();
+    if let AnalysisEvent::Error { error, .. } = &mut event
+        && error.len() > MAX_ERROR_LEN
+    {
+        let mut end = MAX_ERROR_LEN;
+        while !error.is_char_boundary(end) {
+            end -= 1;
+        }
+        error.truncate(end);
+    }
+    sqlx::query!(
+        "SELECT pg_notify($1, $2)",
+        CHANNEL,
+        serde_json::to_string(&event)?
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Fans analysis events out to the subscribers of this process.
+#[derive(Clone)]
+pub struct EventHub {
+    sender: broadcast::Sender<AnalysisEvent>,
+}
+
+impl Default for EventHub {
+    fn default() -> Self {
+        EventHub {
+            sender: broadcast::channel(HUB_CAPACITY).0,
+        }
+    }
+}
+
+impl EventHub {
+    pub fn subscribe(&self) -> broadcast::Receiver<AnalysisEvent> {
+        self.sender.subscribe()
+    }
+
+    fn send(&self, event: AnalysisEvent) {
+        // no receivers just means nobody is watching right now
+        let _ = self.sender.send(event);
+    }
+}
+
+/// Forwards notifications published by any replica to the hub, reconnecting on failure.
+pub async fn listen_loop(db: PgPool, hub: EventHub) {
+    loop {
+        if let Err(why) = listen(&db, &hub).await {
+            tracing::error!("analysis event listener failed, reconnecting: {why:?}");
+        }
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    }
+}
+
+async fn listen(db: &PgPool, hub: &EventHub) -> eyre::Result<()> {
+    let mut listener = PgListener::connect_with(db).await?;
+    listener.listen(CHANNEL).await?;
+    loop {
+        let notification = listener.recv().await?;
+        match serde_json::from_str(notification.payload()) {
+            Ok(event) => hub.send(event),
+            Err(why) => tracing::warn!("ignoring malformed analysis event: {why}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_roundtrip() {
+        let event = AnalysisEvent::ChannelCompleted {
+            recording_id: uuid::Uuid::new_v4(),
+            run_id: uuid::Uuid::new_v4(),
+            channel_id: uuid::Uuid::new_v4(),
+            idx_in_file: 1,
+        };
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["type"], "channel_completed");
+        assert_eq!(
+            serde_json::from_value::<AnalysisEvent>(json).unwrap(),
+            event
+        );
+    }
+}
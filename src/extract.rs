@@ -0,0 +1,101 @@
+//! Request extractors that reject with a problem document.
+//!
+//! They wrap axum's [`Path`](axum::extract::Path), [`Query`](axum::extract::Query) and
+//! [`Json`](axum::Json), whose rejections are plain text, and turn the rejection into the
+//! matching [`AppError`].
+
+use axum::{
+    extract::{FromRequest, FromRequestParts, OptionalFromRequest, Request},
+    http::{StatusCode, request::Parts},
+    response::{IntoResponse, Response},
+};
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::result::AppError;
+
+fn rejected(status: StatusCode, detail: String) -> AppError {
+    match status {
+        StatusCode::PAYLOAD_TOO_LARGE => AppError::PayloadTooLarge(detail),
+        StatusCode::UNSUPPORTED_MEDIA_TYPE => AppError::UnsupportedMediaType(detail),
+        // e.g. a route whose path has no parameters to extract
+        status if status.is_server_error() => AppError::Internal(eyre::eyre!(detail)),
+        _ => AppError::BadRequest(detail),
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Path<T>(pub T);
+
+impl<T, S> FromRequestParts<S> for Path<T>
+where
+    T: DeserializeOwned + Send,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let axum::extract::Path(value) = axum::extract::Path::from_request_parts(parts, state)
+            .await
+            .map_err(|rejection| rejected(rejection.status(), rejection.body_text()))?;
+        Ok(Path(value))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Query<T>(pub T);
+
+impl<T, S> FromRequestParts<S> for Query<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let axum::extract::Query(value) = axum::extract::Query::from_request_parts(parts, state)
+            .await
+            .map_err(|rejection| rejected(rejection.status(), rejection.body_text()))?;
+        Ok(Query(value))
+    }
+}
+
+/// Also a response, so handlers can use the one `Json` for both.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json<T>(pub T);
+
+impl<T, S> FromRequest<S> for Json<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let axum::Json(value) = <axum::Json<T> as FromRequest<S>>::from_request(req, state)
+            .await
+            .map_err(|rejection| rejected(rejection.status(), rejection.body_text()))?;
+        Ok(Json(value))
+    }
+}
+
+/// Absent when the request has no JSON content type.
+impl<T, S> OptionalFromRequest<S> for Json<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Option<Self>, Self::Rejection> {
+        let value = <axum::Json<T> as OptionalFromRequest<S>>::from_request(req, state)
+            .await
+            .map_err(|rejection| rejected(rejection.status(), rejection.body_text()))?;
+        Ok(value.map(|axum::Json(value)| Json(value)))
+    }
+}
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
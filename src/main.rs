@@ -1,25 +1,44 @@
 pub mod analysis_submit;
+pub mod blob_cleanup;
+pub mod cursor;
 pub mod endpoints;
+pub mod events;
+pub mod extract;
+pub mod media;
 pub mod message_queue;
+pub mod metric_catalog;
+pub mod metric_filter;
+pub mod metric_stats;
+pub mod metrics_table;
 pub mod result;
+pub mod search;
+pub mod storage;
+pub mod transcript;
 pub mod url;
+pub mod webhooks;
 
-use std::{env::var, sync::Arc};
+use std::{env::var, sync::Arc, time::Duration};
 
 use axum::{
     extract::DefaultBodyLimit,
-    routing::{get, post, put},
+    routing::{delete, get, head, post, put},
 };
-use rdkafka::config::RDKafkaLogLevel;
 use tower_http::cors::CorsLayer;
 
-use crate::message_queue::KafkaKonnections;
+use crate::{
+    message_queue::{MessageBus, kafka::KafkaBus, memory::InMemoryBus},
+    storage::{BlobStorage, local::LocalStorage, s3::S3Storage},
+};
 
 #[derive(Clone)]
 pub struct AppState {
     db: sqlx::PgPool,
-    s3: s3::Bucket,
-    kafka: KafkaKonnections,
+    storage: Arc<dyn BlobStorage>,
+    bus: Arc<dyn MessageBus>,
+    outbox_wakeup: Arc<tokio::sync::Notify>,
+    cleanup_wakeup: Arc<tokio::sync::Notify>,
+    events: events::EventHub,
+    webhook_wakeup: Arc<tokio::sync::Notify>,
 }
 
 #[tokio::main]
@@ -29,67 +48,152 @@ async fn main() {
 
     let url = var("DATABASE_URL").expect("DATABASE_URL should be set to a postgres:// schema");
 
-    let mut conf = rdkafka::ClientConfig::new();
-    conf.set("bootstrap.servers", "localhost:9092");
-    conf.set("message.timeout.ms", "5000");
-    conf.set_log_level(RDKafkaLogLevel::Debug);
-
-    let konn = KafkaKonnections {
-        tx_tasks: conf
-            .clone()
-            .create()
-            .expect("should be able to create kafka producer"),
-        rx_results: Arc::new(tokio::sync::Mutex::new(
-            conf.clone()
-                .set("group.id", "backend-app")
-                .set("allow.auto.create.topics", "true")
-                .create()
-                .expect("should be able to create kafka consumer"),
-        )),
+    let mut blob_routes = None;
+    let storage: Arc<dyn BlobStorage> = match var("STORAGE_BACKEND").as_deref() {
+        Ok("local") => {
+            let local = Arc::new(LocalStorage::from_env().expect("local storage should be usable"));
+            blob_routes = Some(storage::local::router(local.clone()));
+            local
+        }
+        Ok("s3") | Err(_) => {
+            Arc::new(S3Storage::from_env().expect("s3 storage should be configured"))
+        }
+        Ok(other) => panic!("unknown STORAGE_BACKEND '{other}', expected 's3' or 'local'"),
+    };
+
+    let db = sqlx::PgPool::connect(&url)
+        .await
+        .expect("should be able to connect to database");
+
+    sqlx::migrate!()
+        .run(&db)
+        .await
+        .expect("should be able to run migrations");
+
+    let search_config =
+        var("SEARCH_TEXT_CONFIG").unwrap_or_else(|_| search::DEFAULT_TEXT_SEARCH_CONFIG.into());
+    search::configure(&db, &search_config)
+        .await
+        .expect("SEARCH_TEXT_CONFIG should name a text search configuration");
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        None => {}
+        Some("reconcile") => {
+            let fix = args[1..].iter().any(|arg| arg == "--fix");
+            blob_cleanup::run_reconcile_command(&db, storage.as_ref(), fix)
+                .await
+                .expect("reconciliation should succeed");
+            return;
+        }
+        Some(other) => panic!("unknown command '{other}', expected 'reconcile [--fix]'"),
+    }
+
+    let bus: Arc<dyn MessageBus> = match var("MESSAGE_BUS").as_deref() {
+        Ok("memory") => {
+            tracing::warn!("using the in-memory message bus with a demo analysis worker");
+            let (bus, worker) = InMemoryBus::new();
+            tokio::spawn(message_queue::memory::demo_worker(worker));
+            Arc::new(bus)
+        }
+        Ok("kafka") | Err(_) => {
+            Arc::new(KafkaBus::from_env().expect("should be able to connect to kafka"))
+        }
+        Ok(other) => panic!("unknown MESSAGE_BUS '{other}', expected 'kafka' or 'memory'"),
     };
 
     let state = AppState {
-        db: sqlx::PgPool::connect(&url)
-            .await
-            .expect("should be able to connect to database"),
-        s3: *s3::Bucket::new(
-            &var("AWS_BUCKET").expect("AWS_BUCKET must be set"),
-            s3::Region::Custom {
-                region: var("AWS_REGION").expect("AWS_REGION must be set"),
-                endpoint: var("AWS_ENDPOINT_URL").expect("AWS_ENDPOINT_URL must be set"),
-            },
-            s3::creds::Credentials::new(
-                Some(&var("AWS_ACCESS_KEY_ID").expect("AWS_ACCESS_KEY_ID must be set")),
-                Some(&var("AWS_SECRET_ACCESS_KEY").expect("AWS_SECRET_ACCESS_KEY must be set")),
-                None,
-                None,
-                None,
-            )
-            .expect("s3 credentials should be valid"),
-        )
-        .expect("s3 bucket should be valid")
-        .with_path_style(),
-        kafka: konn,
+        db,
+        storage,
+        bus,
+        outbox_wakeup: Arc::new(tokio::sync::Notify::new()),
+        cleanup_wakeup: Arc::new(tokio::sync::Notify::new()),
+        events: events::EventHub::default(),
+        webhook_wakeup: Arc::new(tokio::sync::Notify::new()),
     };
 
     tokio::spawn({
         let state = state.clone();
-        async move { message_queue::recv_loop(state.clone()).await.unwrap() }
+        async move {
+            loop {
+                if let Err(why) = message_queue::recv_loop(state.clone()).await {
+                    tracing::error!("receive loop failed, restarting: {why:?}");
+                }
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+        }
     });
+    tokio::spawn(analysis_submit::relay_loop(state.clone()));
+    tokio::spawn(blob_cleanup::cleanup_loop(state.clone()));
+    tokio::spawn(events::listen_loop(state.db.clone(), state.events.clone()));
+    tokio::spawn(webhooks::delivery_loop(state.clone()));
 
-    sqlx::migrate!()
-        .run(&state.db)
-        .await
-        .expect("should be able to run migrations");
-
-    let app = axum::Router::new()
+    let mut app = axum::Router::new()
         .route("/", get(index))
         .route("/upload", post(endpoints::upload::upload_audio_file))
+        .route(
+            "/uploads",
+            post(endpoints::direct_upload::create_direct_upload),
+        )
+        .route(
+            "/uploads/{id}/complete",
+            post(endpoints::direct_upload::complete_direct_upload),
+        )
+        .route("/files", post(endpoints::tus::create_upload))
+        .route(
+            "/files/{id}",
+            head(endpoints::tus::upload_status)
+                .patch(endpoints::tus::append_to_upload)
+                .delete(endpoints::tus::terminate_upload),
+        )
         .route("/recordings", get(endpoints::recording::list_recordings))
+        .route(
+            "/recordings/metrics.csv",
+            get(endpoints::export::export_bulk_metrics_csv),
+        )
+        .route(
+            "/recordings/metrics.parquet",
+            get(endpoints::export::export_bulk_metrics_parquet),
+        )
         .route(
             "/recordings/{id}",
             get(endpoints::recording::get_recording).delete(endpoints::recording::delete_recording),
         )
+        .route(
+            "/recordings/{id}/analysis",
+            post(endpoints::analysis::reanalyze_recording)
+                .delete(endpoints::analysis::cancel_analysis),
+        )
+        .route(
+            "/recordings/{id}/events",
+            get(endpoints::events::recording_events),
+        )
+        .route(
+            "/recordings/{id}/export",
+            get(endpoints::export::export_annotations),
+        )
+        .route(
+            "/recordings/{id}/metrics.csv",
+            get(endpoints::export::export_metrics_csv),
+        )
+        .route(
+            "/recordings/{id}/metrics.parquet",
+            get(endpoints::export::export_metrics_parquet),
+        )
+        .route(
+            "/recordings/{id}/metrics/summary",
+            get(endpoints::metrics::get_recording_summary),
+        )
+        .route("/recordings/{id}/runs", get(endpoints::run::list_runs))
+        .route(
+            "/recordings/{id}/transcript",
+            get(endpoints::transcript::export_transcript),
+        )
+        .route("/search", get(endpoints::search::search_segments))
+        .route("/segments/query", get(endpoints::segment::query_segments))
+        .route("/metrics/catalog", get(endpoints::metrics::get_catalog))
+        .route("/runs/compare", get(endpoints::run::compare_runs))
+        .route("/runs/{id}", get(endpoints::run::get_run))
         .route("/channels/{id}", get(endpoints::channel::get_channel))
         .route(
             "/channels/{id}/assigned_name",
@@ -99,8 +203,35 @@ async fn main() {
             "/channels/{id}/segments",
             get(endpoints::segment::get_segments),
         )
-        .with_state(state)
-        .layer(DefaultBodyLimit::max(2 * 1024 * 1024 * 1024))
+        .route(
+            "/channels/{id}/metrics/summary",
+            get(endpoints::metrics::get_channel_summary),
+        )
+        .route(
+            "/webhooks",
+            get(endpoints::webhook::list_webhooks).post(endpoints::webhook::create_webhook),
+        )
+        .route("/webhooks/{id}", delete(endpoints::webhook::delete_webhook))
+        .route(
+            "/webhooks/{id}/deliveries",
+            get(endpoints::webhook::list_deliveries),
+        )
+        .route(
+            "/admin/dead-letters",
+            get(endpoints::admin::list_dead_letters),
+        )
+        .route(
+            "/admin/dead-letters/{id}/replay",
+            post(endpoints::admin::replay_dead_letter),
+        )
+        .with_state(state);
+    if let Some(blob_routes) = blob_routes {
+        app = app.merge(blob_routes);
+    }
+    let app = app
+        .layer(DefaultBodyLimit::max(
+            endpoints::upload::MAX_UPLOAD_SIZE as usize,
+        ))
         .layer(CorsLayer::very_permissive());
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000")
@@ -0,0 +1,264 @@
+//! Probing of uploaded audio.
+//!
+//! Uploads are spooled to a temporary file and opened with symphonia; files it cannot
+//! decode are rejected before a recording is created.
+
+use std::{io::Read, path::Path};
+
+use sha2::{Digest, Sha256};
+use symphonia::core::{
+    codecs::{CODEC_TYPE_NULL, DecoderOptions},
+    errors::Error,
+    formats::FormatOptions,
+    io::MediaSourceStream,
+    meta::MetadataOptions,
+    probe::Hint,
+};
+use tempfile::TempPath;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+
+use crate::{
+    result::{AppError, AppResult},
+    storage::BlobStorage,
+};
+
+/// Packets that may fail to decode before the file is considered broken; streams cut
+/// from the middle of a file often start with a few undecodable frames.
+const MAX_DECODE_ERRORS: usize = 16;
+/// How much of a stored object [`probe_stored`] reads; enough for the headers and the
+/// first packets of any supported format.
+pub const PROBE_BYTES: u64 = 4 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct MediaInfo {
+    pub container: String,
+    pub codec: String,
+    pub sample_rate: Option<i32>,
+    pub channels: Option<i32>,
+    /// Missing for streams that do not declare their length.
+    pub duration_sec: Option<f64>,
+    pub bits_per_sample: Option<i32>,
+}
+
+/// A received file in temporary storage.
+pub struct Spooled {
+    /// Removed once dropped.
+    pub path: TempPath,
+    /// Lowercase hex SHA-256 of the contents.
+    pub sha256: String,
+}
+
+/// Copies `reader` into a temporary file, hashing the contents on the way.
+pub async fn spool(mut reader: impl AsyncRead + Unpin) -> eyre::Result<Spooled> {
+    let path = tokio::task::spawn_blocking(tempfile::NamedTempFile::new)
+        .await??
+        .into_temp_path();
+    let mut file = tokio::fs::File::create(&path).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = reader.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        file.write_all(&buffer[..read]).await?;
+    }
+    file.sync_all().await?;
+    Ok(Spooled {
+        path,
+        sha256: hex::encode(hasher.finalize()),
+    })
+}
+
+/// Downloads the object at `key` into a temporary file.
+pub async fn spool_stored(storage: &dyn BlobStorage, key: &str) -> eyre::Result<Spooled> {
+    let reader = storage
+        .get(key)
+        .await?
+        .ok_or_else(|| eyre::eyre!("{key} is missing from storage"))?;
+    spool(reader).await
+}
+
+/// Probes the object at `key` from its first [`PROBE_BYTES`], without downloading the rest.
+///
+/// Durations come from the headers; containers that keep their index at the end of the
+/// file are rejected like undecodable audio.
+pub async fn probe_stored(storage: &dyn BlobStorage, key: &str) -> AppResult<MediaInfo> {
+    let reader = storage
+        .get_prefix(key, PROBE_BYTES)
+        .await?
+        .ok_or_else(|| eyre::eyre!("{key} is missing from storage"))?;
+    let file = spool(reader).await?;
+    probe(&file.path).await
+}
+
+/// Probes the file at `path`, answering 415 if it is not audio that can be decoded.
+pub async fn probe(path: &Path) -> AppResult<MediaInfo> {
+    let path = path.to_owned();
+    tokio::task::spawn_blocking(move || probe_file(&path))
+        .await
+        .map_err(eyre::Report::from)?
+        .map_err(|why| {
+            AppError::unsupported_media_type(format!("file is not decodable audio: {why}"))
+        })
+}
+
+fn probe_file(path: &Path) -> Result<MediaInfo, Error> {
+    let mut header = Vec::with_capacity(16);
+    std::fs::File::open(path)?
+        .take(16)
+        .read_to_end(&mut header)?;
+
+    let source = MediaSourceStream::new(Box::new(std::fs::File::open(path)?), Default::default());
+    let mut format = symphonia::default::get_probe()
+        .format(
+            &Hint::new(),
+            source,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )?
+        .format;
+    let track = format
+        .tracks()
+        .iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or(Error::Unsupported("no audio track"))?;
+    let track_id = track.id;
+    let params = track.codec_params.clone();
+    let codecs = symphonia::default::get_codecs();
+    let mut decoder = codecs.make(&params, &DecoderOptions::default())?;
+
+    // decode the first packet so files with a valid header but broken audio are rejected
+    let mut errors = 0;
+    let spec = loop {
+        let packet = format.next_packet()?;
+        if packet.track_id() != track_id {
+            continue;
+        }
+        match decoder.decode(&packet) {
+            Ok(decoded) => break *decoded.spec(),
+            Err(Error::DecodeError(_)) if errors < MAX_DECODE_ERRORS => errors += 1,
+            Err(why) => return Err(why),
+        }
+    };
+
+    let sample_rate = params.sample_rate.unwrap_or(spec.rate);
+    let duration_sec = params.n_frames.map(|frames| match params.time_base {
+        Some(time_base) => {
+            let time = time_base.calc_time(frames);
+            time.seconds as f64 + time.frac
+        }
+        None => frames as f64 / sample_rate as f64,
+    });
+    Ok(MediaInfo {
+        container: container_name(&header).to_owned(),
+        codec: codecs
+            .get_codec(params.codec)
+            .map_or("unknown", |codec| codec.short_name)
+            .to_owned(),
+        sample_rate: Some(sample_rate as i32),
+        channels: Some(params.channels.unwrap_or(spec.channels).count() as i32),
+        duration_sec,
+        bits_per_sample: params.bits_per_sample.map(|bits| bits as i32),
+    })
+}
+
+/// Names the container from the magic bytes at the start of the file; symphonia does not
+/// report which format reader it picked.
+fn container_name(header: &[u8]) -> &'static str {
+    let at = |offset: usize, magic: &[u8]| header.get(offset..offset + magic.len()) == Some(magic);
+    if at(0, b"RIFF") && at(8, b"WAVE") {
+        "wav"
+    } else if at(0, b"FORM") && (at(8, b"AIFF") || at(8, b"AIFC")) {
+        "aiff"
+    } else if at(0, b"fLaC") {
+        "flac"
+    } else if at(0, b"OggS") {
+        "ogg"
+    } else if at(4, b"ftyp") {
+        "mp4"
+    } else if at(0, &[0x1a, 0x45, 0xdf, 0xa3]) {
+        "mkv"
+    } else if at(0, b"caff") {
+        "caf"
+    } else if at(0, b"ID3") {
+        "mpeg"
+    } else {
+        // ADTS and MPEG audio frames share the sync word but differ in the layer bits
+        match header {
+            [0xff, second, ..] if second & 0xf6 == 0xf0 => "adts",
+            [0xff, second, ..] if second & 0xe0 == 0xe0 => "mpeg",
+            _ => "unknown",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    /// A 16-bit PCM WAV file with `frames` frames of silence.
+    fn wav(sample_rate: u32, channels: u16, frames: u32) -> Vec<u8> {
+        let data_len = frames * channels as u32 * 2;
+        let mut file = Vec::new();
+        file.extend_from_slice(b"RIFF");
+        file.extend_from_slice(&(36 + data_len).to_le_bytes());
+        file.extend_from_slice(b"WAVEfmt ");
+        file.extend_from_slice(&16_u32.to_le_bytes());
+        file.extend_from_slice(&1_u16.to_le_bytes());
+        file.extend_from_slice(&channels.to_le_bytes());
+        file.extend_from_slice(&sample_rate.to_le_bytes());
+        file.extend_from_slice(&(sample_rate * channels as u32 * 2).to_le_bytes());
+        file.extend_from_slice(&(channels * 2).to_le_bytes());
+        file.extend_from_slice(&16_u16.to_le_bytes());
+        file.extend_from_slice(b"data");
+        file.extend_from_slice(&data_len.to_le_bytes());
+        file.resize(file.len() + data_len as usize, 0);
+        file
+    }
+
+    fn temp_file(contents: &[u8]) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(contents).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_probe_wav() {
+        let file = temp_file(&wav(16000, 2, 24000));
+        assert_eq!(
+            probe_file(file.path()).unwrap(),
+            MediaInfo {
+                container: "wav".into(),
+                codec: "pcm_s16le".into(),
+                sample_rate: Some(16000),
+                channels: Some(2),
+                duration_sec: Some(1.5),
+                bits_per_sample: Some(16),
+            }
+        );
+    }
+
+    #[test]
+    fn test_probe_rejects_non_audio() {
+        let file = temp_file(b"%PDF-1.7 definitely not audio");
+        assert!(probe_file(file.path()).is_err());
+
+        let mut truncated = wav(16000, 1, 16000);
+        truncated.truncate(40);
+        let file = temp_file(&truncated);
+        assert!(probe_file(file.path()).is_err());
+    }
+
+    #[test]
+    fn test_container_name() {
+        assert_eq!(container_name(b"fLaC\0\0\0\x22"), "flac");
+        assert_eq!(container_name(b"\0\0\0\x20ftypM4A "), "mp4");
+        assert_eq!(container_name(&[0xff, 0xfb, 0x90, 0x64]), "mpeg");
+        assert_eq!(container_name(&[0xff, 0xf1, 0x50, 0x80]), "adts");
+        assert_eq!(container_name(b""), "unknown");
+    }
+}
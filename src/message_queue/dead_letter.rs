@@ -0,0 +1,48 @@
+use crate::{AppState, message_queue::types::DeadLetter};
+
+/// A result message that could not be ingested.
+pub struct Failure {
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+    pub payload: Vec<u8>,
+    pub error: String,
+    pub attempts: i32,
+}
+
+/// Records the failure in the `dead_letters` table, where it can be replayed from, and
+/// publishes it to the dead-letter topic for external tooling.
+///
+/// Failures are only logged, so a broken database or broker never stops the receive loop.
+pub async fn store(state: &AppState, failure: Failure) {
+    let id = uuid::Uuid::new_v4();
+    let stored = sqlx::query!(
+        "INSERT INTO dead_letters (id, topic, kafka_partition, kafka_offset, payload, error, attempts) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        id,
+        failure.topic,
+        failure.partition,
+        failure.offset,
+        failure.payload,
+        failure.error,
+        failure.attempts,
+    )
+    .execute(&state.db)
+    .await;
+    if let Err(why) = stored {
+        tracing::error!("failed to store dead letter {id} in database: {why}");
+    }
+
+    let message = DeadLetter {
+        id,
+        source_topic: failure.topic,
+        partition: failure.partition,
+        offset: failure.offset,
+        error: failure.error,
+        attempts: failure.attempts,
+        failed_at: chrono::Utc::now(),
+        payload: String::from_utf8_lossy(&failure.payload).into_owned(),
+    };
+    if let Err(why) = state.bus.send_dead_letter(&message).await {
+        tracing::error!("failed to publish dead letter {id}: {why:?}");
+    }
+}
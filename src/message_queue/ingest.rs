@@ -0,0 +1,290 @@
+use crate::{
+    AppState,
+    events::{self, AnalysisEvent},
+    message_queue::types::{self, KafkaAnalysisResponse},
+    metric_catalog::{CatalogUpdate, MetricLevel},
+    webhooks::{self, WebhookEvent},
+};
+
+/// Why a result message could not be ingested.
+#[derive(Debug)]
+pub enum IngestError {
+    /// The message can never be processed, e.g. because it is malformed.
+    Poison(eyre::Report),
+    /// The message may be processed successfully if retried later.
+    Transient(eyre::Report),
+}
+
+impl IngestError {
+    pub fn report(&self) -> &eyre::Report {
+        match self {
+            IngestError::Poison(report) | IngestError::Transient(report) => report,
+        }
+    }
+}
+
+impl From<sqlx::Error> for IngestError {
+    fn from(error: sqlx::Error) -> Self {
+        let transient = match &error {
+            sqlx::Error::Io(_)
+            | sqlx::Error::Tls(_)
+            | sqlx::Error::PoolTimedOut
+            | sqlx::Error::PoolClosed
+            | sqlx::Error::WorkerCrashed => true,
+            // connection exceptions, serialization failures and deadlocks
+            sqlx::Error::Database(db) => db
+                .code()
+                .is_some_and(|code| code.starts_with("08") || code == "40001" || code == "40P01"),
+            _ => false,
+        };
+        if transient {
+            IngestError::Transient(error.into())
+        } else {
+            IngestError::Poison(error.into())
+        }
+    }
+}
+
+struct RunRef {
+    id: uuid::Uuid,
+    recording_id: uuid::Uuid,
+}
+
+/// Stores the content of a single result message.
+pub async fn handle_message(state: &AppState, content: &[u8]) -> Result<(), IngestError> {
+    let response: KafkaAnalysisResponse = serde_json::from_slice(content)
+        .map_err(|why| IngestError::Poison(eyre::eyre!("failed to deserialize message: {why}")))?;
+
+    let mut tx = state.db.begin().await?;
+    let mut run = sqlx::query_as!(
+        RunRef,
+        "SELECT id, recording_id FROM analysis_runs WHERE id=$1",
+        response.id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    if run.is_none() {
+        // requests submitted before analysis runs existed carry the recording id
+        run = sqlx::query_as!(
+            RunRef,
+            "SELECT analysis_runs.id, analysis_runs.recording_id
+            FROM analysis_runs
+            JOIN recordings ON recordings.latest_run = analysis_runs.id
+            WHERE recordings.id=$1",
+            response.id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+    }
+    let Some(run) = run else {
+        tracing::warn!("analysis run {} not found, skipping", response.id);
+        return Ok(());
+    };
+
+    // locking the run orders ingestion against cancellation, so no result lands in a run
+    // that was cancelled in the meantime; the recording is locked first, in the same order
+    // as cancellation and resubmission take them
+    sqlx::query!(
+        "SELECT id FROM recordings WHERE id=$1 FOR NO KEY UPDATE",
+        run.recording_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    let status = sqlx::query_scalar!(
+        "SELECT status FROM analysis_runs WHERE id=$1 FOR NO KEY UPDATE",
+        run.id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if status == "cancelled" {
+        tracing::info!(
+            "analysis run {} was cancelled, ignoring late result",
+            run.id
+        );
+        return Ok(());
+    }
+
+    match response.data {
+        types::KafkaAnalysisResponseInner::RecordingMetrics(recording_metrics) => {
+            let mut catalog = CatalogUpdate::default();
+            catalog.observe(MetricLevel::Recording, &recording_metrics.metrics);
+            // xmax is only zero for a fresh row, so a redelivery is not counted twice
+            let inserted = sqlx::query_scalar!(
+                r#"INSERT INTO recording_stats (run_id, recording_id, metrics_list) VALUES ($1, $2, $3)
+                ON CONFLICT (run_id) DO UPDATE SET metrics_list = EXCLUDED.metrics_list
+                RETURNING (xmax = 0) AS "inserted!""#,
+                run.id,
+                run.recording_id,
+                sqlx::types::Json(recording_metrics.metrics) as _
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+            if inserted {
+                catalog.record(&mut tx).await?;
+            }
+            let updated = sqlx::query!(
+                "UPDATE analysis_runs SET status='done', percent=100, finished_at=now(), last_update=now() WHERE id=$1 AND finished_at IS NULL",
+                run.id
+            )
+            .execute(&mut *tx)
+            .await?;
+            // a redelivered result only refreshes the stored metrics; subscribers were
+            // already told the first time
+            if updated.rows_affected() == 0 {
+                tx.commit().await?;
+                return Ok(());
+            }
+            publish(
+                &mut tx,
+                AnalysisEvent::Done {
+                    recording_id: run.recording_id,
+                    run_id: run.id,
+                },
+            )
+            .await?;
+            notify_webhooks(&mut tx, &run, WebhookEvent::AnalysisCompleted).await?;
+            tx.commit().await?;
+            webhooks::wake_delivery(state);
+        }
+        types::KafkaAnalysisResponseInner::ChannelMetrics(channel_metrics) => {
+            let idx_in_file = channel_metrics.idx;
+            let mut catalog = CatalogUpdate::default();
+            catalog.observe(MetricLevel::Channel, &channel_metrics.metrics);
+            for segment in &channel_metrics.segments {
+                catalog.observe(MetricLevel::Segment, &segment.metrics);
+            }
+            let channel = sqlx::query!(
+                r#"INSERT INTO channels (id, recording, run_id, idx_in_file, metrics_list) VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT (run_id, idx_in_file) DO UPDATE SET metrics_list = EXCLUDED.metrics_list
+                RETURNING id, (xmax = 0) AS "inserted!""#,
+                uuid::Uuid::new_v4(),
+                run.recording_id,
+                run.id,
+                idx_in_file,
+                sqlx::types::Json(channel_metrics.metrics) as _,
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+            let channel_id = channel.id;
+            // the segments were stored with the channel, so a redelivered message only
+            // refreshes the channel metrics and keeps the segment ids clients already saw
+            if !channel.inserted {
+                tx.commit().await?;
+                return Ok(());
+            }
+            catalog.record(&mut tx).await?;
+
+            for segment in channel_metrics.segments {
+                let segment_id = uuid::Uuid::new_v4();
+                sqlx::query!(
+                    "INSERT INTO segments (id, channel, start_sec, end_sec, content, metrics_list) VALUES ($1, $2, $3, $4, $5, $6)",
+                    segment_id,
+                    channel_id,
+                    segment.start,
+                    segment.end,
+                    segment.text,
+                    sqlx::types::Json(segment.metrics) as _,
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            sqlx::query!(
+                "UPDATE analysis_runs SET status='running', started_at=COALESCE(started_at, now()), last_update=now() WHERE id=$1 AND finished_at IS NULL",
+                run.id
+            )
+            .execute(&mut *tx)
+            .await?;
+            publish(
+                &mut tx,
+                AnalysisEvent::ChannelCompleted {
+                    recording_id: run.recording_id,
+                    run_id: run.id,
+                    channel_id,
+                    idx_in_file,
+                },
+            )
+            .await?;
+
+            tx.commit().await?;
+        }
+        types::KafkaAnalysisResponseInner::ProgressMsg(progress_msg) => {
+            let updated = sqlx::query!(
+                "UPDATE analysis_runs SET status='running', percent=$1, description=$2, channel=$3, started_at=COALESCE(started_at, now()), last_update=now() WHERE id=$4 AND finished_at IS NULL",
+                progress_msg.percent_done.unwrap_or_default(),
+                progress_msg.description,
+                progress_msg.channel,
+                run.id
+            )
+            .execute(&mut *tx)
+            .await?;
+            if updated.rows_affected() > 0 {
+                publish(
+                    &mut tx,
+                    AnalysisEvent::Progress {
+                        recording_id: run.recording_id,
+                        run_id: run.id,
+                        percent: progress_msg.percent_done,
+                        description: progress_msg.description,
+                        channel: progress_msg.channel,
+                    },
+                )
+                .await?;
+            }
+            tx.commit().await?;
+        }
+        types::KafkaAnalysisResponseInner::ErrorMsg(error_msg) => {
+            // a late or redelivered error does not overwrite a finished run
+            let updated = sqlx::query!(
+                "UPDATE analysis_runs SET status='error', finished_at=now(), last_update=now(), error=$1 WHERE id=$2 AND finished_at IS NULL",
+                format!("{error_msg:?}"),
+                run.id
+            )
+            .execute(&mut *tx)
+            .await?;
+            if updated.rows_affected() == 0 {
+                tracing::info!("analysis run {} already finished, ignoring error", run.id);
+                return Ok(());
+            }
+            publish(
+                &mut tx,
+                AnalysisEvent::Error {
+                    recording_id: run.recording_id,
+                    run_id: run.id,
+                    error: error_msg.error.clone(),
+                },
+            )
+            .await?;
+            notify_webhooks(
+                &mut tx,
+                &run,
+                WebhookEvent::AnalysisFailed {
+                    error: error_msg.error.clone(),
+                },
+            )
+            .await?;
+            tx.commit().await?;
+            webhooks::wake_delivery(state);
+            tracing::warn!("analysis run {} failed: {error_msg:?}", run.id);
+        }
+    }
+
+    Ok(())
+}
+
+/// Publishes an event within the ingestion transaction.
+async fn publish(conn: &mut sqlx::PgConnection, event: AnalysisEvent) -> Result<(), IngestError> {
+    events::publish(conn, &event)
+        .await
+        .map_err(IngestError::Transient)
+}
+
+/// Queues webhook deliveries within the ingestion transaction.
+async fn notify_webhooks(
+    conn: &mut sqlx::PgConnection,
+    run: &RunRef,
+    event: WebhookEvent,
+) -> Result<(), IngestError> {
+    webhooks::enqueue(conn, run.recording_id, run.id, &event)
+        .await
+        .map_err(IngestError::Transient)
+}
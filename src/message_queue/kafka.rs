@@ -0,0 +1,114 @@
+use std::{env::var, sync::Arc};
+
+use eyre::Context;
+use rdkafka::{
+    Message, Offset, TopicPartitionList,
+    config::RDKafkaLogLevel,
+    consumer::{CommitMode, Consumer, StreamConsumer},
+    producer::{FutureProducer, FutureRecord},
+};
+
+use crate::message_queue::{Delivery, MessageBus, types};
+
+/// Message bus backed by a Kafka broker.
+#[derive(Clone)]
+pub struct KafkaBus {
+    tx_tasks: FutureProducer,
+    rx_results: Arc<StreamConsumer>,
+}
+
+impl KafkaBus {
+    /// Connects to the brokers listed in `KAFKA_BOOTSTRAP_SERVERS`, `localhost:9092` by default.
+    pub fn from_env() -> eyre::Result<Self> {
+        let servers = var("KAFKA_BOOTSTRAP_SERVERS").unwrap_or_else(|_| "localhost:9092".into());
+
+        let mut conf = rdkafka::ClientConfig::new();
+        conf.set("bootstrap.servers", servers);
+        conf.set("message.timeout.ms", "5000");
+        conf.set_log_level(RDKafkaLogLevel::Debug);
+
+        Ok(KafkaBus {
+            tx_tasks: conf
+                .clone()
+                .create()
+                .wrap_err("failed to create kafka producer")?,
+            rx_results: Arc::new(
+                conf.clone()
+                    .set("group.id", "backend-app")
+                    .set("allow.auto.create.topics", "true")
+                    // offsets are committed once a message is stored, see `MessageBus::commit`;
+                    // auto-commit would also commit messages that are still being retried
+                    .set("enable.auto.commit", "false")
+                    .create()
+                    .wrap_err("failed to create kafka consumer")?,
+            ),
+        })
+    }
+
+    /// Resolves once the broker acknowledged the message, or fails after
+    /// `message.timeout.ms`.
+    async fn send_json(&self, topic: &str, value: &impl serde::Serialize) -> eyre::Result<()> {
+        let bytes = serde_json::to_vec(value).wrap_err("failed to serialize message")?;
+        let record: FutureRecord<'_, (), Vec<u8>> = FutureRecord::to(topic).payload(&bytes);
+        self.tx_tasks
+            .send(record, None)
+            .await
+            .map_err(|(why, _)| why)?;
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl MessageBus for KafkaBus {
+    async fn send_request(&self, request: &types::AnalysisRequest) -> eyre::Result<()> {
+        tracing::info!("sending request to kafka: {request:?}");
+        self.send_json("analysis_requests", request)
+            .await
+            .wrap_err("failed to send request to kafka")
+    }
+
+    async fn send_control(&self, control: &types::AnalysisControl) -> eyre::Result<()> {
+        tracing::info!("sending control message to kafka: {control:?}");
+        self.send_json("analysis_control", control)
+            .await
+            .wrap_err("failed to send control message to kafka")
+    }
+
+    async fn send_dead_letter(&self, dead_letter: &types::DeadLetter) -> eyre::Result<()> {
+        self.send_json("metrics_output.dlq", dead_letter)
+            .await
+            .wrap_err("failed to send dead letter to kafka")
+    }
+
+    async fn subscribe(&self) -> eyre::Result<()> {
+        self.rx_results
+            .subscribe(&["metrics_output"])
+            .wrap_err("failed to subscribe to topic 'metrics_output'")
+    }
+
+    async fn recv(&self) -> eyre::Result<Delivery> {
+        let message = self
+            .rx_results
+            .recv()
+            .await
+            .wrap_err("failed to receive message from kafka")?;
+        Ok(Delivery {
+            topic: message.topic().to_owned(),
+            partition: message.partition(),
+            offset: message.offset(),
+            payload: message.payload().map(<[u8]>::to_vec),
+        })
+    }
+
+    async fn commit(&self, delivery: &Delivery) -> eyre::Result<()> {
+        let mut offsets = TopicPartitionList::new();
+        offsets.add_partition_offset(
+            &delivery.topic,
+            delivery.partition,
+            Offset::Offset(delivery.offset + 1),
+        )?;
+        self.rx_results
+            .commit(&offsets, CommitMode::Sync)
+            .wrap_err("failed to commit kafka offset")
+    }
+}
@@ -0,0 +1,258 @@
+use std::{
+    collections::HashSet,
+    sync::atomic::{AtomicI64, Ordering},
+};
+
+use eyre::eyre;
+use tokio::sync::{Mutex, mpsc};
+
+use crate::message_queue::{
+    Delivery, MessageBus,
+    types::{self, AnalysisControlInner, KafkaAnalysisResponseInner, Metric, MetricCollection},
+};
+
+const RESULTS_TOPIC: &str = "metrics_output";
+
+/// Message bus that keeps all traffic inside the process, for tests and local demos.
+pub struct InMemoryBus {
+    requests: mpsc::UnboundedSender<types::AnalysisRequest>,
+    controls: mpsc::UnboundedSender<types::AnalysisControl>,
+    results: Mutex<mpsc::UnboundedReceiver<Vec<u8>>>,
+    dead_letters: Mutex<Vec<types::DeadLetter>>,
+    next_offset: AtomicI64,
+}
+
+/// The worker side of an [`InMemoryBus`].
+pub struct WorkerChannels {
+    pub requests: mpsc::UnboundedReceiver<types::AnalysisRequest>,
+    pub controls: mpsc::UnboundedReceiver<types::AnalysisControl>,
+    /// Raw result payloads, so tests can also feed malformed messages.
+    pub results: mpsc::UnboundedSender<Vec<u8>>,
+}
+
+impl InMemoryBus {
+    pub fn new() -> (Self, WorkerChannels) {
+        let (requests_tx, requests_rx) = mpsc::unbounded_channel();
+        let (controls_tx, controls_rx) = mpsc::unbounded_channel();
+        let (results_tx, results_rx) = mpsc::unbounded_channel();
+        let bus = InMemoryBus {
+            requests: requests_tx,
+            controls: controls_tx,
+            results: Mutex::new(results_rx),
+            dead_letters: Mutex::new(Vec::new()),
+            next_offset: AtomicI64::new(0),
+        };
+        let worker = WorkerChannels {
+            requests: requests_rx,
+            controls: controls_rx,
+            results: results_tx,
+        };
+        (bus, worker)
+    }
+
+    /// Dead letters published so far.
+    pub async fn dead_letters(&self) -> Vec<types::DeadLetter> {
+        self.dead_letters.lock().await.clone()
+    }
+}
+
+#[async_trait::async_trait]
+impl MessageBus for InMemoryBus {
+    async fn send_request(&self, request: &types::AnalysisRequest) -> eyre::Result<()> {
+        self.requests
+            .send(request.clone())
+            .map_err(|_| eyre!("in-memory analysis worker has stopped"))
+    }
+
+    async fn send_control(&self, control: &types::AnalysisControl) -> eyre::Result<()> {
+        self.controls
+            .send(control.clone())
+            .map_err(|_| eyre!("in-memory analysis worker has stopped"))
+    }
+
+    async fn send_dead_letter(&self, dead_letter: &types::DeadLetter) -> eyre::Result<()> {
+        self.dead_letters.lock().await.push(dead_letter.clone());
+        Ok(())
+    }
+
+    async fn subscribe(&self) -> eyre::Result<()> {
+        Ok(())
+    }
+
+    async fn recv(&self) -> eyre::Result<Delivery> {
+        let payload = self
+            .results
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or_else(|| eyre!("in-memory analysis worker has stopped"))?;
+        Ok(Delivery {
+            topic: RESULTS_TOPIC.into(),
+            partition: 0,
+            offset: self.next_offset.fetch_add(1, Ordering::Relaxed),
+            payload: Some(payload),
+        })
+    }
+
+    async fn commit(&self, _delivery: &Delivery) -> eyre::Result<()> {
+        Ok(())
+    }
+}
+
+/// Answers every request with synthetic results, so the upload → analysis → ingest flow
+/// can run without a broker or the real analysis service.
+pub async fn demo_worker(mut channels: WorkerChannels) {
+    let mut cancelled = HashSet::new();
+    while let Some(request) = channels.requests.recv().await {
+        while let Ok(control) = channels.controls.try_recv() {
+            match control.data {
+                AnalysisControlInner::Cancel => cancelled.insert(control.id),
+            };
+        }
+        if cancelled.contains(&request.id) {
+            continue;
+        }
+
+        tracing::info!("demo worker analyzing {}", request.data.download_url);
+        for data in demo_results() {
+            let response = types::KafkaAnalysisResponse {
+                id: request.id,
+                data,
+            };
+            let payload = serde_json::to_vec(&response).expect("results should serialize");
+            if channels.results.send(payload).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+fn demo_results() -> Vec<KafkaAnalysisResponseInner> {
+    let segments = (0..3)
+        .map(|i| types::Segment {
+            start: i as f32 * 2.0,
+            end: i as f32 * 2.0 + 1.5,
+            text: format!("demo segment {i}"),
+            metrics: vec![MetricCollection {
+                provider: "demo".into(),
+                description: Some("synthetic metrics from the in-memory worker".into()),
+                metrics: vec![
+                    Metric::Float {
+                        name: "energy".into(),
+                        value: Some(0.25 * (i + 1) as f32),
+                        description: None,
+                        unit: None,
+                    },
+                    Metric::String {
+                        name: "label".into(),
+                        value: Some(if i % 2 == 0 { "neutral" } else { "happy" }.into()),
+                        description: None,
+                        unit: None,
+                    },
+                ],
+            }],
+        })
+        .collect();
+
+    vec![
+        KafkaAnalysisResponseInner::ProgressMsg(types::ProgressMsg {
+            percent_done: Some(0),
+            channel: Some(0),
+            description: Some("starting demo analysis".into()),
+        }),
+        KafkaAnalysisResponseInner::ChannelMetrics(types::ChannelMetrics {
+            idx: 0,
+            segments,
+            metrics: Vec::new(),
+        }),
+        KafkaAnalysisResponseInner::ProgressMsg(types::ProgressMsg {
+            percent_done: Some(90),
+            channel: None,
+            description: Some("summarizing".into()),
+        }),
+        KafkaAnalysisResponseInner::RecordingMetrics(types::RecordingMetrics {
+            metrics: vec![MetricCollection {
+                provider: "demo".into(),
+                description: None,
+                metrics: vec![Metric::Int {
+                    name: "channels".into(),
+                    value: Some(1),
+                    description: None,
+                    unit: None,
+                }],
+            }],
+        }),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_queue::types::{AnalysisRequestInner, KafkaEnvelope};
+
+    #[tokio::test]
+    async fn test_demo_worker_answers_requests() {
+        let (bus, worker) = InMemoryBus::new();
+        tokio::spawn(demo_worker(worker));
+        bus.subscribe().await.unwrap();
+
+        let id = uuid::Uuid::new_v4();
+        bus.send_request(&request(id)).await.unwrap();
+
+        let mut received = Vec::new();
+        loop {
+            let delivery = bus.recv().await.unwrap();
+            let response: types::KafkaAnalysisResponse =
+                serde_json::from_slice(delivery.payload.as_deref().unwrap()).unwrap();
+            bus.commit(&delivery).await.unwrap();
+            assert_eq!(response.id, id);
+            let done = matches!(
+                response.data,
+                KafkaAnalysisResponseInner::RecordingMetrics(_)
+            );
+            received.push(response.data);
+            if done {
+                break;
+            }
+        }
+
+        assert!(
+            received
+                .iter()
+                .any(|data| matches!(data, KafkaAnalysisResponseInner::ChannelMetrics(_)))
+        );
+    }
+
+    fn request(id: uuid::Uuid) -> types::AnalysisRequest {
+        KafkaEnvelope {
+            id,
+            data: AnalysisRequestInner {
+                download_url: "memory://recording".into(),
+                transcript_url: None,
+                force_diarize: None,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_demo_worker_skips_cancelled_runs() {
+        let (bus, worker) = InMemoryBus::new();
+        let cancelled = uuid::Uuid::new_v4();
+        let active = uuid::Uuid::new_v4();
+        bus.send_control(&KafkaEnvelope {
+            id: cancelled,
+            data: AnalysisControlInner::Cancel,
+        })
+        .await
+        .unwrap();
+        bus.send_request(&request(cancelled)).await.unwrap();
+        bus.send_request(&request(active)).await.unwrap();
+        tokio::spawn(demo_worker(worker));
+
+        let delivery = bus.recv().await.unwrap();
+        let response: types::KafkaAnalysisResponse =
+            serde_json::from_slice(delivery.payload.as_deref().unwrap()).unwrap();
+        assert_eq!(response.id, active);
+    }
+}
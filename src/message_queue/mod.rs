@@ -1,156 +1,119 @@
+pub mod dead_letter;
+pub mod ingest;
+pub mod kafka;
+pub mod memory;
 pub mod types;
 
-use std::sync::Arc;
+use std::time::Duration;
 
-use eyre::Context;
-use rdkafka::{
-    Message,
-    consumer::Consumer,
-    producer::{FutureRecord, Producer},
-    util::Timeout,
-};
+use crate::{AppState, message_queue::ingest::IngestError};
 
-use crate::{AppState, message_queue::types::KafkaAnalysisResponse};
-
-#[derive(Clone)]
-pub struct KafkaKonnections {
-    pub tx_tasks: rdkafka::producer::FutureProducer,
-    pub rx_results: Arc<tokio::sync::Mutex<rdkafka::consumer::StreamConsumer>>,
+/// A message taken off the results channel.
+#[derive(Debug, Clone)]
+pub struct Delivery {
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+    pub payload: Option<Vec<u8>>,
 }
 
-impl KafkaKonnections {
-    pub async fn send_request(&self, request: &types::AnalysisRequest) -> eyre::Result<()> {
-        tracing::info!("sending request to kafka: {request:?}");
-        let bytes = serde_json::to_vec(request).unwrap();
-        let record: FutureRecord<'_, (), Vec<u8>> =
-            FutureRecord::to("analysis_requests").payload(&bytes);
-        self.tx_tasks
-            .send(record, None)
-            .await
-            .map_err(|v| v.0)
-            .wrap_err("failed to send request to kafka")?;
-        self.tx_tasks
-            .flush(Timeout::Never)
-            .wrap_err("failed to flush kafka")?;
-        Ok(())
-    }
+/// Transport between the backend and the analysis workers.
+///
+/// Requests and control messages flow to the workers, results flow back and are
+/// acknowledged with [`MessageBus::commit`] once they have been stored.
+#[async_trait::async_trait]
+pub trait MessageBus: Send + Sync {
+    async fn send_request(&self, request: &types::AnalysisRequest) -> eyre::Result<()>;
+
+    async fn send_control(&self, control: &types::AnalysisControl) -> eyre::Result<()>;
+
+    async fn send_dead_letter(&self, dead_letter: &types::DeadLetter) -> eyre::Result<()>;
+
+    /// Starts listening for results; called once before the first [`MessageBus::recv`].
+    async fn subscribe(&self) -> eyre::Result<()>;
+
+    async fn recv(&self) -> eyre::Result<Delivery>;
+
+    async fn commit(&self, delivery: &Delivery) -> eyre::Result<()>;
 }
 
 pub async fn recv_loop(state: AppState) -> eyre::Result<()> {
-    {
-        let receiver = state.kafka.rx_results.lock().await;
-        receiver
-            .subscribe(&["metrics_output"])
-            .wrap_err("failed to subscribe to topic 'metrics_output'")?;
-        drop(receiver)
-    }
+    state.bus.subscribe().await?;
 
-    println!("waiting for messages");
+    tracing::debug!("listening for analysis results");
 
     loop {
-        tracing::debug!("waiting for receiver lock");
-        let receiver = state.kafka.rx_results.lock().await;
         tracing::debug!("waiting for message");
-        let recv = receiver.recv().await?;
-        tracing::debug!("got message");
-        let Some(content) = recv.payload() else {
-            tracing::debug!("message had no content, skipping");
-            continue;
-        };
-        println!("got message: {}", String::from_utf8_lossy(content));
-        let response: KafkaAnalysisResponse = match serde_json::from_slice(content) {
-            Ok(response) => response,
+        let recv = match state.bus.recv().await {
+            Ok(recv) => recv,
             Err(why) => {
-                tracing::warn!("failed to deserialize message: {why}");
+                tracing::error!("failed to receive message: {why:?}");
+                tokio::time::sleep(Duration::from_secs(1)).await;
                 continue;
             }
         };
-
-        let Some(_) = sqlx::query!("SELECT id FROM recordings WHERE id=$1", response.id)
-            .fetch_optional(&state.db)
-            .await?
-        else {
-            tracing::warn!("recording not found, skipping");
+        tracing::debug!("got message");
+        let Some(content) = &recv.payload else {
+            tracing::debug!("message had no content, skipping");
             continue;
         };
+        tracing::debug!("message content: {}", String::from_utf8_lossy(content));
 
-        match response.data {
-            types::KafkaAnalysisResponseInner::RecordingMetrics(recording_metrics) => {
-                let mut tx = state.db.begin().await?;
-                sqlx::query!(
-                    "INSERT INTO recording_stats (recording_id, metrics_list) VALUES ($1, $2)",
-                    response.id,
-                    sqlx::types::Json(recording_metrics.metrics) as _
-                )
-                .execute(&mut *tx)
-                .await?;
-                sqlx::query!(
-                    "UPDATE recordings SET analysis_status='done', analysis_percent=100, analysis_last_update=now() WHERE id=$1",
-                    response.id
-                )
-                .execute(&mut *tx)
-                .await?;
-                tx.commit().await?;
-            }
-            types::KafkaAnalysisResponseInner::ChannelMetrics(channel_metrics) => {
-                let channel_id = uuid::Uuid::new_v4();
-                let mut tx = state.db.begin().await?;
-                sqlx::query!(
-                    "INSERT INTO channels (id, recording, idx_in_file, metrics_list) VALUES ($1, $2, $3, $4)",
-                    channel_id,
-                    response.id,
-                    channel_metrics.idx,
-                    sqlx::types::Json(channel_metrics.metrics) as _,
-                )
-                .execute(&mut *tx)
-                .await?;
-
-                for segment in channel_metrics.segments {
-                    let segment_id = uuid::Uuid::new_v4();
-                    sqlx::query!(
-                        "INSERT INTO segments (id, channel, start_sec, end_sec, content, metrics_list) VALUES ($1, $2, $3, $4, $5, $6)",
-                        segment_id,
-                        channel_id,
-                        segment.start,
-                        segment.end,
-                        segment.text,
-                        sqlx::types::Json(segment.metrics) as _,
-                    )
-                    .execute(&mut *tx)
-                    .await?;
-                }
-                sqlx::query!(
-                    "UPDATE recordings SET analysis_status='running', analysis_last_update=now() WHERE id=$1",
-                    response.id
-                )
-                .execute(&mut *tx)
-                .await?;
-
-                tx.commit().await?;
-            }
-            types::KafkaAnalysisResponseInner::ProgressMsg(progress_msg) => {
-                sqlx::query!(
-                    "UPDATE recordings SET analysis_status='running', analysis_percent=$1, analysis_description=$2, analysis_channel=$3, analysis_last_update=now() WHERE id=$4",
-                    progress_msg.percent_done.unwrap_or_default(),
-                    progress_msg.description,
-                    progress_msg.channel,
-                    response.id
-                )
-                .execute(&state.db)
-                .await?;
-            }
-            types::KafkaAnalysisResponseInner::ErrorMsg(error_msg) => {
-                sqlx::query!(
-                    "UPDATE recordings SET analysis_status='error', analysis_last_update=now(), analysis_error=$1 WHERE id=$2",
-                    format!("{error_msg:?}"),
-                    response.id
-                )
-                .execute(&state.db)
-                .await?;
-                println!("ERROR: {error_msg:?}")
-            }
+        if let Err(failure) = handle_with_retry(&state, content).await {
+            tracing::error!(
+                "failed to ingest message at {}:{}:{}, moving it to the dead-letter queue: {:?}",
+                recv.topic,
+                recv.partition,
+                recv.offset,
+                failure.error
+            );
+            dead_letter::store(
+                &state,
+                dead_letter::Failure {
+                    topic: recv.topic.clone(),
+                    partition: recv.partition,
+                    offset: recv.offset,
+                    payload: content.clone(),
+                    error: format!("{:?}", failure.error),
+                    attempts: failure.attempts,
+                },
+            )
+            .await;
         }
 
-        receiver.commit_message(&recv, rdkafka::consumer::CommitMode::Sync)?;
+        if let Err(why) = state.bus.commit(&recv).await {
+            tracing::error!("failed to commit message: {why:?}");
+        }
+    }
+}
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// A message that can never be ingested.
+struct Poisoned {
+    error: eyre::Report,
+    attempts: i32,
+}
+
+/// Retries transient failures with exponential backoff for as long as they last, so no
+/// message is committed or dead-lettered while e.g. the database is down; poison messages
+/// fail immediately.
+async fn handle_with_retry(state: &AppState, content: &[u8]) -> Result<(), Poisoned> {
+    let mut backoff = INITIAL_BACKOFF;
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        match ingest::handle_message(state, content).await {
+            Ok(()) => return Ok(()),
+            Err(IngestError::Transient(why)) => {
+                tracing::warn!(
+                    "transient error while ingesting message (attempt {attempts}), retrying in {backoff:?}: {why}"
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+            Err(IngestError::Poison(error)) => return Err(Poisoned { error, attempts }),
+        }
     }
 }
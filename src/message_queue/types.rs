@@ -2,6 +2,7 @@ use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct KafkaEnvelope<T> {
+    /// Id of the analysis run the message belongs to.
     pub id: uuid::Uuid,
     pub data: T,
 }
@@ -15,6 +16,14 @@ pub struct AnalysisRequestInner {
 
 pub type AnalysisRequest = KafkaEnvelope<AnalysisRequestInner>;
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "_kind")]
+pub enum AnalysisControlInner {
+    Cancel,
+}
+
+pub type AnalysisControl = KafkaEnvelope<AnalysisControlInner>;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EmotionMetrics {
     arousal: f32,
@@ -66,6 +75,19 @@ pub enum KafkaAnalysisResponseInner {
 
 pub type KafkaAnalysisResponse = KafkaEnvelope<KafkaAnalysisResponseInner>;
 
+/// A result message that could not be ingested, as published to the dead-letter topic.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub id: uuid::Uuid,
+    pub source_topic: String,
+    pub partition: i32,
+    pub offset: i64,
+    pub error: String,
+    pub attempts: i32,
+    pub failed_at: chrono::DateTime<chrono::Utc>,
+    pub payload: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MetricCollection {
     pub provider: String,
@@ -103,6 +125,39 @@ pub enum Metric {
     },
 }
 
+impl Metric {
+    pub fn name(&self) -> &str {
+        match self {
+            Metric::Int { name, .. }
+            | Metric::Float { name, .. }
+            | Metric::String { name, .. }
+            | Metric::Bool { name, .. } => name,
+        }
+    }
+
+    /// Value of a numeric metric, `None` for strings, booleans and missing values.
+    ///
+    /// Floats are widened through their shortest representation, as they are stored in
+    /// JSON, so `0.1` stays `0.1` instead of `0.10000000149`.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Metric::Int { value, .. } => value.map(|v| v as f64),
+            Metric::Float { value, .. } => value.and_then(|v| v.to_string().parse().ok()),
+            Metric::String { .. } | Metric::Bool { .. } => None,
+        }
+    }
+
+    /// The value as text, as written into annotation labels.
+    pub fn value_label(&self) -> Option<String> {
+        match self {
+            Metric::Int { value, .. } => value.map(|v| v.to_string()),
+            Metric::Float { value, .. } => value.map(|v| v.to_string()),
+            Metric::String { value, .. } => value.clone(),
+            Metric::Bool { value, .. } => value.map(|v| v.to_string()),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -122,7 +177,7 @@ mod tests {
             metric,
             Metric::Int {
                 name: "test".to_string(),
-                value: 1,
+                value: Some(1),
                 description: Some("test".to_string()),
                 unit: None
             }
@@ -0,0 +1,208 @@
+//! The catalog of every metric the analysis service has reported.
+//!
+//! Ingestion folds each result into [`CatalogUpdate`] and adds it to the `metric_catalog`
+//! table, which keeps one row per provider, metric name, level and value type with
+//! running counts and the range of numeric values. Rows are never decremented, so the
+//! counts describe everything observed, including results of deleted recordings.
+
+use std::collections::BTreeMap;
+
+use sqlx::PgConnection;
+
+use crate::message_queue::types::{Metric, MetricCollection};
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum MetricLevel {
+    Recording,
+    Channel,
+    Segment,
+}
+
+impl MetricLevel {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MetricLevel::Recording => "recording",
+            MetricLevel::Channel => "channel",
+            MetricLevel::Segment => "segment",
+        }
+    }
+}
+
+/// The type tag `Metric` is serialized with.
+fn value_type(metric: &Metric) -> &'static str {
+    match metric {
+        Metric::Int { .. } => "int",
+        Metric::Float { .. } => "float",
+        Metric::String { .. } => "str",
+        Metric::Bool { .. } => "bool",
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct CatalogKey {
+    provider: String,
+    name: String,
+    level: MetricLevel,
+    value_type: &'static str,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct CatalogStats {
+    unit: Option<String>,
+    description: Option<String>,
+    provider_description: Option<String>,
+    value_count: i64,
+    null_count: i64,
+    min_value: Option<f64>,
+    max_value: Option<f64>,
+}
+
+/// Observations of one result message, to be added to the catalog in one go.
+#[derive(Debug, Default)]
+pub struct CatalogUpdate {
+    // ordered, so concurrent ingestions lock catalog rows in the same order
+    entries: BTreeMap<CatalogKey, CatalogStats>,
+}
+
+impl CatalogUpdate {
+    pub fn observe(&mut self, level: MetricLevel, collections: &[MetricCollection]) {
+        for collection in collections {
+            for metric in &collection.metrics {
+                let (unit, description) = match metric {
+                    Metric::Int {
+                        unit, description, ..
+                    }
+                    | Metric::Float {
+                        unit, description, ..
+                    }
+                    | Metric::String {
+                        unit, description, ..
+                    }
+                    | Metric::Bool {
+                        unit, description, ..
+                    } => (unit, description),
+                };
+                let stats = self
+                    .entries
+                    .entry(CatalogKey {
+                        provider: collection.provider.clone(),
+                        name: metric.name().to_owned(),
+                        level,
+                        value_type: value_type(metric),
+                    })
+                    .or_default();
+                if unit.is_some() {
+                    stats.unit.clone_from(unit);
+                }
+                if description.is_some() {
+                    stats.description.clone_from(description);
+                }
+                if collection.description.is_some() {
+                    stats
+                        .provider_description
+                        .clone_from(&collection.description);
+                }
+                match metric.value_label() {
+                    Some(_) => stats.value_count += 1,
+                    None => stats.null_count += 1,
+                }
+                if let Some(value) = metric.as_f64() {
+                    stats.min_value = Some(stats.min_value.map_or(value, |min| min.min(value)));
+                    stats.max_value = Some(stats.max_value.map_or(value, |max| max.max(value)));
+                }
+            }
+        }
+    }
+
+    /// Adds the observations to the catalog on the caller's connection.
+    pub async fn record(&self, conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+        for (key, stats) in &self.entries {
+            sqlx::query!(
+                "INSERT INTO metric_catalog (
+                    provider, name, level, value_type, unit, description, provider_description,
+                    value_count, null_count, min_value, max_value
+                ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+                ON CONFLICT (provider, name, level, value_type) DO UPDATE SET
+                    unit = COALESCE(EXCLUDED.unit, metric_catalog.unit),
+                    description = COALESCE(EXCLUDED.description, metric_catalog.description),
+                    provider_description = COALESCE(EXCLUDED.provider_description, metric_catalog.provider_description),
+                    value_count = metric_catalog.value_count + EXCLUDED.value_count,
+                    null_count = metric_catalog.null_count + EXCLUDED.null_count,
+                    min_value = LEAST(metric_catalog.min_value, EXCLUDED.min_value),
+                    max_value = GREATEST(metric_catalog.max_value, EXCLUDED.max_value),
+                    last_seen_at = now()",
+                key.provider,
+                key.name,
+                key.level.as_str(),
+                key.value_type,
+                stats.unit,
+                stats.description,
+                stats.provider_description,
+                stats.value_count,
+                stats.null_count,
+                stats.min_value,
+                stats.max_value,
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_observe() {
+        let collections = vec![MetricCollection {
+            provider: "emotion".into(),
+            description: Some("emotion recognition".into()),
+            metrics: vec![
+                Metric::Float {
+                    name: "valence".into(),
+                    value: Some(-0.5),
+                    description: None,
+                    unit: None,
+                },
+                Metric::Float {
+                    name: "valence".into(),
+                    value: None,
+                    description: Some("pleasantness".into()),
+                    unit: None,
+                },
+                Metric::Int {
+                    name: "valence".into(),
+                    value: Some(1),
+                    description: None,
+                    unit: Some("points".into()),
+                },
+            ],
+        }];
+        let mut update = CatalogUpdate::default();
+        update.observe(MetricLevel::Segment, &collections);
+        update.observe(MetricLevel::Segment, &collections);
+
+        let entries: Vec<_> = update.entries.iter().collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].0.value_type, "float");
+        assert_eq!(
+            entries[0].1,
+            &CatalogStats {
+                unit: None,
+                description: Some("pleasantness".into()),
+                provider_description: Some("emotion recognition".into()),
+                value_count: 2,
+                null_count: 2,
+                min_value: Some(-0.5),
+                max_value: Some(-0.5),
+            }
+        );
+        assert_eq!(entries[1].0.value_type, "int");
+        assert_eq!(entries[1].1.unit.as_deref(), Some("points"));
+    }
+}
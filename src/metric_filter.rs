@@ -0,0 +1,490 @@
+//! A small filter language over segment metrics.
+//!
+//! A filter compares metrics with literals and combines the comparisons with `and`, `or`,
+//! `not` and parentheses:
+//!
+//! ```text
+//! emotion2vec.valence < -0.3 and not (speech_rate > 5 or asr.language = 'en')
+//! ```
+//!
+//! A metric is written as `provider.name`, or as `name` to match it from any provider;
+//! names that are not plain words can be double-quoted. Literals are numbers, single-quoted
+//! strings (`''` for a quote) and `true` or `false`. Numbers only match `Int` and `Float`
+//! metrics, strings `String` metrics and booleans `Bool` metrics; strings and booleans can
+//! only be compared with `=` and `!=`.
+//!
+//! A filter compiles into a single SQL/JSON path predicate for `metrics_list @@ ...`,
+//! which a `jsonb_path_ops` GIN index on `metrics_list` can serve.
+
+use std::fmt;
+
+/// Nesting deeper than this is rejected, so parsing cannot overflow the stack.
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    Compare(Comparison),
+    Not(Box<Filter>),
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub provider: Option<String>,
+    pub metric: String,
+    pub op: Op,
+    pub value: Literal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Op {
+    fn symbol(self) -> &'static str {
+        match self {
+            Op::Eq => "==",
+            Op::Ne => "!=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Number(f64),
+    String(String),
+    Bool(bool),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilterError {
+    /// 1-based character position the error was found at.
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "column {}: {}", self.column, self.message)
+    }
+}
+
+impl std::error::Error for FilterError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Number(f64),
+    String(String),
+    Op(Op),
+    Dot,
+    Open,
+    Close,
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Word(word) => format!("'{word}'"),
+            Token::Quoted(name) => format!("\"{name}\""),
+            Token::Number(value) => format!("the number {value}"),
+            Token::String(_) => "a string".into(),
+            Token::Op(op) => format!("'{}'", op.symbol().replace("==", "=")),
+            Token::Dot => "'.'".into(),
+            Token::Open => "'('".into(),
+            Token::Close => "')'".into(),
+        }
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self, Token::Word(word) if word.eq_ignore_ascii_case(keyword))
+    }
+}
+
+/// Splits the filter into tokens with their 1-based columns.
+fn tokenize(input: &str) -> Result<Vec<(usize, Token)>, FilterError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let column = i + 1;
+        let error = |message: String| FilterError { column, message };
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        let token = match c {
+            _ if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '.' if !next.is_some_and(|next| next.is_ascii_digit()) => Token::Dot,
+            '=' if next == Some('=') => {
+                i += 1;
+                Token::Op(Op::Eq)
+            }
+            '=' => Token::Op(Op::Eq),
+            '!' if next == Some('=') => {
+                i += 1;
+                Token::Op(Op::Ne)
+            }
+            '<' if next == Some('>') => {
+                i += 1;
+                Token::Op(Op::Ne)
+            }
+            '<' if next == Some('=') => {
+                i += 1;
+                Token::Op(Op::Le)
+            }
+            '<' => Token::Op(Op::Lt),
+            '>' if next == Some('=') => {
+                i += 1;
+                Token::Op(Op::Ge)
+            }
+            '>' => Token::Op(Op::Gt),
+            '\'' | '"' => {
+                let mut text = String::new();
+                loop {
+                    i += 1;
+                    match chars.get(i) {
+                        None => return Err(error("unterminated quote".into())),
+                        Some(&quote) if quote == c && chars.get(i + 1) == Some(&c) => {
+                            text.push(c);
+                            i += 1;
+                        }
+                        Some(&quote) if quote == c => break,
+                        Some(&other) => text.push(other),
+                    }
+                }
+                match c {
+                    '\'' => Token::String(text),
+                    _ => Token::Quoted(text),
+                }
+            }
+            '0'..='9' | '-' | '+' | '.' => {
+                let start = i;
+                while chars
+                    .get(i + 1)
+                    .is_some_and(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '+'))
+                {
+                    i += 1;
+                }
+                let word: String = chars[start..=i].iter().collect();
+                match word.parse::<f64>() {
+                    Ok(value) if value.is_finite() => Token::Number(value),
+                    _ => return Err(error(format!("invalid number '{word}'"))),
+                }
+            }
+            _ if c.is_alphabetic() || c == '_' => {
+                let start = i;
+                while chars
+                    .get(i + 1)
+                    .is_some_and(|c| c.is_alphanumeric() || matches!(c, '_' | '-'))
+                {
+                    i += 1;
+                }
+                Token::Word(chars[start..=i].iter().collect())
+            }
+            _ => return Err(error(format!("unexpected character '{c}'"))),
+        };
+        tokens.push((column, token));
+        i += 1;
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    position: usize,
+    /// Column just past the input, for errors at its end.
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(_, token)| token)
+    }
+
+    fn column(&self) -> usize {
+        self.tokens
+            .get(self.position)
+            .map_or(self.end, |(column, _)| *column)
+    }
+
+    fn error(&self, message: impl Into<String>) -> FilterError {
+        FilterError {
+            column: self.column(),
+            message: message.into(),
+        }
+    }
+
+    fn unexpected(&self, expected: &str) -> FilterError {
+        match self.peek() {
+            Some(token) => self.error(format!("expected {expected}, found {}", token.describe())),
+            None => self.error(format!("expected {expected}, found the end of the filter")),
+        }
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek().cloned();
+        self.position += 1;
+        token
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let found = self.peek().is_some_and(|token| token.is_keyword(keyword));
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn or(&mut self, depth: usize) -> Result<Filter, FilterError> {
+        let mut filter = self.and(depth)?;
+        while self.eat_keyword("or") {
+            filter = Filter::Or(Box::new(filter), Box::new(self.and(depth)?));
+        }
+        Ok(filter)
+    }
+
+    fn and(&mut self, depth: usize) -> Result<Filter, FilterError> {
+        let mut filter = self.unary(depth)?;
+        while self.eat_keyword("and") {
+            filter = Filter::And(Box::new(filter), Box::new(self.unary(depth)?));
+        }
+        Ok(filter)
+    }
+
+    fn unary(&mut self, depth: usize) -> Result<Filter, FilterError> {
+        if depth >= MAX_DEPTH {
+            return Err(self.error(format!("filter nests deeper than {MAX_DEPTH} levels")));
+        }
+        if self.eat_keyword("not") {
+            return Ok(Filter::Not(Box::new(self.unary(depth + 1)?)));
+        }
+        if self.peek() == Some(&Token::Open) {
+            self.position += 1;
+            let filter = self.or(depth + 1)?;
+            if self.peek() != Some(&Token::Close) {
+                return Err(self.unexpected("')'"));
+            }
+            self.position += 1;
+            return Ok(filter);
+        }
+        self.comparison().map(Filter::Compare)
+    }
+
+    fn name(&mut self) -> Result<String, FilterError> {
+        match self.peek() {
+            Some(Token::Word(word))
+                if !["and", "or", "not", "true", "false"]
+                    .iter()
+                    .any(|keyword| word.eq_ignore_ascii_case(keyword)) =>
+            {
+                let word = word.clone();
+                self.position += 1;
+                Ok(word)
+            }
+            Some(Token::Quoted(name)) => {
+                let name = name.clone();
+                self.position += 1;
+                Ok(name)
+            }
+            _ => Err(self.unexpected("a metric name")),
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Comparison, FilterError> {
+        let first = self.name()?;
+        let (provider, metric) = if self.peek() == Some(&Token::Dot) {
+            self.position += 1;
+            (Some(first), self.name()?)
+        } else {
+            (None, first)
+        };
+
+        let Some(Token::Op(op)) = self.peek().cloned() else {
+            return Err(self.unexpected("a comparison operator"));
+        };
+        self.position += 1;
+
+        let column = self.column();
+        let value = match self.next() {
+            Some(Token::Number(value)) => Literal::Number(value),
+            Some(Token::String(text)) => Literal::String(text),
+            Some(token) if token.is_keyword("true") => Literal::Bool(true),
+            Some(token) if token.is_keyword("false") => Literal::Bool(false),
+            _ => {
+                self.position -= 1;
+                return Err(self.unexpected("a number, a string, true or false"));
+            }
+        };
+        let kind = match value {
+            Literal::String(_) => Some("strings"),
+            Literal::Bool(_) => Some("booleans"),
+            Literal::Number(_) => None,
+        };
+        if let Some(kind) = kind
+            && !matches!(op, Op::Eq | Op::Ne)
+        {
+            return Err(FilterError {
+                column,
+                message: format!("{kind} can only be compared with '=' or '!='"),
+            });
+        }
+
+        Ok(Comparison {
+            provider,
+            metric,
+            op,
+            value,
+        })
+    }
+}
+
+impl Filter {
+    pub fn parse(input: &str) -> Result<Filter, FilterError> {
+        let mut parser = Parser {
+            tokens: tokenize(input)?,
+            position: 0,
+            end: input.chars().count() + 1,
+        };
+        if parser.peek().is_none() {
+            return Err(parser.error("filter is empty"));
+        }
+        let filter = parser.or(0)?;
+        if parser.peek().is_some() {
+            return Err(parser.unexpected("'and', 'or' or the end of the filter"));
+        }
+        Ok(filter)
+    }
+
+    /// The filter as a predicate on a `Vec<MetricCollection>` serialized to JSON.
+    pub fn json_path(&self) -> String {
+        match self {
+            Filter::Compare(comparison) => comparison.json_path(),
+            Filter::Not(filter) => format!("!({})", filter.json_path()),
+            Filter::And(left, right) => format!("({} && {})", left.json_path(), right.json_path()),
+            Filter::Or(left, right) => format!("({} || {})", left.json_path(), right.json_path()),
+        }
+    }
+}
+
+impl Comparison {
+    fn json_path(&self) -> String {
+        let collections = match &self.provider {
+            Some(provider) => format!("$[*] ? (@.provider == {})", json_string(provider)),
+            None => "$[*]".into(),
+        };
+        // the type tags written by `Metric`'s serde representation
+        let (types, value) = match &self.value {
+            Literal::Number(value) => (
+                "(@.type == \"int\" || @.type == \"float\")",
+                value.to_string(),
+            ),
+            Literal::String(text) => ("@.type == \"str\"", json_string(text)),
+            Literal::Bool(value) => ("@.type == \"bool\"", value.to_string()),
+        };
+        format!(
+            "exists({collections}.metrics[*] ? (@.name == {} && {types} && @.value {} {value}))",
+            json_string(&self.metric),
+            self.op.symbol()
+        )
+    }
+}
+
+/// A string literal for a path expression, which shares JSON's escapes.
+fn json_string(text: &str) -> String {
+    serde_json::to_string(text).expect("strings should serialize")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compare(provider: Option<&str>, metric: &str, op: Op, value: Literal) -> Filter {
+        Filter::Compare(Comparison {
+            provider: provider.map(str::to_owned),
+            metric: metric.into(),
+            op,
+            value,
+        })
+    }
+
+    #[test]
+    fn test_parse_precedence() {
+        let filter = Filter::parse(
+            "emotion2vec.valence < -0.3 OR not (rate >= 5 and \"my asr\".lang = 'it''s')",
+        )
+        .unwrap();
+        assert_eq!(
+            filter,
+            Filter::Or(
+                Box::new(compare(
+                    Some("emotion2vec"),
+                    "valence",
+                    Op::Lt,
+                    Literal::Number(-0.3)
+                )),
+                Box::new(Filter::Not(Box::new(Filter::And(
+                    Box::new(compare(None, "rate", Op::Ge, Literal::Number(5.0))),
+                    Box::new(compare(
+                        Some("my asr"),
+                        "lang",
+                        Op::Eq,
+                        Literal::String("it's".into())
+                    )),
+                ))))
+            )
+        );
+    }
+
+    #[test]
+    fn test_json_path() {
+        let filter = Filter::parse("emotion2vec.valence < -0.3 and not voiced = true").unwrap();
+        assert_eq!(
+            filter.json_path(),
+            r#"(exists($[*] ? (@.provider == "emotion2vec").metrics[*] ? (@.name == "valence" && (@.type == "int" || @.type == "float") && @.value < -0.3)) && !(exists($[*].metrics[*] ? (@.name == "voiced" && @.type == "bool" && @.value == true))))"#
+        );
+        let filter = Filter::parse(r#"asr.text != 'say "hi"'"#).unwrap();
+        assert!(filter.json_path().contains(r#"@.value != "say \"hi\"""#));
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |input| Filter::parse(input).unwrap_err();
+        assert_eq!(
+            error("asr.lang < 'en'"),
+            FilterError {
+                column: 12,
+                message: "strings can only be compared with '=' or '!='".into()
+            }
+        );
+        assert_eq!(error("rate > fast").column, 8);
+        assert_eq!(
+            error("rate > 5 and").message,
+            "expected a metric name, found the end of the filter"
+        );
+        assert_eq!(error("(rate > 5").column, 10);
+        assert_eq!(error("rate > 5 rate").column, 10);
+        assert_eq!(
+            error("rate 5").message,
+            "expected a comparison operator, found the number 5"
+        );
+        assert_eq!(error("rate > 1e999").message, "invalid number '1e999'");
+        assert_eq!(error("  ").message, "filter is empty");
+        assert!(error(&"(".repeat(100)).message.contains("deeper"));
+    }
+}
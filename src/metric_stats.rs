@@ -0,0 +1,353 @@
+//! Summary statistics of segment metrics.
+//!
+//! [`Summarizer`] collects the metrics of a set of segments and reduces every metric to
+//! descriptive statistics if it is numeric, or to a distribution of values if it is a
+//! string or boolean. With [`Weighting::Duration`] every segment counts in proportion to
+//! its length, so a long utterance outweighs a short interjection.
+
+use std::collections::BTreeMap;
+
+use crate::message_queue::types::{Metric, MetricCollection};
+
+/// Percentiles reported when none are requested.
+pub const DEFAULT_PERCENTILES: [f64; 6] = [5.0, 10.0, 25.0, 75.0, 90.0, 95.0];
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Weighting {
+    /// Every segment counts once.
+    #[default]
+    Segment,
+    /// Every segment counts in proportion to its duration.
+    Duration,
+}
+
+#[derive(Debug, Default)]
+struct Observations {
+    /// Values of `int` and `float` metrics with the weight of their segment.
+    numbers: Vec<(f64, f64)>,
+    number_nulls: u64,
+    /// Count and total weight of every `str` and `bool` value.
+    labels: BTreeMap<String, (u64, f64)>,
+    label_nulls: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Percentile {
+    pub percentile: f64,
+    pub value: f64,
+}
+
+/// Statistics of the numeric values of a metric; they are all `None` when no segment
+/// had a value. The standard deviation is the population one.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct NumericSummary {
+    pub count: u64,
+    pub null_count: u64,
+    pub mean: Option<f64>,
+    pub median: Option<f64>,
+    pub std_dev: Option<f64>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub percentiles: Vec<Percentile>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ValueShare {
+    pub value: String,
+    pub count: u64,
+    /// Fraction of the (weighted) values that are this one.
+    pub share: f64,
+}
+
+/// How often each value of a string or boolean metric occurs, most frequent first.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Distribution {
+    pub count: u64,
+    pub null_count: u64,
+    pub values: Vec<ValueShare>,
+}
+
+/// A metric is summarized both ways if a provider reported it with different types.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct MetricSummary {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub numeric: Option<NumericSummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub distribution: Option<Distribution>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ProviderSummary {
+    pub provider: String,
+    pub metrics: Vec<MetricSummary>,
+}
+
+pub struct Summarizer {
+    weighting: Weighting,
+    percentiles: Vec<f64>,
+    providers: BTreeMap<String, BTreeMap<String, Observations>>,
+}
+
+impl Summarizer {
+    /// `percentiles` are between 0 and 100.
+    pub fn new(weighting: Weighting, percentiles: Vec<f64>) -> Self {
+        Summarizer {
+            weighting,
+            percentiles,
+            providers: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the metrics of a segment lasting `duration` seconds.
+    pub fn add(&mut self, duration: f64, collections: &[MetricCollection]) {
+        let weight = match self.weighting {
+            Weighting::Segment => 1.0,
+            Weighting::Duration => duration.max(0.0),
+        };
+        for collection in collections {
+            let metrics = self
+                .providers
+                .entry(collection.provider.clone())
+                .or_default();
+            for metric in &collection.metrics {
+                let observations = metrics.entry(metric.name().to_owned()).or_default();
+                match metric {
+                    Metric::Int { .. } | Metric::Float { .. } => match metric.as_f64() {
+                        Some(value) => observations.numbers.push((value, weight)),
+                        None => observations.number_nulls += 1,
+                    },
+                    Metric::String { .. } | Metric::Bool { .. } => match metric.value_label() {
+                        Some(label) => {
+                            let (count, total) = observations.labels.entry(label).or_default();
+                            *count += 1;
+                            *total += weight;
+                        }
+                        None => observations.label_nulls += 1,
+                    },
+                }
+            }
+        }
+    }
+
+    pub fn finish(self) -> Vec<ProviderSummary> {
+        self.providers
+            .into_iter()
+            .map(|(provider, metrics)| ProviderSummary {
+                provider,
+                metrics: metrics
+                    .into_iter()
+                    .map(|(name, observations)| MetricSummary {
+                        name,
+                        numeric: (!observations.numbers.is_empty()
+                            || observations.number_nulls > 0)
+                            .then(|| {
+                                numeric_summary(
+                                    observations.numbers,
+                                    observations.number_nulls,
+                                    &self.percentiles,
+                                )
+                            }),
+                        distribution: (!observations.labels.is_empty()
+                            || observations.label_nulls > 0)
+                            .then(|| distribution(observations.labels, observations.label_nulls)),
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+fn numeric_summary(
+    mut values: Vec<(f64, f64)>,
+    null_count: u64,
+    percentiles: &[f64],
+) -> NumericSummary {
+    let count = values.len() as u64;
+    values.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let min = values.first().map(|(value, _)| *value);
+    let max = values.last().map(|(value, _)| *value);
+
+    // zero-length segments carry no weight when weighting by duration
+    values.retain(|(_, weight)| *weight > 0.0);
+    let total: f64 = values.iter().map(|(_, weight)| weight).sum();
+    if values.is_empty() {
+        return NumericSummary {
+            count,
+            null_count,
+            mean: None,
+            median: None,
+            std_dev: None,
+            min,
+            max,
+            percentiles: vec![],
+        };
+    }
+    let mean = values
+        .iter()
+        .map(|(value, weight)| value * weight)
+        .sum::<f64>()
+        / total;
+    let variance = values
+        .iter()
+        .map(|(value, weight)| weight * (value - mean).powi(2))
+        .sum::<f64>()
+        / total;
+
+    NumericSummary {
+        count,
+        null_count,
+        mean: Some(mean),
+        median: Some(percentile(&values, 50.0)),
+        std_dev: Some(variance.sqrt()),
+        min,
+        max,
+        percentiles: percentiles
+            .iter()
+            .map(|&p| Percentile {
+                percentile: p,
+                value: percentile(&values, p),
+            })
+            .collect(),
+    }
+}
+
+/// Weighted percentile of sorted values with positive weights, interpolating linearly
+/// between neighbours.
+///
+/// Each value sits at the middle of its share of the total weight, at
+/// `(S_i - w_i / 2) / S_n` with `S_i` the running total of weights; percentiles before
+/// the first or after the last value are clamped to it.
+fn percentile(values: &[(f64, f64)], p: f64) -> f64 {
+    let total: f64 = values.iter().map(|(_, weight)| weight).sum();
+    let target = p.clamp(0.0, 100.0) / 100.0;
+    let mut before = 0.0;
+    let mut previous: Option<(f64, f64)> = None;
+    for &(value, weight) in values {
+        let position = (before + weight / 2.0) / total;
+        if position >= target {
+            return match previous {
+                Some((previous_value, previous_position)) => {
+                    let fraction = (target - previous_position) / (position - previous_position);
+                    previous_value + fraction * (value - previous_value)
+                }
+                None => value,
+            };
+        }
+        previous = Some((value, position));
+        before += weight;
+    }
+    values[values.len() - 1].0
+}
+
+fn distribution(labels: BTreeMap<String, (u64, f64)>, null_count: u64) -> Distribution {
+    let count = labels.values().map(|(count, _)| count).sum();
+    let total: f64 = labels.values().map(|(_, weight)| weight).sum();
+    let mut values: Vec<_> = labels
+        .into_iter()
+        .map(|(value, (count, weight))| ValueShare {
+            value,
+            count,
+            share: if total > 0.0 { weight / total } else { 0.0 },
+        })
+        .collect();
+    // the stable sort keeps equally frequent values in alphabetical order
+    values.sort_by(|a, b| b.share.total_cmp(&a.share).then(b.count.cmp(&a.count)));
+    Distribution {
+        count,
+        null_count,
+        values,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(energy: Option<f32>, label: Option<&str>) -> Vec<MetricCollection> {
+        vec![MetricCollection {
+            provider: "demo".into(),
+            description: None,
+            metrics: vec![
+                Metric::Float {
+                    name: "energy".into(),
+                    value: energy,
+                    description: None,
+                    unit: None,
+                },
+                Metric::String {
+                    name: "label".into(),
+                    value: label.map(str::to_owned),
+                    description: None,
+                    unit: None,
+                },
+            ],
+        }]
+    }
+
+    #[test]
+    fn test_numeric_summary() {
+        let mut summarizer = Summarizer::new(Weighting::Segment, vec![0.0, 25.0, 100.0]);
+        for energy in [Some(4.0), Some(1.0), None, Some(3.0), Some(2.0)] {
+            summarizer.add(1.0, &segment(energy, None));
+        }
+        let summary = summarizer.finish();
+        let energy = summary[0].metrics[0].numeric.as_ref().unwrap();
+        assert_eq!(energy.count, 4);
+        assert_eq!(energy.null_count, 1);
+        assert_eq!(energy.mean, Some(2.5));
+        assert_eq!(energy.median, Some(2.5));
+        assert_eq!(energy.std_dev, Some(1.25f64.sqrt()));
+        assert_eq!((energy.min, energy.max), (Some(1.0), Some(4.0)));
+        let percentiles: Vec<_> = energy.percentiles.iter().map(|p| p.value).collect();
+        assert_eq!(percentiles, vec![1.0, 1.5, 4.0]);
+        assert_eq!(summary[0].metrics[0].distribution, None);
+    }
+
+    #[test]
+    fn test_weighted_summary() {
+        let mut summarizer = Summarizer::new(Weighting::Duration, vec![]);
+        summarizer.add(3.0, &segment(Some(1.0), Some("happy")));
+        summarizer.add(1.0, &segment(Some(5.0), Some("sad")));
+        summarizer.add(0.0, &segment(Some(100.0), Some("sad")));
+        let summary = summarizer.finish();
+
+        let energy = summary[0].metrics[0].numeric.as_ref().unwrap();
+        assert_eq!(energy.count, 3);
+        assert_eq!(energy.mean, Some(2.0));
+        assert_eq!(energy.std_dev, Some(3.0f64.sqrt()));
+        assert_eq!(energy.max, Some(100.0));
+
+        let label = summary[0].metrics[1].distribution.as_ref().unwrap();
+        assert_eq!(label.count, 3);
+        assert_eq!(
+            label.values,
+            vec![
+                ValueShare {
+                    value: "happy".into(),
+                    count: 1,
+                    share: 0.75,
+                },
+                ValueShare {
+                    value: "sad".into(),
+                    count: 2,
+                    share: 0.25,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_percentile() {
+        assert_eq!(percentile(&[(7.0, 1.0)], 90.0), 7.0);
+        // the heavy first value sits at 3/8 and the light last one at 7/8
+        let values = [(0.0, 3.0), (10.0, 1.0)];
+        assert_eq!(percentile(&values, 25.0), 0.0);
+        assert_eq!(percentile(&values, 50.0), 2.5);
+        assert_eq!(percentile(&values, 100.0), 10.0);
+        let values = [(0.0, 1.0), (10.0, 1.0), (20.0, 1.0)];
+        assert_eq!(percentile(&values, 50.0), 10.0);
+        assert_eq!(percentile(&values, 75.0), 17.5);
+    }
+}
@@ -0,0 +1,441 @@
+//! Flattening of segment metrics into a table, written as CSV or Parquet.
+//!
+//! Every segment is one row; every `provider.metric_name` seen in any segment is one
+//! column, typed after the [`Metric`] variant. A column whose variant differs between
+//! segments falls back to text.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+use parquet::{
+    basic::{Compression, LogicalType, Repetition, Type as PhysicalType},
+    column::writer::ColumnWriterImpl,
+    data_type::{
+        BoolType, ByteArray, ByteArrayType, DataType, DoubleType, FloatType, Int32Type, Int64Type,
+    },
+    file::{properties::WriterProperties, writer::SerializedFileWriter},
+    schema::types::Type,
+};
+
+use crate::message_queue::types::{Metric, MetricCollection};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableFormat {
+    Csv,
+    Parquet,
+}
+
+impl TableFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            TableFormat::Csv => "text/csv; charset=utf-8",
+            TableFormat::Parquet => "application/vnd.apache.parquet",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            TableFormat::Csv => "csv",
+            TableFormat::Parquet => "parquet",
+        }
+    }
+}
+
+pub struct SegmentRow {
+    pub recording_id: uuid::Uuid,
+    pub channel: i32,
+    pub segment_id: uuid::Uuid,
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+    pub metrics: Vec<MetricCollection>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Cells {
+    Int32(Vec<Option<i32>>),
+    Int64(Vec<Option<i64>>),
+    Float(Vec<Option<f32>>),
+    Double(Vec<Option<f64>>),
+    Text(Vec<Option<String>>),
+    Bool(Vec<Option<bool>>),
+}
+
+impl Cells {
+    fn csv_cell(&self, row: usize) -> String {
+        fn show<T: ToString>(cell: &Option<T>) -> String {
+            cell.as_ref().map(T::to_string).unwrap_or_default()
+        }
+        match self {
+            Cells::Int32(cells) => show(&cells[row]),
+            Cells::Int64(cells) => show(&cells[row]),
+            Cells::Float(cells) => show(&cells[row]),
+            Cells::Double(cells) => show(&cells[row]),
+            Cells::Text(cells) => show(&cells[row]),
+            Cells::Bool(cells) => show(&cells[row]),
+        }
+    }
+
+    fn physical_type(&self) -> (PhysicalType, Option<LogicalType>) {
+        match self {
+            Cells::Int32(_) => (PhysicalType::INT32, None),
+            Cells::Int64(_) => (PhysicalType::INT64, None),
+            Cells::Float(_) => (PhysicalType::FLOAT, None),
+            Cells::Double(_) => (PhysicalType::DOUBLE, None),
+            Cells::Text(_) => (PhysicalType::BYTE_ARRAY, Some(LogicalType::String)),
+            Cells::Bool(_) => (PhysicalType::BOOLEAN, None),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Column {
+    name: String,
+    /// Set for the segment columns, which never have missing values.
+    required: bool,
+    cells: Cells,
+}
+
+pub struct MetricsTable {
+    rows: usize,
+    columns: Vec<Column>,
+}
+
+impl MetricsTable {
+    /// Builds the table; `with_recording` adds a leading `recording_id` column for exports
+    /// that span several recordings.
+    pub fn new(rows: &[SegmentRow], with_recording: bool) -> MetricsTable {
+        let segment_column = |name: &str, cells| Column {
+            name: name.to_owned(),
+            required: true,
+            cells,
+        };
+        let mut columns = Vec::new();
+        if with_recording {
+            columns.push(segment_column(
+                "recording_id",
+                Cells::Text(
+                    rows.iter()
+                        .map(|row| Some(row.recording_id.to_string()))
+                        .collect(),
+                ),
+            ));
+        }
+        columns.extend([
+            segment_column(
+                "channel",
+                Cells::Int32(rows.iter().map(|row| Some(row.channel)).collect()),
+            ),
+            segment_column(
+                "segment_id",
+                Cells::Text(
+                    rows.iter()
+                        .map(|row| Some(row.segment_id.to_string()))
+                        .collect(),
+                ),
+            ),
+            segment_column(
+                "start",
+                Cells::Double(rows.iter().map(|row| Some(row.start)).collect()),
+            ),
+            segment_column(
+                "end",
+                Cells::Double(rows.iter().map(|row| Some(row.end)).collect()),
+            ),
+            segment_column(
+                "text",
+                Cells::Text(rows.iter().map(|row| Some(row.text.clone())).collect()),
+            ),
+        ]);
+
+        // the first metric of a name wins if a collection repeats it
+        let metrics: Vec<HashMap<String, &Metric>> = rows
+            .iter()
+            .map(|row| {
+                let mut metrics = HashMap::new();
+                for collection in &row.metrics {
+                    for metric in &collection.metrics {
+                        metrics
+                            .entry(format!("{}.{}", collection.provider, metric.name()))
+                            .or_insert(metric);
+                    }
+                }
+                metrics
+            })
+            .collect();
+        // a sample metric per column, or `None` once two variants were seen
+        let mut samples: BTreeMap<String, Option<&Metric>> = BTreeMap::new();
+        for (name, metric) in metrics.iter().flatten() {
+            samples
+                .entry(name.clone())
+                .and_modify(|sample| {
+                    if sample.is_some_and(|sample| {
+                        std::mem::discriminant(sample) != std::mem::discriminant(*metric)
+                    }) {
+                        *sample = None;
+                    }
+                })
+                .or_insert(Some(*metric));
+        }
+
+        for (name, sample) in samples {
+            let cells = metrics.iter().map(|metrics| metrics.get(&name));
+            let cells = match sample {
+                Some(Metric::Int { .. }) => Cells::Int64(
+                    cells
+                        .map(|metric| match metric {
+                            Some(Metric::Int { value, .. }) => *value,
+                            _ => None,
+                        })
+                        .collect(),
+                ),
+                Some(Metric::Float { .. }) => Cells::Float(
+                    cells
+                        .map(|metric| match metric {
+                            Some(Metric::Float { value, .. }) => *value,
+                            _ => None,
+                        })
+                        .collect(),
+                ),
+                Some(Metric::Bool { .. }) => Cells::Bool(
+                    cells
+                        .map(|metric| match metric {
+                            Some(Metric::Bool { value, .. }) => *value,
+                            _ => None,
+                        })
+                        .collect(),
+                ),
+                _ => Cells::Text(
+                    cells
+                        .map(|metric| metric.and_then(|metric| metric.value_label()))
+                        .collect(),
+                ),
+            };
+            columns.push(Column {
+                name,
+                required: false,
+                cells,
+            });
+        }
+
+        MetricsTable {
+            rows: rows.len(),
+            columns,
+        }
+    }
+
+    pub fn write(&self, format: TableFormat) -> eyre::Result<Vec<u8>> {
+        match format {
+            TableFormat::Csv => self.to_csv(),
+            TableFormat::Parquet => self.to_parquet(),
+        }
+    }
+
+    fn to_csv(&self) -> eyre::Result<Vec<u8>> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.write_record(self.columns.iter().map(|column| &column.name))?;
+        for row in 0..self.rows {
+            writer.write_record(self.columns.iter().map(|column| column.cells.csv_cell(row)))?;
+        }
+        Ok(writer.into_inner()?)
+    }
+
+    fn to_parquet(&self) -> eyre::Result<Vec<u8>> {
+        let fields = self
+            .columns
+            .iter()
+            .map(|column| {
+                let (physical, logical) = column.cells.physical_type();
+                let repetition = match column.required {
+                    true => Repetition::REQUIRED,
+                    false => Repetition::OPTIONAL,
+                };
+                Type::primitive_type_builder(&column.name, physical)
+                    .with_logical_type(logical)
+                    .with_repetition(repetition)
+                    .build()
+                    .map(Arc::new)
+            })
+            .collect::<Result<_, _>>()?;
+        let schema = Type::group_type_builder("schema")
+            .with_fields(fields)
+            .build()?;
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+
+        let mut writer =
+            SerializedFileWriter::new(Vec::new(), Arc::new(schema), Arc::new(properties))?;
+        let mut row_group = writer.next_row_group()?;
+        for column in &self.columns {
+            let mut output = row_group
+                .next_column()?
+                .ok_or_else(|| eyre::eyre!("missing parquet column {}", column.name))?;
+            match &column.cells {
+                Cells::Int32(cells) => write_cells(output.typed::<Int32Type>(), cells.clone())?,
+                Cells::Int64(cells) => write_cells(output.typed::<Int64Type>(), cells.clone())?,
+                Cells::Float(cells) => write_cells(output.typed::<FloatType>(), cells.clone())?,
+                Cells::Double(cells) => write_cells(output.typed::<DoubleType>(), cells.clone())?,
+                Cells::Bool(cells) => write_cells(output.typed::<BoolType>(), cells.clone())?,
+                Cells::Text(cells) => write_cells(
+                    output.typed::<ByteArrayType>(),
+                    cells
+                        .iter()
+                        .map(|cell| cell.as_deref().map(ByteArray::from))
+                        .collect(),
+                )?,
+            }
+            output.close()?;
+        }
+        row_group.close()?;
+        Ok(writer.into_inner()?)
+    }
+}
+
+/// Writes one column; definition levels are ignored by the writer for required columns.
+fn write_cells<T: DataType>(
+    writer: &mut ColumnWriterImpl<'_, T>,
+    cells: Vec<Option<T::T>>,
+) -> parquet::errors::Result<()> {
+    let levels: Vec<i16> = cells.iter().map(|cell| cell.is_some() as i16).collect();
+    let values: Vec<T::T> = cells.into_iter().flatten().collect();
+    writer.write_batch(&values, Some(&levels), None)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use parquet::{
+        file::reader::{FileReader, SerializedFileReader},
+        record::Field,
+    };
+
+    use super::*;
+
+    fn rows() -> Vec<SegmentRow> {
+        let metric = |provider: &str, metrics| MetricCollection {
+            provider: provider.into(),
+            metrics,
+            description: None,
+        };
+        vec![
+            SegmentRow {
+                recording_id: uuid::Uuid::nil(),
+                channel: 0,
+                segment_id: uuid::Uuid::nil(),
+                start: 0.5,
+                end: 1.25,
+                text: "hello, \"world\"".into(),
+                metrics: vec![
+                    metric(
+                        "emotion",
+                        vec![
+                            Metric::String {
+                                name: "label".into(),
+                                value: Some("happy".into()),
+                                description: None,
+                                unit: None,
+                            },
+                            Metric::Float {
+                                name: "score".into(),
+                                value: Some(0.75),
+                                description: None,
+                                unit: None,
+                            },
+                        ],
+                    ),
+                    metric(
+                        "asr",
+                        vec![Metric::Int {
+                            name: "words".into(),
+                            value: Some(2),
+                            description: None,
+                            unit: None,
+                        }],
+                    ),
+                ],
+            },
+            SegmentRow {
+                recording_id: uuid::Uuid::nil(),
+                channel: 1,
+                segment_id: uuid::Uuid::max(),
+                start: 2.0,
+                end: 3.0,
+                text: "bye".into(),
+                metrics: vec![metric(
+                    "asr",
+                    vec![Metric::Bool {
+                        name: "words".into(),
+                        value: Some(true),
+                        description: None,
+                        unit: None,
+                    }],
+                )],
+            },
+        ]
+    }
+
+    #[test]
+    fn test_columns_follow_variants() {
+        let table = MetricsTable::new(&rows(), false);
+        let names: Vec<&str> = table.columns.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "channel",
+                "segment_id",
+                "start",
+                "end",
+                "text",
+                "asr.words",
+                "emotion.label",
+                "emotion.score"
+            ]
+        );
+        assert_eq!(
+            table.columns[5].cells,
+            Cells::Text(vec![Some("2".into()), Some("true".into())])
+        );
+        assert_eq!(table.columns[7].cells, Cells::Float(vec![Some(0.75), None]));
+    }
+
+    #[test]
+    fn test_csv() {
+        let csv = MetricsTable::new(&rows(), true)
+            .write(TableFormat::Csv)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "recording_id,channel,segment_id,start,end,text,asr.words,emotion.label,emotion.score\n\
+            00000000-0000-0000-0000-000000000000,0,00000000-0000-0000-0000-000000000000,0.5,1.25,\"hello, \"\"world\"\"\",2,happy,0.75\n\
+            00000000-0000-0000-0000-000000000000,1,ffffffff-ffff-ffff-ffff-ffffffffffff,2,3,bye,true,,\n"
+        );
+    }
+
+    #[test]
+    fn test_parquet_round_trip() {
+        let parquet = MetricsTable::new(&rows(), false)
+            .write(TableFormat::Parquet)
+            .unwrap();
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&parquet).unwrap();
+        let reader = SerializedFileReader::new(file).unwrap();
+        let rows: Vec<Vec<(String, Field)>> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| row.unwrap().into_columns())
+            .collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0][0], ("channel".into(), Field::Int(0)));
+        assert_eq!(
+            rows[0][4],
+            ("text".into(), Field::Str("hello, \"world\"".into()))
+        );
+        assert_eq!(rows[0][7], ("emotion.score".into(), Field::Float(0.75)));
+        assert_eq!(rows[1][6], ("emotion.label".into(), Field::Null));
+    }
+}
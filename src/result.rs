@@ -1,22 +1,163 @@
-use axum::response::IntoResponse;
+use std::sync::OnceLock;
+
+use axum::{
+    http::{StatusCode, header},
+    response::IntoResponse,
+};
 
 pub type AppResult<T> = Result<T, AppError>;
-pub struct AppError(eyre::Report);
+
+/// Error returned by request handlers.
+///
+/// Rendered as an RFC 7807 `application/problem+json` document. The `code` member is
+/// stable and meant for clients to match on; `detail` is for humans.
+#[derive(Debug)]
+pub enum AppError {
+    NotFound(String),
+    BadRequest(String),
+    Forbidden(String),
+    Conflict(String),
+    PreconditionFailed(String),
+    PayloadTooLarge(String),
+    UnsupportedMediaType(String),
+    UpstreamUnavailable(eyre::Report),
+    Internal(eyre::Report),
+}
+
+impl AppError {
+    pub fn not_found(detail: impl Into<String>) -> Self {
+        AppError::NotFound(detail.into())
+    }
+
+    pub fn bad_request(detail: impl Into<String>) -> Self {
+        AppError::BadRequest(detail.into())
+    }
+
+    pub fn forbidden(detail: impl Into<String>) -> Self {
+        AppError::Forbidden(detail.into())
+    }
+
+    pub fn conflict(detail: impl Into<String>) -> Self {
+        AppError::Conflict(detail.into())
+    }
+
+    pub fn precondition_failed(detail: impl Into<String>) -> Self {
+        AppError::PreconditionFailed(detail.into())
+    }
+
+    pub fn unsupported_media_type(detail: impl Into<String>) -> Self {
+        AppError::UnsupportedMediaType(detail.into())
+    }
+
+    pub fn upstream(report: impl Into<eyre::Report>) -> Self {
+        AppError::UpstreamUnavailable(report.into())
+    }
+
+    /// Classifies a multipart parsing failure; body limit violations become 413.
+    pub fn multipart(error: axum::extract::multipart::MultipartError) -> Self {
+        if error.status() == StatusCode::PAYLOAD_TOO_LARGE {
+            AppError::PayloadTooLarge(error.body_text())
+        } else {
+            AppError::BadRequest(error.body_text())
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            AppError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            AppError::UpstreamUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::NotFound(_) => "not_found",
+            AppError::BadRequest(_) => "bad_request",
+            AppError::Forbidden(_) => "forbidden",
+            AppError::Conflict(_) => "conflict",
+            AppError::PreconditionFailed(_) => "precondition_failed",
+            AppError::PayloadTooLarge(_) => "payload_too_large",
+            AppError::UnsupportedMediaType(_) => "unsupported_media_type",
+            AppError::UpstreamUnavailable(_) => "upstream_unavailable",
+            AppError::Internal(_) => "internal_error",
+        }
+    }
+
+    fn detail(&self) -> String {
+        match self {
+            AppError::NotFound(detail)
+            | AppError::BadRequest(detail)
+            | AppError::Forbidden(detail)
+            | AppError::Conflict(detail)
+            | AppError::PreconditionFailed(detail)
+            | AppError::PayloadTooLarge(detail)
+            | AppError::UnsupportedMediaType(detail) => detail.clone(),
+            AppError::UpstreamUnavailable(report) | AppError::Internal(report) => {
+                if expose_internal_details() {
+                    format!("{report:?}")
+                } else if matches!(self, AppError::UpstreamUnavailable(_)) {
+                    "a backing service is temporarily unavailable".into()
+                } else {
+                    "internal server error".into()
+                }
+            }
+        }
+    }
+}
 
 impl<T> From<T> for AppError
 where
     T: Into<eyre::Report>,
 {
     fn from(value: T) -> Self {
-        AppError(value.into())
+        AppError::Internal(value.into())
     }
 }
 
+/// Internal error reports are only sent to clients when `APP_ENV=development`.
+fn expose_internal_details() -> bool {
+    static EXPOSE: OnceLock<bool> = OnceLock::new();
+    *EXPOSE.get_or_init(|| std::env::var("APP_ENV").is_ok_and(|env| env == "development"))
+}
+
+#[derive(serde::Serialize)]
+struct ProblemDetails {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    title: &'static str,
+    status: u16,
+    detail: String,
+    code: &'static str,
+}
+
 impl IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
+        let status = self.status();
+        match &self {
+            AppError::UpstreamUnavailable(report) => tracing::warn!("upstream error: {report:?}"),
+            AppError::Internal(report) => tracing::error!("internal error: {report:?}"),
+            _ => {}
+        }
+
+        let problem = ProblemDetails {
+            kind: "about:blank",
+            title: status.canonical_reason().unwrap_or("Error"),
+            status: status.as_u16(),
+            detail: self.detail(),
+            code: self.code(),
+        };
+
         (
-            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-            format!("{:?}", self.0),
+            status,
+            [(header::CONTENT_TYPE, "application/problem+json")],
+            serde_json::to_string(&problem).unwrap_or_default(),
         )
             .into_response()
     }
@@ -0,0 +1,35 @@
+//! Full-text search over segment contents.
+//!
+//! Segments are indexed by a trigger using the text search configuration stored in
+//! `search_settings`; queries must be parsed with the same configuration to match.
+
+use sqlx::PgPool;
+
+/// Used when `SEARCH_TEXT_CONFIG` is not set; most recordings are in Russian.
+pub const DEFAULT_TEXT_SEARCH_CONFIG: &str = "russian";
+
+/// Switches the index to the given Postgres text search configuration, such as `russian`
+/// or `simple`, re-indexing every segment if it changed.
+pub async fn configure(db: &PgPool, config: &str) -> eyre::Result<()> {
+    let mut tx = db.begin().await?;
+    let changed = sqlx::query_scalar!(
+        r#"UPDATE search_settings SET text_search_config = $1::TEXT::REGCONFIG
+        WHERE text_search_config <> $1::TEXT::REGCONFIG
+        RETURNING TRUE AS "changed!""#,
+        config
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .is_some();
+    if changed {
+        tracing::info!("re-indexing segments for text search configuration '{config}'");
+        sqlx::query!(
+            "UPDATE segments SET content_tsv = to_tsvector($1::TEXT::REGCONFIG, content)",
+            config
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
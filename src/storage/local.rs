@@ -0,0 +1,600 @@
+use std::{env::var, path::PathBuf, sync::Arc, time::Duration};
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use eyre::{Context, eyre};
+use futures_util::TryStreamExt;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::{
+    extract::{Path, Query},
+    result::{AppError, AppResult},
+    storage::{BlobInfo, BlobStorage, BoxedReader, Checksum, UploadedPart, content_disposition},
+};
+
+/// Stores blobs as files below a root directory.
+///
+/// Presigned URLs point at the backend itself (see [`router`]) and carry an HMAC of the
+/// key and expiry time, so they work like S3 presigned URLs without exposing the files.
+pub struct LocalStorage {
+    root: PathBuf,
+    public_url: String,
+    signing_key: Vec<u8>,
+}
+
+impl LocalStorage {
+    /// Reads `STORAGE_LOCAL_ROOT`, `PUBLIC_BASE_URL` and `STORAGE_SIGNING_KEY`.
+    pub fn from_env() -> eyre::Result<Self> {
+        let root = PathBuf::from(var("STORAGE_LOCAL_ROOT").unwrap_or_else(|_| "data/blobs".into()));
+        std::fs::create_dir_all(&root)
+            .wrap_err_with(|| format!("failed to create storage root {}", root.display()))?;
+
+        let signing_key = match var("STORAGE_SIGNING_KEY") {
+            Ok(key) => key.into_bytes(),
+            Err(_) => {
+                tracing::warn!(
+                    "STORAGE_SIGNING_KEY is not set, download links will not survive a restart"
+                );
+                [uuid::Uuid::new_v4(), uuid::Uuid::new_v4()]
+                    .iter()
+                    .flat_map(|id| id.into_bytes())
+                    .collect()
+            }
+        };
+
+        Ok(LocalStorage {
+            root,
+            public_url: var("PUBLIC_BASE_URL").unwrap_or_else(|_| "http://localhost:3000".into()),
+            signing_key,
+        })
+    }
+
+    fn path(&self, key: &str) -> eyre::Result<PathBuf> {
+        let valid = key
+            .split('/')
+            .all(|part| !part.is_empty() && part != "." && part != "..")
+            && !key.contains('\\');
+        if !valid {
+            return Err(eyre!("invalid blob key '{key}'"));
+        }
+        Ok(self.root.join(key))
+    }
+
+    /// Directory holding the parts of a multipart upload, outside of the key namespace.
+    fn multipart_dir(&self, upload_id: &str) -> eyre::Result<PathBuf> {
+        let upload_id: uuid::Uuid = upload_id
+            .parse()
+            .map_err(|_| eyre!("invalid multipart upload id '{upload_id}'"))?;
+        Ok(self.root.join(".multipart").join(upload_id.to_string()))
+    }
+
+    /// `scope` is whatever else the URL grants, see [`SignedQuery::scope`].
+    fn mac(&self, method: &str, key: &str, expires: i64, scope: Option<&str>) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.signing_key)
+            .expect("hmac should accept keys of any length");
+        mac.update(format!("{method}\n{key}\n{expires}\n{}", scope.unwrap_or_default()).as_bytes());
+        mac
+    }
+
+    fn presign(
+        &self,
+        method: &str,
+        key: &str,
+        expires_in: Duration,
+        mut query: SignedQuery,
+    ) -> eyre::Result<String> {
+        self.path(key)?;
+        query.expires = chrono::Utc::now().timestamp() + expires_in.as_secs() as i64;
+        query.signature = hex::encode(
+            self.mac(method, key, query.expires, query.scope().as_deref())
+                .finalize()
+                .into_bytes(),
+        );
+        Ok(format!(
+            "{}/blobs/{key}?{}",
+            self.public_url,
+            serde_urlencoded::to_string(&query)?
+        ))
+    }
+
+    fn verify(&self, method: &str, key: &str, query: &SignedQuery) -> bool {
+        let Ok(signature) = hex::decode(&query.signature) else {
+            return false;
+        };
+        query.expires >= chrono::Utc::now().timestamp()
+            && self
+                .mac(method, key, query.expires, query.scope().as_deref())
+                .verify_slice(&signature)
+                .is_ok()
+    }
+}
+
+#[async_trait::async_trait]
+impl BlobStorage for LocalStorage {
+    async fn put_stream(&self, key: &str, mut reader: BoxedReader<'_>) -> eyre::Result<()> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        // write next to the destination and rename, so readers never see partial files
+        let partial = path.with_extension(format!("partial-{}", uuid::Uuid::new_v4()));
+        let mut file = tokio::fs::File::create(&partial).await?;
+        let written = async {
+            tokio::io::copy(&mut reader, &mut file).await?;
+            file.flush().await?;
+            tokio::fs::rename(&partial, &path).await
+        }
+        .await;
+        if let Err(why) = written {
+            let _ = tokio::fs::remove_file(&partial).await;
+            return Err(why).wrap_err_with(|| format!("failed to store {key}"));
+        }
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> eyre::Result<Option<BoxedReader<'static>>> {
+        match tokio::fs::File::open(self.path(key)?).await {
+            Ok(file) => Ok(Some(Box::new(file))),
+            Err(why) if why.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(why) => Err(why.into()),
+        }
+    }
+
+    async fn get_prefix(
+        &self,
+        key: &str,
+        length: u64,
+    ) -> eyre::Result<Option<BoxedReader<'static>>> {
+        Ok(self
+            .get(key)
+            .await?
+            .map(|reader| Box::new(reader.take(length)) as BoxedReader<'static>))
+    }
+
+    async fn delete(&self, key: &str) -> eyre::Result<()> {
+        match tokio::fs::remove_file(self.path(key)?).await {
+            Ok(()) => Ok(()),
+            Err(why) if why.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(why) => Err(why.into()),
+        }
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> eyre::Result<()> {
+        let Some(reader) = self.get(from).await? else {
+            return Err(eyre!("cannot copy {from}, it does not exist"));
+        };
+        self.put_stream(to, reader).await
+    }
+
+    async fn list(&self, prefix: &str) -> eyre::Result<Vec<BlobInfo>> {
+        let mut blobs = Vec::new();
+        let mut dirs = vec![self.root.clone()];
+        while let Some(dir) = dirs.pop() {
+            let mut entries = tokio::fs::read_dir(&dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                if entry.file_name().to_string_lossy().starts_with('.') {
+                    continue;
+                }
+                let metadata = entry.metadata().await?;
+                if metadata.is_dir() {
+                    dirs.push(entry.path());
+                    continue;
+                }
+                let Some(key) = entry
+                    .path()
+                    .strip_prefix(&self.root)
+                    .ok()
+                    .and_then(|path| path.to_str())
+                    .map(|key| key.replace(std::path::MAIN_SEPARATOR, "/"))
+                else {
+                    continue;
+                };
+                let is_partial = entry
+                    .path()
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .is_some_and(|ext| ext.starts_with("partial-"));
+                if is_partial || !key.starts_with(prefix) {
+                    continue;
+                }
+                blobs.push(BlobInfo {
+                    key,
+                    size: metadata.len(),
+                    last_modified: metadata.modified().ok().map(chrono::DateTime::from),
+                });
+            }
+        }
+        Ok(blobs)
+    }
+
+    async fn create_multipart(&self, key: &str) -> eyre::Result<String> {
+        self.path(key)?;
+        let upload_id = uuid::Uuid::new_v4().to_string();
+        tokio::fs::create_dir_all(self.multipart_dir(&upload_id)?).await?;
+        Ok(upload_id)
+    }
+
+    async fn upload_part(
+        &self,
+        _key: &str,
+        upload_id: &str,
+        part_number: u32,
+        data: Vec<u8>,
+    ) -> eyre::Result<UploadedPart> {
+        let dir = self.multipart_dir(upload_id)?;
+        if !tokio::fs::try_exists(&dir).await? {
+            return Err(eyre!("multipart upload {upload_id} does not exist"));
+        }
+        let etag = hex::encode(Sha256::digest(&data));
+        tokio::fs::write(dir.join(part_number.to_string()), data).await?;
+        Ok(UploadedPart { part_number, etag })
+    }
+
+    async fn complete_multipart(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: &[UploadedPart],
+    ) -> eyre::Result<()> {
+        let dir = self.multipart_dir(upload_id)?;
+        let mut reader: BoxedReader<'static> = Box::new(tokio::io::empty());
+        for part in parts {
+            let file = tokio::fs::File::open(dir.join(part.part_number.to_string()))
+                .await
+                .wrap_err_with(|| format!("part {} of {key} is missing", part.part_number))?;
+            reader = Box::new(reader.chain(file));
+        }
+        self.put_stream(key, reader).await?;
+        tokio::fs::remove_dir_all(dir).await?;
+        Ok(())
+    }
+
+    async fn abort_multipart(&self, _key: &str, upload_id: &str) -> eyre::Result<()> {
+        match tokio::fs::remove_dir_all(self.multipart_dir(upload_id)?).await {
+            Ok(()) => Ok(()),
+            Err(why) if why.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(why) => Err(why.into()),
+        }
+    }
+
+    async fn presign_get(
+        &self,
+        key: &str,
+        expires_in: Duration,
+        download_filename: Option<&str>,
+    ) -> eyre::Result<String> {
+        let query = SignedQuery {
+            filename: download_filename.map(str::to_owned),
+            ..Default::default()
+        };
+        self.presign("GET", key, expires_in, query)
+    }
+
+    async fn presign_put(
+        &self,
+        key: &str,
+        expires_in: Duration,
+        checksum: Option<&Checksum>,
+    ) -> eyre::Result<String> {
+        let query = SignedQuery {
+            checksum: checksum.map(Checksum::to_string),
+            ..Default::default()
+        };
+        self.presign("PUT", key, expires_in, query)
+    }
+
+    async fn presign_upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: u32,
+        expires_in: Duration,
+        checksum: Option<&Checksum>,
+    ) -> eyre::Result<String> {
+        self.multipart_dir(upload_id)?;
+        let query = SignedQuery {
+            upload_id: Some(upload_id.to_owned()),
+            part_number: Some(part_number),
+            checksum: checksum.map(Checksum::to_string),
+            ..Default::default()
+        };
+        self.presign("PUT", key, expires_in, query)
+    }
+
+    async fn head(&self, key: &str) -> eyre::Result<Option<BlobInfo>> {
+        match tokio::fs::metadata(self.path(key)?).await {
+            Ok(metadata) => Ok(Some(BlobInfo {
+                key: key.to_owned(),
+                size: metadata.len(),
+                last_modified: metadata.modified().ok().map(chrono::DateTime::from),
+            })),
+            Err(why) if why.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(why) => Err(why.into()),
+        }
+    }
+}
+
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct SignedQuery {
+    expires: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    filename: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upload_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    part_number: Option<u32>,
+    /// Digest the body of a `PUT` must have, see [`Checksum`]'s `Display`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    checksum: Option<String>,
+    signature: String,
+}
+
+impl SignedQuery {
+    /// The download filename, or the multipart upload part a `PUT` goes to and the digest
+    /// it must have.
+    fn scope(&self) -> Option<String> {
+        let scope = match (&self.upload_id, self.part_number) {
+            (Some(upload_id), Some(part_number)) => Some(format!("part {upload_id} {part_number}")),
+            _ => self.filename.clone(),
+        };
+        match &self.checksum {
+            Some(checksum) => Some(format!("{} {checksum}", scope.unwrap_or_default())),
+            None => scope,
+        }
+    }
+}
+
+/// Routes serving presigned URLs of a [`LocalStorage`].
+pub fn router<S>(storage: Arc<LocalStorage>) -> axum::Router<S> {
+    axum::Router::new()
+        .route("/blobs/{*key}", get(download_blob).put(upload_blob))
+        .with_state(storage)
+}
+
+/// Largest part accepted through a presigned part URL.
+const MAX_PART_SIZE: usize = 512 * 1024 * 1024;
+
+async fn upload_blob(
+    State(storage): State<Arc<LocalStorage>>,
+    Path(key): Path<String>,
+    Query(query): Query<SignedQuery>,
+    body: Body,
+) -> AppResult<Response> {
+    if !storage.verify("PUT", &key, &query) {
+        return Err(AppError::forbidden("upload link is invalid or has expired"));
+    }
+    let checksum = query
+        .checksum
+        .as_deref()
+        .map(str::parse::<Checksum>)
+        .transpose()?;
+    let mismatch = || AppError::bad_request("body does not match the checksum of the upload link");
+
+    match (&query.upload_id, query.part_number, checksum) {
+        (Some(upload_id), Some(part_number), checksum) => {
+            let data = axum::body::to_bytes(body, MAX_PART_SIZE)
+                .await
+                .map_err(|why| AppError::PayloadTooLarge(why.to_string()))?;
+            if checksum.is_some_and(|checksum| !checksum.matches(&data)) {
+                return Err(mismatch());
+            }
+            let part = storage
+                .upload_part(&key, upload_id, part_number, data.to_vec())
+                .await?;
+            Ok(([(header::ETAG, format!("\"{}\"", part.etag))], ()).into_response())
+        }
+        // checked before storing, so a mismatching body never replaces the object
+        (_, _, Some(checksum)) => {
+            let data = axum::body::to_bytes(body, MAX_PART_SIZE)
+                .await
+                .map_err(|why| AppError::PayloadTooLarge(why.to_string()))?;
+            if !checksum.matches(&data) {
+                return Err(mismatch());
+            }
+            storage.put_stream(&key, Box::new(&data[..])).await?;
+            Ok(StatusCode::OK.into_response())
+        }
+        _ => {
+            let stream = body.into_data_stream().map_err(std::io::Error::other);
+            storage
+                .put_stream(&key, Box::new(tokio_util::io::StreamReader::new(stream)))
+                .await?;
+            Ok(StatusCode::OK.into_response())
+        }
+    }
+}
+
+async fn download_blob(
+    State(storage): State<Arc<LocalStorage>>,
+    Path(key): Path<String>,
+    Query(query): Query<SignedQuery>,
+) -> AppResult<Response> {
+    if !storage.verify("GET", &key, &query) {
+        return Err(AppError::forbidden(
+            "download link is invalid or has expired",
+        ));
+    }
+
+    let path = storage.path(&key)?;
+    let file = match tokio::fs::File::open(&path).await {
+        Ok(file) => file,
+        Err(why) if why.kind() == std::io::ErrorKind::NotFound => {
+            return Err(AppError::not_found("blob not found"));
+        }
+        Err(why) => return Err(why.into()),
+    };
+    let length = file.metadata().await?.len();
+
+    let mut response = (
+        [
+            (header::CONTENT_TYPE, "application/octet-stream".to_owned()),
+            (header::CONTENT_LENGTH, length.to_string()),
+        ],
+        Body::from_stream(tokio_util::io::ReaderStream::new(file)),
+    )
+        .into_response();
+    if let Some(filename) = &query.filename {
+        response.headers_mut().insert(
+            header::CONTENT_DISPOSITION,
+            content_disposition(filename)
+                .try_into()
+                .map_err(|_| AppError::bad_request("invalid download filename"))?,
+        );
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The directory is removed when the returned `TempDir` is dropped.
+    fn storage() -> (tempfile::TempDir, LocalStorage) {
+        let root = tempfile::TempDir::new().unwrap();
+        let storage = LocalStorage {
+            root: root.path().to_owned(),
+            public_url: "http://backend.test".into(),
+            signing_key: b"test key".to_vec(),
+        };
+        (root, storage)
+    }
+
+    #[tokio::test]
+    async fn test_put_get_delete() {
+        let (_root, storage) = storage();
+        storage
+            .put_stream("original_upload/abc", Box::new(&b"audio bytes"[..]))
+            .await
+            .unwrap();
+
+        let mut content = Vec::new();
+        let mut reader = storage.get("original_upload/abc").await.unwrap().unwrap();
+        reader.read_to_end(&mut content).await.unwrap();
+        assert_eq!(content, b"audio bytes");
+
+        storage
+            .copy("original_upload/abc", "original_upload/def")
+            .await
+            .unwrap();
+        let mut copied = Vec::new();
+        let mut reader = storage.get("original_upload/def").await.unwrap().unwrap();
+        reader.read_to_end(&mut copied).await.unwrap();
+        assert_eq!(copied, b"audio bytes");
+
+        storage.delete("original_upload/abc").await.unwrap();
+        assert!(storage.get("original_upload/abc").await.unwrap().is_none());
+        storage.delete("original_upload/abc").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_multipart_upload() {
+        let (_root, storage) = storage();
+        let upload_id = storage
+            .create_multipart("original_upload/abc")
+            .await
+            .unwrap();
+        let second = storage
+            .upload_part("original_upload/abc", &upload_id, 2, b"world".to_vec())
+            .await
+            .unwrap();
+        let first = storage
+            .upload_part("original_upload/abc", &upload_id, 1, b"hello ".to_vec())
+            .await
+            .unwrap();
+        assert!(storage.list("").await.unwrap().is_empty());
+
+        storage
+            .complete_multipart("original_upload/abc", &upload_id, &[first, second])
+            .await
+            .unwrap();
+
+        let mut content = String::new();
+        let mut reader = storage.get("original_upload/abc").await.unwrap().unwrap();
+        reader.read_to_string(&mut content).await.unwrap();
+        assert_eq!(content, "hello world");
+        storage
+            .abort_multipart("original_upload/abc", &upload_id)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_list_by_prefix() {
+        let (_root, storage) = storage();
+        for key in [
+            "original_upload/a",
+            "original_upload/b",
+            "original_transcript/a",
+        ] {
+            storage
+                .put_stream(key, Box::new(&b"1234"[..]))
+                .await
+                .unwrap();
+        }
+
+        let mut keys: Vec<_> = storage
+            .list("original_upload/")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|blob| (blob.key, blob.size))
+            .collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            [
+                ("original_upload/a".into(), 4),
+                ("original_upload/b".into(), 4)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_presigned_url_is_verified() {
+        let (_root, storage) = storage();
+        let url = storage
+            .presign_get(
+                "original_upload/abc",
+                Duration::from_secs(60),
+                Some("a.wav"),
+            )
+            .await
+            .unwrap();
+        let (path, query) = url.split_once('?').unwrap();
+        assert_eq!(path, "http://backend.test/blobs/original_upload/abc");
+
+        let query: SignedQuery = serde_urlencoded::from_str(query).unwrap();
+        assert!(storage.verify("GET", "original_upload/abc", &query));
+        assert!(!storage.verify("GET", "original_upload/other", &query));
+        assert!(!storage.verify("PUT", "original_upload/abc", &query));
+
+        let expires = chrono::Utc::now().timestamp() - 1;
+        let expired = SignedQuery {
+            expires,
+            signature: hex::encode(
+                storage
+                    .mac("GET", "original_upload/abc", expires, None)
+                    .finalize()
+                    .into_bytes(),
+            ),
+            ..Default::default()
+        };
+        assert!(!storage.verify("GET", "original_upload/abc", &expired));
+    }
+
+    #[test]
+    fn test_rejects_keys_outside_root() {
+        let (_root, storage) = storage();
+        assert!(storage.path("../etc/passwd").is_err());
+        assert!(storage.path("original_upload//abc").is_err());
+        assert!(storage.path("original_upload/abc").is_ok());
+    }
+}
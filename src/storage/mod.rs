@@ -0,0 +1,237 @@
+//! Blob storage for uploaded audio and transcripts.
+
+pub mod local;
+pub mod s3;
+
+use std::time::Duration;
+
+use tokio::io::AsyncRead;
+
+pub type BoxedReader<'a> = Box<dyn AsyncRead + Send + Unpin + 'a>;
+
+/// An object returned by [`BlobStorage::list`].
+#[derive(Debug, Clone)]
+pub struct BlobInfo {
+    pub key: String,
+    pub size: u64,
+    pub last_modified: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// A stored part of a multipart upload.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct UploadedPart {
+    pub part_number: u32,
+    pub etag: String,
+}
+
+#[async_trait::async_trait]
+pub trait BlobStorage: Send + Sync {
+    /// Streams `reader` into the object at `key`, replacing any existing object.
+    async fn put_stream(&self, key: &str, reader: BoxedReader<'_>) -> eyre::Result<()>;
+
+    /// Opens the object at `key` for reading, or returns `None` if it does not exist.
+    async fn get(&self, key: &str) -> eyre::Result<Option<BoxedReader<'static>>>;
+
+    /// Like [`BlobStorage::get`], but only reads the first `length` bytes.
+    async fn get_prefix(
+        &self,
+        key: &str,
+        length: u64,
+    ) -> eyre::Result<Option<BoxedReader<'static>>>;
+
+    /// Removes the object at `key`; removing a missing object is not an error.
+    async fn delete(&self, key: &str) -> eyre::Result<()>;
+
+    /// Copies the object at `from` to `to` without downloading it, replacing any object
+    /// at `to`.
+    async fn copy(&self, from: &str, to: &str) -> eyre::Result<()>;
+
+    /// Size and modification time of the object at `key`, or `None` if it does not exist.
+    async fn head(&self, key: &str) -> eyre::Result<Option<BlobInfo>>;
+
+    /// Lists every object whose key starts with `prefix`.
+    async fn list(&self, prefix: &str) -> eyre::Result<Vec<BlobInfo>>;
+
+    /// Starts a multipart upload of the object at `key` and returns its upload id.
+    ///
+    /// Parts other than the last one must be at least 5 MiB.
+    async fn create_multipart(&self, key: &str) -> eyre::Result<String>;
+
+    /// Stores part `part_number` (starting at 1), replacing an earlier part with that number.
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: u32,
+        data: Vec<u8>,
+    ) -> eyre::Result<UploadedPart>;
+
+    /// Assembles the parts, in order, into the object at `key`.
+    async fn complete_multipart(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: &[UploadedPart],
+    ) -> eyre::Result<()>;
+
+    /// Discards a multipart upload and its parts.
+    async fn abort_multipart(&self, key: &str, upload_id: &str) -> eyre::Result<()>;
+
+    /// URL that allows anyone holding it to download the object until it expires.
+    ///
+    /// With `download_filename` set, browsers save the object under that name.
+    async fn presign_get(
+        &self,
+        key: &str,
+        expires_in: Duration,
+        download_filename: Option<&str>,
+    ) -> eyre::Result<String>;
+
+    /// URL that allows anyone holding it to `PUT` the object until it expires.
+    ///
+    /// With `checksum` set, only a body with that digest is accepted.
+    async fn presign_put(
+        &self,
+        key: &str,
+        expires_in: Duration,
+        checksum: Option<&Checksum>,
+    ) -> eyre::Result<String>;
+
+    /// URL that allows anyone holding it to `PUT` one part of a multipart upload.
+    ///
+    /// The response carries the part's `ETag`, which is needed to complete the upload.
+    /// With `checksum` set, only a part with that digest is accepted.
+    async fn presign_upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: u32,
+        expires_in: Duration,
+        checksum: Option<&Checksum>,
+    ) -> eyre::Result<String>;
+}
+
+/// Digest a presigned `PUT` is restricted to.
+///
+/// S3 only checks it if the client sends it in the [`Checksum::header`]; a SHA-256 is not
+/// accepted for parts, since S3 then needs it again to assemble them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Checksum {
+    Sha256([u8; 32]),
+    Md5([u8; 16]),
+}
+
+impl Checksum {
+    /// Parses a hex SHA-256.
+    pub fn sha256(hex: &str) -> Option<Self> {
+        hex::decode(hex).ok()?.try_into().ok().map(Checksum::Sha256)
+    }
+
+    /// Parses a hex MD5.
+    pub fn md5(hex: &str) -> Option<Self> {
+        hex::decode(hex).ok()?.try_into().ok().map(Checksum::Md5)
+    }
+
+    /// Name and value of the request header that carries the digest.
+    pub fn header(&self) -> (&'static str, String) {
+        use base64::Engine;
+        let encode = |digest: &[u8]| base64::engine::general_purpose::STANDARD.encode(digest);
+        match self {
+            Checksum::Sha256(digest) => ("x-amz-checksum-sha256", encode(digest)),
+            Checksum::Md5(digest) => ("content-md5", encode(digest)),
+        }
+    }
+
+    pub fn matches(&self, data: &[u8]) -> bool {
+        use sha2::Digest;
+        match self {
+            Checksum::Sha256(digest) => sha2::Sha256::digest(data).as_slice() == digest,
+            Checksum::Md5(digest) => md5::Md5::digest(data).as_slice() == digest,
+        }
+    }
+}
+
+impl std::fmt::Display for Checksum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Checksum::Sha256(digest) => write!(f, "sha256:{}", hex::encode(digest)),
+            Checksum::Md5(digest) => write!(f, "md5:{}", hex::encode(digest)),
+        }
+    }
+}
+
+impl std::str::FromStr for Checksum {
+    type Err = eyre::Report;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let checksum = match value.split_once(':') {
+            Some(("sha256", hex)) => Checksum::sha256(hex),
+            Some(("md5", hex)) => Checksum::md5(hex),
+            _ => None,
+        };
+        checksum.ok_or_else(|| eyre::eyre!("invalid checksum '{value}'"))
+    }
+}
+
+/// `Content-Disposition` value that downloads the object as `filename`.
+///
+/// The quoted `filename` is an ASCII approximation for old clients; `filename*` carries
+/// the exact name percent-encoded as UTF-8 (RFC 6266, RFC 5987).
+pub fn content_disposition(filename: &str) -> String {
+    let mut fallback = String::with_capacity(filename.len());
+    for c in filename.chars() {
+        match c {
+            '"' | '\\' => {
+                fallback.push('\\');
+                fallback.push(c);
+            }
+            ' '..='~' => fallback.push(c),
+            _ => fallback.push('_'),
+        }
+    }
+
+    let mut encoded = String::with_capacity(filename.len());
+    for byte in filename.bytes() {
+        if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+
+    format!("attachment; filename=\"{fallback}\"; filename*=UTF-8''{encoded}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksum() {
+        let checksum = Checksum::md5("5eb63bbbe01eeed093cb22bb8f5acdc3").unwrap();
+        assert!(checksum.matches(b"hello world"));
+        assert!(!checksum.matches(b"hello world!"));
+        assert_eq!(
+            checksum.header(),
+            ("content-md5", "XrY7u+Ae7tCTyyK7j1rNww==".into())
+        );
+        assert_eq!(checksum.to_string().parse::<Checksum>().unwrap(), checksum);
+        assert!(Checksum::sha256("5eb63bbbe01eeed093cb22bb8f5acdc3").is_none());
+    }
+
+    #[test]
+    fn test_content_disposition() {
+        assert_eq!(
+            content_disposition("talk.wav"),
+            "attachment; filename=\"talk.wav\"; filename*=UTF-8''talk.wav"
+        );
+        assert_eq!(
+            content_disposition("a \"b\"\\c.wav"),
+            "attachment; filename=\"a \\\"b\\\"\\\\c.wav\"; filename*=UTF-8''a%20%22b%22%5Cc.wav"
+        );
+        assert_eq!(
+            content_disposition("запись\r\n.wav"),
+            "attachment; filename=\"________.wav\"; filename*=UTF-8''%D0%B7%D0%B0%D0%BF%D0%B8%D1%81%D1%8C%0D%0A.wav"
+        );
+    }
+}
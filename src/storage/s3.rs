@@ -0,0 +1,306 @@
+use std::{collections::HashMap, env::var, time::Duration};
+
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use eyre::{Context, eyre};
+use futures_util::TryStreamExt;
+
+use crate::storage::{
+    BlobInfo, BlobStorage, BoxedReader, Checksum, UploadedPart, content_disposition,
+};
+
+pub struct S3Storage {
+    bucket: Box<s3::Bucket>,
+}
+
+impl S3Storage {
+    pub fn from_env() -> eyre::Result<Self> {
+        let bucket = s3::Bucket::new(
+            &var("AWS_BUCKET").wrap_err("AWS_BUCKET must be set")?,
+            s3::Region::Custom {
+                region: var("AWS_REGION").wrap_err("AWS_REGION must be set")?,
+                endpoint: var("AWS_ENDPOINT_URL").wrap_err("AWS_ENDPOINT_URL must be set")?,
+            },
+            s3::creds::Credentials::new(
+                Some(&var("AWS_ACCESS_KEY_ID").wrap_err("AWS_ACCESS_KEY_ID must be set")?),
+                Some(&var("AWS_SECRET_ACCESS_KEY").wrap_err("AWS_SECRET_ACCESS_KEY must be set")?),
+                None,
+                None,
+                None,
+            )
+            .wrap_err("s3 credentials should be valid")?,
+        )
+        .wrap_err("s3 bucket should be valid")?
+        .with_path_style();
+        Ok(S3Storage { bucket })
+    }
+}
+
+fn is_not_found(error: &s3::error::S3Error) -> bool {
+    matches!(error, s3::error::S3Error::HttpFailWithBody(404, _))
+}
+
+/// Signing the checksum header makes S3 reject bodies without that digest.
+fn checksum_headers(checksum: &Checksum) -> eyre::Result<HeaderMap> {
+    let (name, value) = checksum.header();
+    let mut headers = HeaderMap::new();
+    headers.insert(HeaderName::from_static(name), HeaderValue::try_from(value)?);
+    Ok(headers)
+}
+
+#[async_trait::async_trait]
+impl BlobStorage for S3Storage {
+    async fn put_stream(&self, key: &str, mut reader: BoxedReader<'_>) -> eyre::Result<()> {
+        let response = self
+            .bucket
+            .put_object_stream_builder(key)
+            .execute_stream(&mut reader)
+            .await?;
+        if response.status_code() != 200 {
+            return Err(eyre!(
+                "failed to upload {key} to storage (status code: {})",
+                response.status_code()
+            ));
+        }
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> eyre::Result<Option<BoxedReader<'static>>> {
+        let response = match self.bucket.get_object_stream(key).await {
+            Ok(response) => response,
+            Err(error) if is_not_found(&error) => return Ok(None),
+            Err(error) => return Err(error.into()),
+        };
+        match response.status_code {
+            200 => {}
+            404 => return Ok(None),
+            status => {
+                return Err(eyre!(
+                    "failed to read {key} from storage (status code: {status})"
+                ));
+            }
+        }
+        let stream = response.bytes.map_err(std::io::Error::other);
+        Ok(Some(Box::new(tokio_util::io::StreamReader::new(stream))))
+    }
+
+    async fn get_prefix(
+        &self,
+        key: &str,
+        length: u64,
+    ) -> eyre::Result<Option<BoxedReader<'static>>> {
+        if length == 0 {
+            return Ok(Some(Box::new(tokio::io::empty())));
+        }
+        let response = match self.bucket.get_object_range(key, 0, Some(length - 1)).await {
+            Ok(response) => response,
+            Err(error) if is_not_found(&error) => return Ok(None),
+            // the range of an empty object is not satisfiable
+            Err(s3::error::S3Error::HttpFailWithBody(416, _)) => {
+                return Ok(Some(Box::new(tokio::io::empty())));
+            }
+            Err(error) => return Err(error.into()),
+        };
+        match response.status_code() {
+            200 | 206 => {}
+            404 => return Ok(None),
+            status => {
+                return Err(eyre!(
+                    "failed to read {key} from storage (status code: {status})"
+                ));
+            }
+        }
+        Ok(Some(Box::new(std::io::Cursor::new(
+            response.as_slice().to_vec(),
+        ))))
+    }
+
+    async fn delete(&self, key: &str) -> eyre::Result<()> {
+        let response = match self.bucket.delete_object(key).await {
+            Ok(response) => response,
+            Err(error) if is_not_found(&error) => return Ok(()),
+            Err(error) => return Err(error.into()),
+        };
+        match response.status_code() {
+            200 | 204 | 404 => Ok(()),
+            status => Err(eyre!(
+                "failed to delete {key} from storage (status code: {status})"
+            )),
+        }
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> eyre::Result<()> {
+        match self.bucket.copy_object_internal(from, to).await? {
+            200 => Ok(()),
+            status => Err(eyre!(
+                "failed to copy {from} to {to} in storage (status code: {status})"
+            )),
+        }
+    }
+
+    async fn head(&self, key: &str) -> eyre::Result<Option<BlobInfo>> {
+        let (head, status) = match self.bucket.head_object(key).await {
+            Ok(response) => response,
+            Err(error) if is_not_found(&error) => return Ok(None),
+            Err(error) => return Err(error.into()),
+        };
+        match status {
+            200 => {}
+            404 => return Ok(None),
+            status => {
+                return Err(eyre!(
+                    "failed to inspect {key} in storage (status code: {status})"
+                ));
+            }
+        }
+        Ok(Some(BlobInfo {
+            key: key.to_owned(),
+            size: head.content_length.unwrap_or_default().max(0) as u64,
+            last_modified: head
+                .last_modified
+                .and_then(|modified| chrono::DateTime::parse_from_rfc2822(&modified).ok())
+                .map(|modified| modified.to_utc()),
+        }))
+    }
+
+    async fn list(&self, prefix: &str) -> eyre::Result<Vec<BlobInfo>> {
+        let pages = self.bucket.list(prefix.to_owned(), None).await?;
+        Ok(pages
+            .into_iter()
+            .flat_map(|page| page.contents)
+            .map(|object| BlobInfo {
+                last_modified: chrono::DateTime::parse_from_rfc3339(&object.last_modified)
+                    .ok()
+                    .map(|time| time.to_utc()),
+                key: object.key,
+                size: object.size,
+            })
+            .collect())
+    }
+
+    async fn create_multipart(&self, key: &str) -> eyre::Result<String> {
+        let response = self
+            .bucket
+            .initiate_multipart_upload(key, "application/octet-stream")
+            .await?;
+        Ok(response.upload_id)
+    }
+
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: u32,
+        data: Vec<u8>,
+    ) -> eyre::Result<UploadedPart> {
+        let part = self
+            .bucket
+            .put_multipart_chunk(
+                data,
+                key,
+                part_number,
+                upload_id,
+                "application/octet-stream",
+            )
+            .await?;
+        Ok(UploadedPart {
+            part_number: part.part_number,
+            etag: part.etag,
+        })
+    }
+
+    async fn complete_multipart(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: &[UploadedPart],
+    ) -> eyre::Result<()> {
+        let parts = parts
+            .iter()
+            .map(|part| s3::serde_types::Part {
+                part_number: part.part_number,
+                etag: part.etag.clone(),
+            })
+            .collect();
+        let response = self
+            .bucket
+            .complete_multipart_upload(key, upload_id, parts)
+            .await?;
+        if response.status_code() != 200 {
+            return Err(eyre!(
+                "failed to complete upload of {key} (status code: {})",
+                response.status_code()
+            ));
+        }
+        Ok(())
+    }
+
+    async fn abort_multipart(&self, key: &str, upload_id: &str) -> eyre::Result<()> {
+        match self.bucket.abort_upload(key, upload_id).await {
+            Ok(()) => Ok(()),
+            Err(error) if is_not_found(&error) => Ok(()),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    async fn presign_get(
+        &self,
+        key: &str,
+        expires_in: Duration,
+        download_filename: Option<&str>,
+    ) -> eyre::Result<String> {
+        let custom_queries = download_filename.map(|filename| {
+            let mut queries = HashMap::new();
+            queries.insert(
+                "response-content-disposition".to_owned(),
+                content_disposition(filename),
+            );
+            queries
+        });
+        Ok(self
+            .bucket
+            .presign_get(key, expires_in.as_secs() as u32, custom_queries)
+            .await?)
+    }
+
+    async fn presign_put(
+        &self,
+        key: &str,
+        expires_in: Duration,
+        checksum: Option<&Checksum>,
+    ) -> eyre::Result<String> {
+        Ok(self
+            .bucket
+            .presign_put(
+                key,
+                expires_in.as_secs() as u32,
+                checksum.map(checksum_headers).transpose()?,
+                None,
+            )
+            .await?)
+    }
+
+    async fn presign_upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: u32,
+        expires_in: Duration,
+        checksum: Option<&Checksum>,
+    ) -> eyre::Result<String> {
+        if let Some(Checksum::Sha256(_)) = checksum {
+            return Err(eyre!("parts can only be restricted to an MD5"));
+        }
+        let mut queries = HashMap::new();
+        queries.insert("partNumber".to_owned(), part_number.to_string());
+        queries.insert("uploadId".to_owned(), upload_id.to_owned());
+        Ok(self
+            .bucket
+            .presign_put(
+                key,
+                expires_in.as_secs() as u32,
+                checksum.map(checksum_headers).transpose()?,
+                Some(queries),
+            )
+            .await?)
+    }
+}
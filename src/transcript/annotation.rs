@@ -0,0 +1,342 @@
+//! Export of interval tiers as Praat TextGrid and ELAN EAF files.
+
+use std::{collections::BTreeSet, fmt::Write};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnnotationFormat {
+    TextGrid,
+    Eaf,
+}
+
+impl AnnotationFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            AnnotationFormat::TextGrid => "text/plain; charset=utf-8",
+            AnnotationFormat::Eaf => "application/xml; charset=utf-8",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            AnnotationFormat::TextGrid => "TextGrid",
+            AnnotationFormat::Eaf => "eaf",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tier {
+    pub name: String,
+    pub intervals: Vec<Interval>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Interval {
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+}
+
+/// The audio an EAF file links to.
+pub struct EafMedia<'a> {
+    pub filename: &'a str,
+    pub mime_type: &'a str,
+}
+
+/// Sorts the intervals and trims overlaps, which neither Praat nor ELAN accept within a
+/// tier; intervals left without duration are dropped.
+fn tidy(intervals: &[Interval]) -> Vec<Interval> {
+    let mut sorted = intervals.to_vec();
+    sorted.sort_by(|a, b| a.start.total_cmp(&b.start));
+    let mut tidied: Vec<Interval> = Vec::with_capacity(sorted.len());
+    for mut interval in sorted {
+        interval.start = interval.start.max(0.0);
+        if let Some(previous) = tidied.last() {
+            interval.start = interval.start.max(previous.end);
+        }
+        if interval.end > interval.start {
+            interval.text = interval
+                .text
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ");
+            tidied.push(interval);
+        }
+    }
+    tidied
+}
+
+/// Tier names made unique by numbering repeats, since both tools look tiers up by name.
+fn unique_names(tiers: &[Tier]) -> Vec<String> {
+    let mut seen = BTreeSet::new();
+    tiers
+        .iter()
+        .map(|tier| {
+            let mut name = tier.name.clone();
+            let mut copy = 1;
+            while !seen.insert(name.clone()) {
+                copy += 1;
+                name = format!("{} ({copy})", tier.name);
+            }
+            name
+        })
+        .collect()
+}
+
+/// Writes a TextGrid in Praat's long text format, covering `0..duration` or up to the
+/// last interval if that ends later; gaps between intervals become empty intervals.
+pub fn textgrid(tiers: &[Tier], duration: f64) -> String {
+    let tiers: Vec<(String, Vec<Interval>)> = unique_names(tiers)
+        .into_iter()
+        .zip(tiers.iter().map(|tier| tidy(&tier.intervals)))
+        .collect();
+    let xmax = tiers
+        .iter()
+        .filter_map(|(_, intervals)| intervals.last())
+        .map(|interval| interval.end)
+        .fold(duration.max(0.0), f64::max);
+
+    let mut output = String::new();
+    let _ = write!(
+        output,
+        "File type = \"ooTextFile\"\nObject class = \"TextGrid\"\n\nxmin = 0\nxmax = {xmax}\ntiers? <exists>\nsize = {}\nitem []:\n",
+        tiers.len()
+    );
+    for (index, (name, intervals)) in tiers.iter().enumerate() {
+        let mut filled = Vec::with_capacity(intervals.len() * 2 + 1);
+        let mut position = 0.0;
+        for interval in intervals {
+            if interval.start > position {
+                filled.push((position, interval.start, ""));
+            }
+            filled.push((interval.start, interval.end, interval.text.as_str()));
+            position = interval.end;
+        }
+        if position < xmax || filled.is_empty() {
+            filled.push((position, xmax, ""));
+        }
+
+        let _ = write!(
+            output,
+            "    item [{}]:\n        class = \"IntervalTier\"\n        name = \"{}\"\n        xmin = 0\n        xmax = {xmax}\n        intervals: size = {}\n",
+            index + 1,
+            praat_escape(name),
+            filled.len()
+        );
+        for (number, (start, end, text)) in filled.iter().enumerate() {
+            let _ = write!(
+                output,
+                "        intervals [{}]:\n            xmin = {start}\n            xmax = {end}\n            text = \"{}\"\n",
+                number + 1,
+                praat_escape(text)
+            );
+        }
+    }
+    output
+}
+
+fn praat_escape(text: &str) -> String {
+    text.replace('"', "\"\"")
+}
+
+/// Writes an EAF 3.0 document with one time-aligned tier per [`Tier`].
+pub fn eaf(tiers: &[Tier], media: Option<&EafMedia>, created_at: DateTime<Utc>) -> String {
+    let tiers: Vec<(String, Vec<Interval>)> = unique_names(tiers)
+        .into_iter()
+        .zip(tiers.iter().map(|tier| tidy(&tier.intervals)))
+        .collect();
+    let millis = |seconds: f64| (seconds * 1000.0).round() as u64;
+    let slots: BTreeSet<u64> = tiers
+        .iter()
+        .flat_map(|(_, intervals)| intervals)
+        .flat_map(|interval| [millis(interval.start), millis(interval.end)])
+        .collect();
+    let slot_id = |seconds: f64| {
+        let index = slots.iter().position(|&slot| slot == millis(seconds));
+        format!("ts{}", index.expect("slot should exist") + 1)
+    };
+
+    let mut output = String::new();
+    let _ = write!(
+        output,
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<ANNOTATION_DOCUMENT AUTHOR=\"\" DATE=\"{}\" FORMAT=\"3.0\" VERSION=\"3.0\" xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" xsi:noNamespaceSchemaLocation=\"http://www.mpi.nl/tools/elan/EAFv3.0.xsd\">\n    <HEADER MEDIA_FILE=\"\" TIME_UNITS=\"milliseconds\">\n",
+        created_at.to_rfc3339_opts(SecondsFormat::Secs, false)
+    );
+    if let Some(media) = media {
+        let _ = writeln!(
+            output,
+            "        <MEDIA_DESCRIPTOR MEDIA_URL=\"file:///{0}\" MIME_TYPE=\"{1}\" RELATIVE_MEDIA_URL=\"./{0}\"/>",
+            xml_escape(&percent_encode(media.filename)),
+            xml_escape(media.mime_type)
+        );
+    }
+    output.push_str("    </HEADER>\n    <TIME_ORDER>\n");
+    for (index, slot) in slots.iter().enumerate() {
+        let _ = writeln!(
+            output,
+            "        <TIME_SLOT TIME_SLOT_ID=\"ts{}\" TIME_VALUE=\"{slot}\"/>",
+            index + 1
+        );
+    }
+    output.push_str("    </TIME_ORDER>\n");
+
+    let mut annotation = 0;
+    for (name, intervals) in &tiers {
+        let _ = writeln!(
+            output,
+            "    <TIER LINGUISTIC_TYPE_REF=\"default-lt\" TIER_ID=\"{}\">",
+            xml_escape(name)
+        );
+        for interval in intervals {
+            annotation += 1;
+            let _ = write!(
+                output,
+                "        <ANNOTATION>\n            <ALIGNABLE_ANNOTATION ANNOTATION_ID=\"a{annotation}\" TIME_SLOT_REF1=\"{}\" TIME_SLOT_REF2=\"{}\">\n                <ANNOTATION_VALUE>{}</ANNOTATION_VALUE>\n            </ALIGNABLE_ANNOTATION>\n        </ANNOTATION>\n",
+                slot_id(interval.start),
+                slot_id(interval.end),
+                xml_escape(&interval.text)
+            );
+        }
+        output.push_str("    </TIER>\n");
+    }
+    output.push_str(concat!(
+        "    <LINGUISTIC_TYPE GRAPHIC_REFERENCES=\"false\" LINGUISTIC_TYPE_ID=\"default-lt\" TIME_ALIGNABLE=\"true\"/>\n",
+        "    <CONSTRAINT DESCRIPTION=\"Time subdivision of parent annotation's time interval, no time gaps allowed within this interval\" STEREOTYPE=\"Time_Subdivision\"/>\n",
+        "    <CONSTRAINT DESCRIPTION=\"Symbolic subdivision of a parent annotation. Annotations refering to the same parent are ordered\" STEREOTYPE=\"Symbolic_Subdivision\"/>\n",
+        "    <CONSTRAINT DESCRIPTION=\"1-1 association with a parent annotation\" STEREOTYPE=\"Symbolic_Association\"/>\n",
+        "    <CONSTRAINT DESCRIPTION=\"Time alignable annotations within the parent annotation's time interval, gaps are allowed\" STEREOTYPE=\"Included_In\"/>\n",
+        "</ANNOTATION_DOCUMENT>\n",
+    ));
+    output
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Escapes characters that are not allowed in a file URL.
+fn percent_encode(filename: &str) -> String {
+    let mut encoded = String::with_capacity(filename.len());
+    for byte in filename.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            let _ = write!(encoded, "%{byte:02X}");
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interval(start: f64, end: f64, text: &str) -> Interval {
+        Interval {
+            start,
+            end,
+            text: text.into(),
+        }
+    }
+
+    fn tiers() -> Vec<Tier> {
+        vec![
+            Tier {
+                name: "Anna".into(),
+                intervals: vec![
+                    interval(1.5, 2.0, "say \"hi\""),
+                    interval(0.5, 1.75, "first"),
+                ],
+            },
+            Tier {
+                name: "Anna".into(),
+                intervals: vec![],
+            },
+        ]
+    }
+
+    #[test]
+    fn test_textgrid() {
+        assert_eq!(
+            textgrid(&tiers(), 3.0),
+            r#"File type = "ooTextFile"
+Object class = "TextGrid"
+
+xmin = 0
+xmax = 3
+tiers? <exists>
+size = 2
+item []:
+    item [1]:
+        class = "IntervalTier"
+        name = "Anna"
+        xmin = 0
+        xmax = 3
+        intervals: size = 4
+        intervals [1]:
+            xmin = 0
+            xmax = 0.5
+            text = ""
+        intervals [2]:
+            xmin = 0.5
+            xmax = 1.75
+            text = "first"
+        intervals [3]:
+            xmin = 1.75
+            xmax = 2
+            text = "say ""hi"""
+        intervals [4]:
+            xmin = 2
+            xmax = 3
+            text = ""
+    item [2]:
+        class = "IntervalTier"
+        name = "Anna (2)"
+        xmin = 0
+        xmax = 3
+        intervals: size = 1
+        intervals [1]:
+            xmin = 0
+            xmax = 3
+            text = ""
+"#
+        );
+    }
+
+    #[test]
+    fn test_textgrid_round_trips() {
+        let exported = textgrid(&tiers(), 0.0);
+        let segments = super::super::textgrid::parse(&exported).unwrap();
+        let texts: Vec<&str> = segments.iter().map(|s| s.text.as_str()).collect();
+        assert_eq!(texts, ["first", "say \"hi\""]);
+    }
+
+    #[test]
+    fn test_eaf() {
+        let media = EafMedia {
+            filename: "talk 1.wav",
+            mime_type: "audio/x-wav",
+        };
+        let created_at = DateTime::parse_from_rfc3339("2026-03-01T12:00:00Z")
+            .unwrap()
+            .to_utc();
+        let exported = eaf(&tiers(), Some(&media), created_at);
+        assert!(exported.contains("DATE=\"2026-03-01T12:00:00+00:00\""));
+        assert!(exported.contains(
+            "MEDIA_URL=\"file:///talk%201.wav\" MIME_TYPE=\"audio/x-wav\" RELATIVE_MEDIA_URL=\"./talk%201.wav\""
+        ));
+        assert!(exported.contains("<TIME_SLOT TIME_SLOT_ID=\"ts1\" TIME_VALUE=\"500\"/>"));
+        assert!(exported.contains("<TIME_SLOT TIME_SLOT_ID=\"ts3\" TIME_VALUE=\"2000\"/>"));
+        assert!(exported.contains(
+            "ANNOTATION_ID=\"a2\" TIME_SLOT_REF1=\"ts2\" TIME_SLOT_REF2=\"ts3\">\n                <ANNOTATION_VALUE>say &quot;hi&quot;</ANNOTATION_VALUE>"
+        ));
+        assert!(exported.contains("TIER_ID=\"Anna (2)\""));
+    }
+}
@@ -0,0 +1,112 @@
+//! JSON segments, either as a bare array or as `{"segments": [...]}` like the canonical
+//! format: `{"speaker": "A", "start": 1.5, "end": 3.0, "text": "..."}`, where only `text`
+//! is required.
+
+use serde_json::value::RawValue;
+
+use super::{ParseError, Segment};
+
+#[derive(serde::Deserialize)]
+struct RawSegment {
+    speaker: Option<String>,
+    start: Option<f64>,
+    end: Option<f64>,
+    text: String,
+}
+
+#[derive(serde::Deserialize)]
+struct Document<'a> {
+    #[serde(borrow)]
+    segments: Vec<&'a RawValue>,
+}
+
+pub fn parse(contents: &str) -> Result<Vec<Segment>, ParseError> {
+    // segments are kept raw first so errors can be traced back to the line they start on
+    let raw_segments = if contents.trim_start().starts_with('[') {
+        serde_json::from_str::<Vec<&RawValue>>(contents)
+    } else {
+        serde_json::from_str::<Document>(contents).map(|document| document.segments)
+    }
+    .map_err(|why| json_error(1, &why))?;
+
+    let mut segments = Vec::new();
+    for raw in raw_segments {
+        let offset = raw.get().as_ptr() as usize - contents.as_ptr() as usize;
+        let line = contents[..offset].matches('\n').count() + 1;
+        let segment: RawSegment =
+            serde_json::from_str(raw.get()).map_err(|why| json_error(line, &why))?;
+        match (segment.start, segment.end) {
+            (Some(start), Some(_)) if start < 0.0 => {
+                return Err(ParseError::new(
+                    line,
+                    format!("segment starts at {start}, before the recording"),
+                ));
+            }
+            (Some(start), Some(end)) if end < start => {
+                return Err(ParseError::new(line, "segment ends before it starts"));
+            }
+            (Some(_), None) | (None, Some(_)) => {
+                return Err(ParseError::new(
+                    line,
+                    "segment must have both start and end, or neither",
+                ));
+            }
+            _ => {}
+        }
+
+        let text = segment.text.trim();
+        if !text.is_empty() {
+            segments.push(Segment {
+                speaker: segment.speaker.filter(|speaker| !speaker.is_empty()),
+                start: segment.start,
+                end: segment.end,
+                text: text.to_owned(),
+            });
+        }
+    }
+    Ok(segments)
+}
+
+/// Converts an error from parsing JSON that starts on `first_line`.
+fn json_error(first_line: usize, error: &serde_json::Error) -> ParseError {
+    // serde_json appends the position to the message
+    let message = error.to_string();
+    let message = message
+        .rsplit_once(" at line ")
+        .map_or(message.as_str(), |(message, _)| message);
+    ParseError::new(first_line + error.line().max(1) - 1, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let wrapped = r#"{"segments": [{"speaker": "A", "start": 0, "end": 1.5, "text": " hi ", "words": []}]}"#;
+        assert_eq!(
+            parse(wrapped).unwrap(),
+            vec![Segment {
+                speaker: Some("A".into()),
+                start: Some(0.0),
+                end: Some(1.5),
+                text: "hi".into(),
+            }]
+        );
+        let bare = r#"[{"text": "untimed"}, {"text": ""}]"#;
+        assert_eq!(parse(bare).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_errors_point_at_line() {
+        let error = parse("[\n{\"text\": \"ok\"},\n{\"start\": 2, \"end\": 1, \"text\": \"x\"}\n]")
+            .unwrap_err();
+        assert_eq!(error, ParseError::new(3, "segment ends before it starts"));
+        assert_eq!(
+            parse("{\"segments\": [\n{\"start\": 1}\n]}")
+                .unwrap_err()
+                .line,
+            2
+        );
+    }
+}
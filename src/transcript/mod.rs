@@ -0,0 +1,251 @@
+//! Parsing of uploaded transcripts and rendering of exported ones.
+//!
+//! Transcripts in any of the supported formats are converted to a [`Transcript`], which is
+//! stored as JSON and handed to the analysis service instead of the original file.
+
+pub mod annotation;
+mod json;
+pub mod render;
+mod srt;
+mod text;
+mod textgrid;
+mod vtt;
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Transcript {
+    pub segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Segment {
+    pub speaker: Option<String>,
+    /// Seconds from the start of the recording; missing for plain text transcripts.
+    pub start: Option<f64>,
+    pub end: Option<f64>,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TranscriptFormat {
+    Srt,
+    WebVtt,
+    Text,
+    TextGrid,
+    Json,
+}
+
+impl TranscriptFormat {
+    /// Picks the format by file extension, falling back to the contents.
+    pub fn detect(filename: Option<&str>, contents: &str) -> Self {
+        let extension = filename
+            .and_then(|filename| filename.rsplit_once('.'))
+            .map(|(_, extension)| extension.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("srt") => return TranscriptFormat::Srt,
+            Some("vtt") => return TranscriptFormat::WebVtt,
+            Some("txt") => return TranscriptFormat::Text,
+            Some("textgrid") => return TranscriptFormat::TextGrid,
+            Some("json") => return TranscriptFormat::Json,
+            _ => {}
+        }
+
+        let contents = contents.trim_start_matches('\u{feff}').trim_start();
+        let first_line = contents.lines().next().unwrap_or_default();
+        if first_line.starts_with("WEBVTT") {
+            TranscriptFormat::WebVtt
+        } else if first_line.contains("ooTextFile") {
+            TranscriptFormat::TextGrid
+        } else if contents.starts_with(['{', '[']) {
+            TranscriptFormat::Json
+        } else if first_line.trim().parse::<u64>().is_ok()
+            && contents
+                .lines()
+                .nth(1)
+                .is_some_and(|line| line.contains("-->"))
+        {
+            TranscriptFormat::Srt
+        } else {
+            TranscriptFormat::Text
+        }
+    }
+
+    pub fn parse(self, contents: &str) -> Result<Transcript, ParseError> {
+        let contents = contents.strip_prefix('\u{feff}').unwrap_or(contents);
+        let segments = match self {
+            TranscriptFormat::Srt => srt::parse(contents)?,
+            TranscriptFormat::WebVtt => vtt::parse(contents)?,
+            TranscriptFormat::Text => text::parse(contents)?,
+            TranscriptFormat::TextGrid => textgrid::parse(contents)?,
+            TranscriptFormat::Json => json::parse(contents)?,
+        };
+        if segments.is_empty() {
+            return Err(ParseError::new(1, "transcript contains no text"));
+        }
+        Ok(Transcript { segments })
+    }
+}
+
+impl fmt::Display for TranscriptFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TranscriptFormat::Srt => "SRT",
+            TranscriptFormat::WebVtt => "WebVTT",
+            TranscriptFormat::Text => "plain text",
+            TranscriptFormat::TextGrid => "TextGrid",
+            TranscriptFormat::Json => "JSON",
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    /// 1-based line the problem was found on.
+    pub line: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    fn new(line: usize, message: impl Into<String>) -> Self {
+        ParseError {
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Decodes UTF-8, or UTF-16 with a byte order mark as Praat writes it.
+pub fn decode(bytes: &[u8]) -> Result<String, ParseError> {
+    let utf16 = match bytes {
+        [0xff, 0xfe, rest @ ..] => Some((rest, u16::from_le_bytes as fn([u8; 2]) -> u16)),
+        [0xfe, 0xff, rest @ ..] => Some((rest, u16::from_be_bytes as fn([u8; 2]) -> u16)),
+        _ => None,
+    };
+    if let Some((rest, from_bytes)) = utf16 {
+        let units = rest
+            .chunks_exact(2)
+            .map(|pair| from_bytes([pair[0], pair[1]]));
+        return char::decode_utf16(units)
+            .collect::<Result<String, _>>()
+            .map_err(|_| ParseError::new(1, "file is not valid UTF-16"));
+    }
+
+    String::from_utf8(bytes.to_vec()).map_err(|why| {
+        let valid = &bytes[..why.utf8_error().valid_up_to()];
+        let line = valid.iter().filter(|&&byte| byte == b'\n').count() + 1;
+        ParseError::new(line, "file is not valid UTF-8")
+    })
+}
+
+/// Parses `[HH:]MM:SS[.mmm]` into seconds; both `.` and `,` separate the fraction.
+fn parse_timestamp(value: &str) -> Option<f64> {
+    let parts: Vec<&str> = value.split(':').collect();
+    let (hours, minutes, seconds) = match parts[..] {
+        [hours, minutes, seconds] => (hours, minutes, seconds),
+        [minutes, seconds] => ("0", minutes, seconds),
+        _ => return None,
+    };
+    let (whole, fraction) = seconds.split_once(['.', ',']).unwrap_or((seconds, "0"));
+    let is_digits = |part: &str| !part.is_empty() && part.bytes().all(|byte| byte.is_ascii_digit());
+    if ![hours, minutes, whole, fraction].into_iter().all(is_digits) {
+        return None;
+    }
+
+    let minutes: u32 = minutes.parse().ok()?;
+    let whole: u32 = whole.parse().ok()?;
+    if minutes >= 60 || whole >= 60 {
+        return None;
+    }
+    let hours: f64 = hours.parse().ok()?;
+    let fraction: f64 = format!("0.{fraction}").parse().ok()?;
+    Some(hours * 3600.0 + minutes as f64 * 60.0 + whole as f64 + fraction)
+}
+
+/// Parses a cue timing line like `00:00:01,000 --> 00:00:02,500`; anything after the end
+/// timestamp, such as WebVTT cue settings, is ignored.
+fn parse_cue_timing(line: &str) -> Option<(f64, f64)> {
+    let (start, rest) = line.split_once("-->")?;
+    let end = rest.split_whitespace().next()?;
+    Some((parse_timestamp(start.trim())?, parse_timestamp(end)?))
+}
+
+fn check_timing(line: usize, start: f64, end: f64) -> Result<(), ParseError> {
+    if end < start {
+        return Err(ParseError::new(line, "segment ends before it starts"));
+    }
+    Ok(())
+}
+
+/// Removes markup like `<i>` or `<c.yellow>` from cue text.
+fn strip_tags(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut in_tag = false;
+    for c in text.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => stripped.push(c),
+            _ => {}
+        }
+    }
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        let detect = |filename, contents| TranscriptFormat::detect(filename, contents);
+        assert_eq!(detect(Some("a.SRT"), ""), TranscriptFormat::Srt);
+        assert_eq!(detect(Some("a.TextGrid"), ""), TranscriptFormat::TextGrid);
+        assert_eq!(detect(None, "WEBVTT\n\n"), TranscriptFormat::WebVtt);
+        assert_eq!(
+            detect(Some("upload"), "1\n00:00:01,000 --> 00:00:02,000\nhi\n"),
+            TranscriptFormat::Srt
+        );
+        assert_eq!(
+            detect(None, "File type = \"ooTextFile\"\n"),
+            TranscriptFormat::TextGrid
+        );
+        assert_eq!(
+            detect(None, "  [{\"text\": \"hi\"}]"),
+            TranscriptFormat::Json
+        );
+        assert_eq!(detect(None, "hello there\n"), TranscriptFormat::Text);
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("01:02:03,250"), Some(3723.25));
+        assert_eq!(parse_timestamp("02:03.5"), Some(123.5));
+        assert_eq!(parse_timestamp("00:61:00.000"), None);
+        assert_eq!(parse_timestamp("1:2"), Some(62.0));
+        assert_eq!(parse_timestamp("a:00:00"), None);
+        assert_eq!(parse_timestamp("00:00:-1"), None);
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode(b"hi").unwrap(), "hi");
+        assert_eq!(decode(&[0xff, 0xfe, b'h', 0, b'i', 0]).unwrap(), "hi");
+        assert_eq!(decode(&[0xfe, 0xff, 0, b'h', 0, b'i']).unwrap(), "hi");
+        assert_eq!(decode(b"one\ntwo \xff").unwrap_err().line, 2);
+    }
+
+    #[test]
+    fn test_empty_transcript_is_rejected() {
+        let error = TranscriptFormat::Text.parse("\n  \n").unwrap_err();
+        assert_eq!(error, ParseError::new(1, "transcript contains no text"));
+    }
+}
@@ -0,0 +1,185 @@
+//! Rendering of segments as subtitles or plain text.
+
+use std::fmt::Write;
+
+use super::Segment;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SubtitleFormat {
+    Srt,
+    Vtt,
+    Txt,
+}
+
+impl SubtitleFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            SubtitleFormat::Srt => "application/x-subrip; charset=utf-8",
+            SubtitleFormat::Vtt => "text/vtt; charset=utf-8",
+            SubtitleFormat::Txt => "text/plain; charset=utf-8",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            SubtitleFormat::Srt => "srt",
+            SubtitleFormat::Vtt => "vtt",
+            SubtitleFormat::Txt => "txt",
+        }
+    }
+
+    /// Renders the segments, prefixing each line with the speaker.
+    ///
+    /// Text keeps the given order. Subtitle cues are sorted by start time, which WebVTT
+    /// requires and SRT players expect; simultaneous cues keep the given order.
+    pub fn render(self, segments: &[Segment]) -> String {
+        let mut segments: Vec<&Segment> = segments.iter().collect();
+        if self != SubtitleFormat::Txt {
+            segments.sort_by(|a, b| {
+                a.start
+                    .unwrap_or_default()
+                    .total_cmp(&b.start.unwrap_or_default())
+            });
+        }
+
+        let mut output = String::new();
+        if self == SubtitleFormat::Vtt {
+            output.push_str("WEBVTT\n\n");
+        }
+        for (index, segment) in segments.iter().enumerate() {
+            let start = segment.start.unwrap_or_default();
+            let end = segment.end.unwrap_or(start);
+            let line = cue_text(segment);
+            match self {
+                SubtitleFormat::Srt => {
+                    let _ = write!(
+                        output,
+                        "{}\n{} --> {}\n{line}\n\n",
+                        index + 1,
+                        timestamp(start, ','),
+                        timestamp(end, ',')
+                    );
+                }
+                SubtitleFormat::Vtt => {
+                    let line = line
+                        .replace('&', "&amp;")
+                        .replace('<', "&lt;")
+                        .replace('>', "&gt;");
+                    let _ = write!(
+                        output,
+                        "{} --> {}\n{line}\n\n",
+                        timestamp(start, '.'),
+                        timestamp(end, '.')
+                    );
+                }
+                SubtitleFormat::Txt => {
+                    let _ = writeln!(output, "{line}");
+                }
+            }
+        }
+        output
+    }
+}
+
+/// The segment text on one line; blank lines would end the cue early.
+fn cue_text(segment: &Segment) -> String {
+    let text = segment
+        .text
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    match &segment.speaker {
+        Some(speaker) => format!("{speaker}: {text}"),
+        None => text,
+    }
+}
+
+/// Formats seconds as `HH:MM:SS` followed by `separator` and milliseconds.
+fn timestamp(seconds: f64, separator: char) -> String {
+    let millis = (seconds.max(0.0) * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}{separator}{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segments() -> Vec<Segment> {
+        vec![
+            Segment {
+                speaker: Some("Anna".into()),
+                start: Some(1.5),
+                end: Some(3.0),
+                text: "Hello\n\nthere".into(),
+            },
+            Segment {
+                speaker: Some("Channel 1".into()),
+                start: Some(3661.0004),
+                end: Some(3662.25),
+                text: "a < b".into(),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_render_srt() {
+        assert_eq!(
+            SubtitleFormat::Srt.render(&segments()),
+            "1\n00:00:01,500 --> 00:00:03,000\nAnna: Hello there\n\n2\n01:01:01,000 --> 01:01:02,250\nChannel 1: a < b\n\n"
+        );
+    }
+
+    #[test]
+    fn test_render_vtt() {
+        assert_eq!(
+            SubtitleFormat::Vtt.render(&segments()),
+            "WEBVTT\n\n00:00:01.500 --> 00:00:03.000\nAnna: Hello there\n\n01:01:01.000 --> 01:01:02.250\nChannel 1: a &lt; b\n\n"
+        );
+    }
+
+    #[test]
+    fn test_render_channels_in_order() {
+        // two channels listed one after another, as exported without merging
+        let segment = |speaker: &str, start: f64, text: &str| Segment {
+            speaker: Some(speaker.into()),
+            start: Some(start),
+            end: Some(start + 1.0),
+            text: text.into(),
+        };
+        let segments = vec![
+            segment("Channel 0", 0.0, "first"),
+            segment("Channel 0", 4.0, "third"),
+            segment("Channel 1", 2.0, "second"),
+            segment("Channel 1", 4.0, "fourth"),
+        ];
+
+        let cues = |output: String| -> Vec<String> {
+            output
+                .lines()
+                .filter_map(|line| line.split_once(": ").map(|(_, text)| text.to_owned()))
+                .collect()
+        };
+        let chronological = ["first", "second", "third", "fourth"];
+        assert_eq!(cues(SubtitleFormat::Srt.render(&segments)), chronological);
+        assert_eq!(cues(SubtitleFormat::Vtt.render(&segments)), chronological);
+        assert_eq!(
+            cues(SubtitleFormat::Txt.render(&segments)),
+            ["first", "third", "second", "fourth"]
+        );
+    }
+
+    #[test]
+    fn test_render_txt() {
+        assert_eq!(
+            SubtitleFormat::Txt.render(&segments()),
+            "Anna: Hello there\nChannel 1: a < b\n"
+        );
+    }
+}
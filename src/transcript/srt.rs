@@ -0,0 +1,92 @@
+//! SubRip subtitles: numbered cues with a timing line and one or more lines of text.
+
+use super::{ParseError, Segment, check_timing, parse_cue_timing, strip_tags};
+
+pub fn parse(contents: &str) -> Result<Vec<Segment>, ParseError> {
+    let mut lines = contents
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .peekable();
+    let mut segments = Vec::new();
+    loop {
+        while lines.next_if(|(_, line)| line.is_empty()).is_some() {}
+        let Some((number, cue)) = lines.next() else {
+            break;
+        };
+        if cue.parse::<u64>().is_err() {
+            return Err(ParseError::new(
+                number,
+                format!("expected a cue number, found '{cue}'"),
+            ));
+        }
+
+        let Some((number, timing)) = lines.next() else {
+            return Err(ParseError::new(
+                number + 1,
+                "expected a timing line after the cue number",
+            ));
+        };
+        let (start, end) = parse_cue_timing(timing).ok_or_else(|| {
+            ParseError::new(
+                number,
+                format!("expected 'HH:MM:SS,mmm --> HH:MM:SS,mmm', found '{timing}'"),
+            )
+        })?;
+        check_timing(number, start, end)?;
+
+        let mut text = Vec::new();
+        while let Some((_, line)) = lines.next_if(|(_, line)| !line.is_empty()) {
+            text.push(line);
+        }
+        let text = strip_tags(&text.join(" ")).trim().to_owned();
+        if !text.is_empty() {
+            segments.push(Segment {
+                speaker: None,
+                start: Some(start),
+                end: Some(end),
+                text,
+            });
+        }
+    }
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let contents = "1\r\n00:00:01,000 --> 00:00:02,500\r\n<i>Hello</i>\r\nthere\r\n\r\n\r\n2\r\n00:00:03,000 --> 00:00:04,000 X1:0\r\nBye\r\n";
+        assert_eq!(
+            parse(contents).unwrap(),
+            vec![
+                Segment {
+                    speaker: None,
+                    start: Some(1.0),
+                    end: Some(2.5),
+                    text: "Hello there".into(),
+                },
+                Segment {
+                    speaker: None,
+                    start: Some(3.0),
+                    end: Some(4.0),
+                    text: "Bye".into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_errors_point_at_line() {
+        let error = parse("1\n00:00:01,000 --> 00:00:02,000\nok\n\n2\n00:00:03 -> 00:00:04\nbad\n")
+            .unwrap_err();
+        assert_eq!(error.line, 6);
+
+        let error = parse("1\n00:00:05,000 --> 00:00:04,000\nbackwards\n").unwrap_err();
+        assert_eq!(error, ParseError::new(2, "segment ends before it starts"));
+
+        assert_eq!(parse("one\n").unwrap_err().line, 1);
+    }
+}
@@ -0,0 +1,38 @@
+//! Plain text without timing; every non-empty line becomes a segment.
+
+use super::{ParseError, Segment};
+
+pub fn parse(contents: &str) -> Result<Vec<Segment>, ParseError> {
+    let mut segments = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        if line.chars().any(|c| c.is_control() && c != '\t') {
+            return Err(ParseError::new(
+                index + 1,
+                "line contains control characters; is this a text file?",
+            ));
+        }
+        let text = line.trim();
+        if !text.is_empty() {
+            segments.push(Segment {
+                speaker: None,
+                start: None,
+                end: None,
+                text: text.to_owned(),
+            });
+        }
+    }
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let segments = parse("First line\r\n\r\n  second\tline \n").unwrap();
+        let texts: Vec<&str> = segments.iter().map(|s| s.text.as_str()).collect();
+        assert_eq!(texts, ["First line", "second\tline"]);
+        assert_eq!(parse("ok\nbinary\0data\n").unwrap_err().line, 2);
+    }
+}
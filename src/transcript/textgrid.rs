@@ -0,0 +1,308 @@
+//! Praat TextGrid files in the long or short text format.
+//!
+//! Both formats are the same sequence of numbers, strings and `<exists>` flags; the long
+//! format only adds labels like `xmin =` or `intervals [1]:`, which are skipped. Each
+//! interval tier is one speaker.
+
+use super::{ParseError, Segment, check_timing};
+
+enum Token {
+    Number(f64),
+    Text(String),
+    Flag(String),
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Number(value) => format!("the number {value}"),
+            Token::Text(text) => format!("the string \"{text}\""),
+            Token::Flag(flag) => format!("<{flag}>"),
+        }
+    }
+}
+
+struct Tokens<'a> {
+    rest: &'a str,
+    line: usize,
+}
+
+impl Tokens<'_> {
+    /// Returns the next token and the line it starts on.
+    fn next(&mut self) -> Result<(usize, Token), ParseError> {
+        loop {
+            let Some(c) = self.rest.chars().next() else {
+                return Err(ParseError::new(self.line, "unexpected end of file"));
+            };
+            match c {
+                '\n' => {
+                    self.line += 1;
+                    self.rest = &self.rest[1..];
+                }
+                _ if c.is_whitespace() => self.rest = &self.rest[c.len_utf8()..],
+                '!' => self.skip_line(),
+                '"' => return self.string(),
+                '<' => {
+                    let line = self.line;
+                    let end = self
+                        .rest
+                        .find('>')
+                        .ok_or_else(|| ParseError::new(line, "unterminated flag, expected '>'"))?;
+                    let flag = self.rest[1..end].to_owned();
+                    self.rest = &self.rest[end + 1..];
+                    return Ok((line, Token::Flag(flag)));
+                }
+                '0'..='9' | '-' | '+' | '.' => {
+                    let end = self
+                        .rest
+                        .find(char::is_whitespace)
+                        .unwrap_or(self.rest.len());
+                    let word = &self.rest[..end];
+                    self.rest = &self.rest[end..];
+                    let value = word.parse().map_err(|_| {
+                        ParseError::new(self.line, format!("expected a number, found '{word}'"))
+                    })?;
+                    return Ok((self.line, Token::Number(value)));
+                }
+                // labels of the long format, like `xmin =` or `intervals [1]:`
+                _ => {
+                    let line_end = self.rest.find(['\n', '"']).unwrap_or(self.rest.len());
+                    let Some(position) = self.rest[..line_end].find(['=', ':', '?']) else {
+                        let word = self.rest.split_whitespace().next().unwrap_or_default();
+                        return Err(ParseError::new(
+                            self.line,
+                            format!("expected a number or a string, found '{word}'"),
+                        ));
+                    };
+                    self.rest = &self.rest[position + 1..];
+                }
+            }
+        }
+    }
+
+    fn skip_line(&mut self) {
+        let line_end = self.rest.find('\n').unwrap_or(self.rest.len());
+        self.rest = &self.rest[line_end..];
+    }
+
+    /// Reads a quoted string, in which `""` stands for a quote.
+    fn string(&mut self) -> Result<(usize, Token), ParseError> {
+        let line = self.line;
+        let mut text = String::new();
+        let mut chars = self.rest.char_indices().skip(1).peekable();
+        while let Some((position, c)) = chars.next() {
+            match c {
+                '"' if chars.peek().is_some_and(|&(_, next)| next == '"') => {
+                    chars.next();
+                    text.push('"');
+                }
+                '"' => {
+                    self.rest = &self.rest[position + 1..];
+                    return Ok((line, Token::Text(text)));
+                }
+                '\n' => {
+                    self.line += 1;
+                    text.push(c);
+                }
+                _ => text.push(c),
+            }
+        }
+        Err(ParseError::new(line, "unterminated string"))
+    }
+
+    fn number(&mut self) -> Result<(usize, f64), ParseError> {
+        match self.next()? {
+            (line, Token::Number(value)) => Ok((line, value)),
+            (line, token) => Err(ParseError::new(
+                line,
+                format!("expected a number, found {}", token.describe()),
+            )),
+        }
+    }
+
+    fn count(&mut self) -> Result<usize, ParseError> {
+        let (line, value) = self.number()?;
+        if value < 0.0 || value.fract() != 0.0 {
+            return Err(ParseError::new(
+                line,
+                format!("expected a count, found {value}"),
+            ));
+        }
+        Ok(value as usize)
+    }
+
+    fn text(&mut self) -> Result<(usize, String), ParseError> {
+        match self.next()? {
+            (line, Token::Text(text)) => Ok((line, text)),
+            (line, token) => Err(ParseError::new(
+                line,
+                format!("expected a string, found {}", token.describe()),
+            )),
+        }
+    }
+
+    fn expect_text(&mut self, expected: &str) -> Result<(), ParseError> {
+        let (line, text) = self.text()?;
+        if text != expected {
+            return Err(ParseError::new(
+                line,
+                format!("expected \"{expected}\", found \"{text}\""),
+            ));
+        }
+        Ok(())
+    }
+}
+
+pub fn parse(contents: &str) -> Result<Vec<Segment>, ParseError> {
+    let mut tokens = Tokens {
+        rest: contents,
+        line: 1,
+    };
+    tokens.expect_text("ooTextFile")?;
+    tokens.expect_text("TextGrid")?;
+    tokens.number()?;
+    tokens.number()?;
+    match tokens.next()? {
+        (_, Token::Flag(flag)) if flag == "exists" => {}
+        (_, Token::Flag(flag)) if flag == "absent" => return Ok(Vec::new()),
+        (line, token) => {
+            return Err(ParseError::new(
+                line,
+                format!("expected <exists>, found {}", token.describe()),
+            ));
+        }
+    }
+
+    let mut segments = Vec::new();
+    for _ in 0..tokens.count()? {
+        let (line, class) = tokens.text()?;
+        let (_, name) = tokens.text()?;
+        tokens.number()?;
+        tokens.number()?;
+        let count = tokens.count()?;
+        match class.as_str() {
+            "IntervalTier" => {
+                for _ in 0..count {
+                    let (line, start) = tokens.number()?;
+                    let (_, end) = tokens.number()?;
+                    let (_, text) = tokens.text()?;
+                    check_timing(line, start, end)?;
+                    let text = text.trim();
+                    if !text.is_empty() {
+                        segments.push(Segment {
+                            speaker: Some(name.trim().to_owned()).filter(|name| !name.is_empty()),
+                            start: Some(start),
+                            end: Some(end),
+                            text: text.to_owned(),
+                        });
+                    }
+                }
+            }
+            // points mark instants, which have no text span to transcribe
+            "TextTier" => {
+                for _ in 0..count {
+                    tokens.number()?;
+                    tokens.text()?;
+                }
+            }
+            _ => {
+                return Err(ParseError::new(
+                    line,
+                    format!("unknown tier class \"{class}\""),
+                ));
+            }
+        }
+    }
+    segments.sort_by(|a, b| {
+        a.start
+            .unwrap_or_default()
+            .total_cmp(&b.start.unwrap_or_default())
+    });
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LONG: &str = r#"File type = "ooTextFile"
+Object class = "TextGrid"
+
+xmin = 0
+xmax = 3.5
+tiers? <exists>
+size = 2
+item []:
+    item [1]:
+        class = "IntervalTier"
+        name = "Mary"
+        xmin = 0
+        xmax = 3.5
+        intervals: size = 3
+        intervals [1]:
+            xmin = 0
+            xmax = 1.25
+            text = "say ""hi"""
+        intervals [2]:
+            xmin = 1.25
+            xmax = 2
+            text = ""
+        intervals [3]:
+            xmin = 2
+            xmax = 3.5
+            text = "bye"
+    item [2]:
+        class = "TextTier"
+        name = "events"
+        xmin = 0
+        xmax = 3.5
+        points: size = 1
+        points [1]:
+            number = 1
+            mark = "cough"
+"#;
+
+    const SHORT: &str = "File type = \"ooTextFile\"\nObject class = \"TextGrid\"\n\n0\n2\n<exists>\n2\n\"IntervalTier\"\n\"B\"\n0\n2\n1\n1\n2\n\"second\"\n\"IntervalTier\"\n\"A\"\n0\n2\n1\n0\n1\n\"first\"\n";
+
+    #[test]
+    fn test_parse_long_format() {
+        let segments = parse(LONG).unwrap();
+        assert_eq!(
+            segments,
+            vec![
+                Segment {
+                    speaker: Some("Mary".into()),
+                    start: Some(0.0),
+                    end: Some(1.25),
+                    text: "say \"hi\"".into(),
+                },
+                Segment {
+                    speaker: Some("Mary".into()),
+                    start: Some(2.0),
+                    end: Some(3.5),
+                    text: "bye".into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_short_format() {
+        let segments = parse(SHORT).unwrap();
+        let speakers: Vec<_> = segments.iter().map(|s| s.speaker.as_deref()).collect();
+        assert_eq!(speakers, [Some("A"), Some("B")]);
+    }
+
+    #[test]
+    fn test_errors_point_at_line() {
+        let broken = LONG.replace("xmax = 2\n", "xmax = two\n");
+        let error = parse(&broken).unwrap_err();
+        assert_eq!(error.line, 21);
+
+        let truncated = &LONG[..LONG.find("intervals [3]").unwrap()];
+        assert_eq!(
+            parse(truncated).unwrap_err().message,
+            "unexpected end of file"
+        );
+    }
+}
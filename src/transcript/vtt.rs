@@ -0,0 +1,125 @@
+//! WebVTT subtitles; the speaker is taken from `<v>` voice tags.
+
+use super::{ParseError, Segment, check_timing, parse_cue_timing, strip_tags};
+
+pub fn parse(contents: &str) -> Result<Vec<Segment>, ParseError> {
+    let mut lines = contents
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .peekable();
+    match lines.next() {
+        Some((_, header))
+            if header == "WEBVTT"
+                || header.starts_with("WEBVTT ")
+                || header.starts_with("WEBVTT\t") => {}
+        _ => return Err(ParseError::new(1, "file must start with 'WEBVTT'")),
+    }
+    // the rest of the header block carries no cues
+    while lines.next_if(|(_, line)| !line.is_empty()).is_some() {}
+
+    let mut segments = Vec::new();
+    loop {
+        while lines.next_if(|(_, line)| line.is_empty()).is_some() {}
+        let Some((number, first)) = lines.next() else {
+            break;
+        };
+        if first.starts_with("NOTE") || first == "STYLE" || first == "REGION" {
+            while lines.next_if(|(_, line)| !line.is_empty()).is_some() {}
+            continue;
+        }
+
+        let (number, timing) = if first.contains("-->") {
+            (number, first)
+        } else {
+            // the first line was the cue identifier
+            lines.next_if(|(_, line)| !line.is_empty()).ok_or_else(|| {
+                ParseError::new(
+                    number + 1,
+                    "expected a timing line after the cue identifier",
+                )
+            })?
+        };
+        let (start, end) = parse_cue_timing(timing).ok_or_else(|| {
+            ParseError::new(
+                number,
+                format!("expected '[HH:]MM:SS.mmm --> [HH:]MM:SS.mmm', found '{timing}'"),
+            )
+        })?;
+        check_timing(number, start, end)?;
+
+        let mut payload = Vec::new();
+        while let Some((_, line)) = lines.next_if(|(_, line)| !line.is_empty()) {
+            payload.push(line);
+        }
+        let payload = payload.join(" ");
+        let (speaker, text) = split_voice(&payload);
+        let text = decode_entities(&strip_tags(text)).trim().to_owned();
+        if !text.is_empty() {
+            segments.push(Segment {
+                speaker,
+                start: Some(start),
+                end: Some(end),
+                text,
+            });
+        }
+    }
+    Ok(segments)
+}
+
+/// Splits a leading `<v Speaker>` or `<v.class Speaker>` tag off the cue payload.
+fn split_voice(payload: &str) -> (Option<String>, &str) {
+    if !(payload.starts_with("<v ") || payload.starts_with("<v.")) {
+        return (None, payload);
+    }
+    let Some(end) = payload.find('>') else {
+        return (None, payload);
+    };
+    let speaker = payload[2..end]
+        .split_once(char::is_whitespace)
+        .map(|(_, name)| name.trim().to_owned())
+        .filter(|name| !name.is_empty());
+    (speaker, &payload[end + 1..])
+}
+
+fn decode_entities(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&nbsp;", "\u{a0}")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let contents = "WEBVTT - interview\nKind: captions\n\nNOTE written by hand\nspans lines\n\nintro\n00:01.000 --> 00:02.000 align:start\n<v.loud Mary>Hello &amp; <b>welcome</b></v>\n\n00:00:03.000 --> 00:00:04.500\nPlain\n";
+        assert_eq!(
+            parse(contents).unwrap(),
+            vec![
+                Segment {
+                    speaker: Some("Mary".into()),
+                    start: Some(1.0),
+                    end: Some(2.0),
+                    text: "Hello & welcome".into(),
+                },
+                Segment {
+                    speaker: None,
+                    start: Some(3.0),
+                    end: Some(4.5),
+                    text: "Plain".into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_errors_point_at_line() {
+        assert_eq!(parse("1\n00:01.000 --> 00:02.000\n").unwrap_err().line, 1);
+        let error =
+            parse("WEBVTT\n\n00:01.000 --> 00:02.000\nok\n\ncue\n00:03 --> soon\n").unwrap_err();
+        assert_eq!(error.line, 7);
+    }
+}
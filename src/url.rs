@@ -9,6 +9,11 @@ impl UrlGenerator {
     pub fn url(&self, path: impl ToString) -> String {
         format!("http://{}{}", self.0.0, path.to_string())
     }
+
+    pub fn url_with_query(&self, path: impl ToString, query: &impl serde::Serialize) -> String {
+        let query = serde_urlencoded::to_string(query).expect("query should be url-encodable");
+        self.url(format!("{}?{}", path.to_string(), query))
+    }
 }
 
 impl<S> FromRequestParts<S> for UrlGenerator
@@ -21,7 +26,9 @@ where
         parts: &mut axum::http::request::Parts,
         state: &S,
     ) -> Result<Self, Self::Rejection> {
-        let host = Host::from_request_parts(parts, state).await?;
+        let host = Host::from_request_parts(parts, state)
+            .await
+            .map_err(|rejection| AppError::bad_request(rejection.body_text()))?;
         Ok(UrlGenerator(host))
     }
 }
@@ -0,0 +1,338 @@
+//! Webhook notifications about finished analysis runs.
+//!
+//! Deliveries are queued in the same transaction as the state change they report and sent
+//! by [`delivery_loop`]. Each request carries these headers:
+//!
+//! - `X-Webhook-Id`: id of the delivery, identical across retries
+//! - `X-Webhook-Event`: `analysis.completed` or `analysis.failed`
+//! - `X-Webhook-Timestamp`: unix time at which the attempt was made
+//! - `X-Webhook-Signature`: `sha256=` followed by the hex HMAC-SHA256 of
+//!   `"{timestamp}.{body}"`, keyed with the subscription secret
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use sqlx::PgConnection;
+
+use crate::AppState;
+
+const DELIVERY_BATCH_SIZE: i64 = 20;
+const DELIVERY_IDLE_INTERVAL: Duration = Duration::from_secs(5);
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long claimed deliveries are hidden from other workers; longer than a batch of
+/// timed out attempts takes, so a delivery is only picked up again if its worker died.
+const CLAIM_LEASE: Duration = Duration::from_secs(300);
+const MAX_ATTEMPTS: i32 = 12;
+const MAX_RETRY_DELAY_SECS: i32 = 3600;
+
+pub enum WebhookEvent {
+    AnalysisCompleted,
+    AnalysisFailed { error: String },
+}
+
+impl WebhookEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            WebhookEvent::AnalysisCompleted => "analysis.completed",
+            WebhookEvent::AnalysisFailed { .. } => "analysis.failed",
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+struct WebhookPayload<'a> {
+    event: &'static str,
+    recording_id: uuid::Uuid,
+    run_id: uuid::Uuid,
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+    occurred_at: DateTime<Utc>,
+}
+
+/// Random secret for subscriptions created without one.
+pub fn generate_secret() -> String {
+    [uuid::Uuid::new_v4(), uuid::Uuid::new_v4()]
+        .iter()
+        .map(|id| hex::encode(id.into_bytes()))
+        .collect()
+}
+
+/// Queues `event` for every subscription covering the recording.
+///
+/// Runs on the caller's connection so nothing is sent if the surrounding transaction
+/// rolls back; call [`wake_delivery`] afterwards to send without delay.
+pub async fn enqueue(
+    conn: &mut PgConnection,
+    recording_id: uuid::Uuid,
+    run_id: uuid::Uuid,
+    event: &WebhookEvent,
+) -> eyre::Result<()> {
+    let payload = WebhookPayload {
+        event: event.name(),
+        recording_id,
+        run_id,
+        status: match event {
+            WebhookEvent::AnalysisCompleted => "done",
+            WebhookEvent::AnalysisFailed { .. } => "error",
+        },
+        error: match event {
+            WebhookEvent::AnalysisCompleted => None,
+            WebhookEvent::AnalysisFailed { error } => Some(error),
+        },
+        occurred_at: Utc::now(),
+    };
+    sqlx::query!(
+        "INSERT INTO webhook_deliveries (id, subscription_id, event, payload)
+        SELECT gen_random_uuid(), id, $1, $2 FROM webhook_subscriptions
+        WHERE recording_id IS NULL OR recording_id=$3",
+        event.name(),
+        serde_json::to_value(&payload)?,
+        recording_id
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Tells the delivery worker that new deliveries were committed.
+pub fn wake_delivery(state: &AppState) {
+    state.webhook_wakeup.notify_one();
+}
+
+/// Sends pending deliveries, retrying failed ones with exponential backoff until
+/// [`MAX_ATTEMPTS`] is reached.
+pub async fn delivery_loop(state: AppState) {
+    let client = reqwest::Client::builder()
+        .timeout(DELIVERY_TIMEOUT)
+        .build()
+        .expect("http client should be constructible");
+    loop {
+        match delivery_batch(&state, &client).await {
+            Ok(attempted) if attempted > 0 => continue,
+            Ok(_) => {}
+            Err(why) => tracing::error!("failed to deliver webhooks: {why:?}"),
+        }
+        let _ = tokio::time::timeout(DELIVERY_IDLE_INTERVAL, state.webhook_wakeup.notified()).await;
+    }
+}
+
+/// Returns the number of deliveries that were attempted.
+///
+/// Deliveries are claimed by moving their next attempt past [`CLAIM_LEASE`], so no
+/// transaction is held open while receivers answer; each outcome is stored on its own.
+async fn delivery_batch(state: &AppState, client: &reqwest::Client) -> eyre::Result<usize> {
+    let deliveries = sqlx::query!(
+        "WITH claimed AS (
+            UPDATE webhook_deliveries SET next_attempt_at = now() + make_interval(secs => $2)
+            WHERE id IN (
+                SELECT id FROM webhook_deliveries
+                WHERE status = 'pending' AND next_attempt_at <= now()
+                ORDER BY created_at
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, subscription_id, event, payload, attempts, created_at
+        )
+        SELECT claimed.id, claimed.event, claimed.payload, claimed.attempts,
+            webhook_subscriptions.url, webhook_subscriptions.secret
+        FROM claimed
+        JOIN webhook_subscriptions ON webhook_subscriptions.id = claimed.subscription_id
+        ORDER BY claimed.created_at",
+        DELIVERY_BATCH_SIZE,
+        CLAIM_LEASE.as_secs_f64()
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    let attempted = deliveries.len();
+    for delivery in deliveries {
+        let attempt = deliver(
+            client,
+            &delivery.url,
+            &delivery.secret,
+            delivery.id,
+            &delivery.event,
+            &delivery.payload.to_string(),
+        )
+        .await;
+
+        match attempt.error {
+            None => {
+                sqlx::query!(
+                    "UPDATE webhook_deliveries SET status='delivered', delivered_at=now(), attempts=attempts+1, last_status_code=$1, last_error=NULL WHERE id=$2",
+                    attempt.status_code,
+                    delivery.id
+                )
+                .execute(&state.db)
+                .await?;
+            }
+            Some(error) => {
+                tracing::warn!(
+                    "failed to deliver webhook {} to {}: {error}",
+                    delivery.id,
+                    delivery.url
+                );
+                let status = if delivery.attempts + 1 >= MAX_ATTEMPTS {
+                    "failed"
+                } else {
+                    "pending"
+                };
+                let delay_secs = 2_i32
+                    .saturating_pow(delivery.attempts.clamp(0, 16) as u32)
+                    .min(MAX_RETRY_DELAY_SECS);
+                sqlx::query!(
+                    "UPDATE webhook_deliveries SET status=$1, attempts=attempts+1, last_status_code=$2, last_error=$3, next_attempt_at=now() + make_interval(secs => $4) WHERE id=$5",
+                    status,
+                    attempt.status_code,
+                    error,
+                    delay_secs as f64,
+                    delivery.id
+                )
+                .execute(&state.db)
+                .await?;
+            }
+        }
+    }
+
+    Ok(attempted)
+}
+
+fn signature(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("hmac should accept keys of any length");
+    mac.update(format!("{timestamp}.{body}").as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+struct Attempt {
+    status_code: Option<i32>,
+    /// Set unless the receiver answered with a 2xx status.
+    error: Option<String>,
+}
+
+async fn deliver(
+    client: &reqwest::Client,
+    url: &str,
+    secret: &str,
+    delivery_id: uuid::Uuid,
+    event: &str,
+    body: &str,
+) -> Attempt {
+    let timestamp = Utc::now().timestamp();
+    let response = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header("X-Webhook-Id", delivery_id.to_string())
+        .header("X-Webhook-Event", event)
+        .header("X-Webhook-Timestamp", timestamp.to_string())
+        .header("X-Webhook-Signature", signature(secret, timestamp, body))
+        .body(body.to_owned())
+        .send()
+        .await;
+
+    match response {
+        Ok(response) if response.status().is_success() => Attempt {
+            status_code: Some(response.status().as_u16().into()),
+            error: None,
+        },
+        Ok(response) => Attempt {
+            status_code: Some(response.status().as_u16().into()),
+            error: Some(format!("receiver responded with {}", response.status())),
+        },
+        Err(why) => Attempt {
+            status_code: None,
+            error: Some(why.to_string()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::{
+        extract::State,
+        http::{HeaderMap, StatusCode},
+        routing::post,
+    };
+
+    use super::*;
+
+    type Received = Arc<Mutex<Vec<(HeaderMap, String)>>>;
+
+    /// Starts a receiver that records requests and answers with `status`.
+    async fn receiver(status: StatusCode) -> (String, Received) {
+        let received = Received::default();
+        let app =
+            axum::Router::new()
+                .route(
+                    "/hook",
+                    post(
+                        move |State(received): State<Received>,
+                              headers: HeaderMap,
+                              body: String| async move {
+                            received.lock().unwrap().push((headers, body));
+                            status
+                        },
+                    ),
+                )
+                .with_state(received.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, received)
+    }
+
+    #[tokio::test]
+    async fn test_delivery_is_signed() {
+        let (url, received) = receiver(StatusCode::NO_CONTENT).await;
+        let id = uuid::Uuid::new_v4();
+        let body = r#"{"event":"analysis.completed"}"#;
+
+        let attempt = deliver(
+            &reqwest::Client::new(),
+            &url,
+            "secret",
+            id,
+            "analysis.completed",
+            body,
+        )
+        .await;
+        assert_eq!(attempt.status_code, Some(204));
+        assert!(attempt.error.is_none());
+
+        let received = received.lock().unwrap();
+        let (headers, received_body) = &received[0];
+        assert_eq!(received_body, body);
+        assert_eq!(headers["x-webhook-id"], id.to_string());
+        assert_eq!(headers["x-webhook-event"], "analysis.completed");
+        let timestamp: i64 = headers["x-webhook-timestamp"]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(
+            headers["x-webhook-signature"],
+            signature("secret", timestamp, body)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_error_status_fails_delivery() {
+        let (url, _) = receiver(StatusCode::INTERNAL_SERVER_ERROR).await;
+        let attempt = deliver(
+            &reqwest::Client::new(),
+            &url,
+            "secret",
+            uuid::Uuid::new_v4(),
+            "analysis.failed",
+            "{}",
+        )
+        .await;
+        assert_eq!(attempt.status_code, Some(500));
+        assert!(attempt.error.is_some());
+    }
+}